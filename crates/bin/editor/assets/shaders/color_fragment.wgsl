@@ -21,12 +21,17 @@ struct FragmentInput {
   @location(0) world_position: vec4<f32>,
   @location(1) world_normal: vec3<f32>,
   @location(2) color: vec3<f32>,
+  @location(3) ao: f32,
+  @location(4) light: vec2<f32>,
 };
 
 @fragment
 fn fragment(input: FragmentInput) -> @location(0) vec4<f32> {
   var pbr_input: PbrInput = pbr_input_new();
-  pbr_input.material.base_color = vec4<f32>(input.color, 1.0);
+  // Brightest of skylight and block light, dark places keep a little color
+  let light = max(input.light.x, input.light.y);
+  let brightness = input.ao * (0.1 + 0.9 * light);
+  pbr_input.material.base_color = vec4<f32>(input.color * brightness, 1.0);
   pbr_input.frag_coord = input.frag_coord;
   pbr_input.world_position = input.world_position;
 
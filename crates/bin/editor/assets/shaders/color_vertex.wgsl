@@ -9,6 +9,8 @@ struct Vertex {
   @location(0) position: vec3<f32>,
   @location(1) normal: vec3<f32>,
   @location(2) color: vec3<f32>,
+  @location(3) ao: f32,
+  @location(4) light: vec2<f32>,
 };
 
 struct VertexOutput {
@@ -16,6 +18,8 @@ struct VertexOutput {
   @location(0) world_position: vec4<f32>,
   @location(1) world_normal: vec3<f32>,
   @location(2) color: vec3<f32>,
+  @location(3) ao: f32,
+  @location(4) light: vec2<f32>,
 };
 
 @vertex
@@ -26,5 +30,7 @@ fn vertex(vertex: Vertex) -> VertexOutput {
   out.world_normal = vertex.normal;
 
   out.color = vertex.color;
+  out.ao = vertex.ao;
+  out.light = vertex.light;
   return out;
 }
@@ -1,22 +1,29 @@
 #import bevy_pbr::mesh_view_bindings
-#import bevy_pbr::mesh_bindings
-#import bevy_pbr::mesh_functions
+#import bevy_pbr::mesh_bindings mesh
+#import bevy_pbr::mesh_functions mesh_position_local_to_world
+#import bevy_pbr::mesh_functions mesh_position_local_to_clip
 
 
 
 struct Vertex {
   @location(0) position: vec3<f32>,
   @location(1) normal: vec3<f32>,
-  @location(2) voxel_weight: vec4<f32>,
-  @location(3) voxel_type_1: vec4<u32>,
+  @location(2) color: vec3<f32>,
+  @location(3) ao: f32,
+  @location(4) light: vec2<f32>,
+  @location(5) voxel_weight: vec4<f32>,
+  @location(6) voxel_types: vec4<u32>,
 };
 
 struct VertexOutput {
   @builtin(position) clip_position: vec4<f32>,
   @location(0) world_position: vec4<f32>,
   @location(1) world_normal: vec3<f32>,
-  @location(2) voxel_weight: vec4<f32>,
-  @location(3) voxel_type_1: vec4<u32>,
+  @location(2) color: vec3<f32>,
+  @location(3) ao: f32,
+  @location(4) light: vec2<f32>,
+  @location(5) voxel_weight: vec4<f32>,
+  @location(6) @interpolate(flat) voxel_types: vec4<u32>,
 };
 
 @vertex
@@ -26,7 +33,10 @@ fn vertex(vertex: Vertex) -> VertexOutput {
   out.clip_position = mesh_position_local_to_clip(mesh.model, vec4<f32>(vertex.position, 1.0));
   out.world_normal = vertex.normal;
 
+  out.color = vertex.color;
+  out.ao = vertex.ao;
+  out.light = vertex.light;
   out.voxel_weight = vertex.voxel_weight;
-  out.voxel_type_1 = vertex.voxel_type_1;
+  out.voxel_types = vertex.voxel_types;
   return out;
 }
@@ -1,6 +1,6 @@
 use bevy::{prelude::*, utils::HashMap};
 use serde::{Deserialize, Serialize};
-use voxels::chunk::chunk_manager::{ChunkManager, Chunk};
+use voxels::{chunk::chunk_manager::{ChunkManager, Chunk}, data::materials::MaterialRegistry};
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
@@ -91,13 +91,17 @@ pub enum CursorState {
 pub struct Data {
   pub status: Status,
   pub terrains: Terrains,
+  /// Older saves use the default materials
+  #[serde(default)]
+  pub materials: Option<MaterialRegistry>,
 }
 
 impl Default for Data {
   fn default() -> Self {
     Self {
       status: Status { position: [0.0, 5.0, 0.0] },
-      terrains: Terrains { keys: Vec::new(), voxels: Vec::new() }
+      terrains: Terrains { keys: Vec::new(), voxels: Vec::new() },
+      materials: None,
     }
   }
 }
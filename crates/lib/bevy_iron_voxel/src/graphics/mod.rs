@@ -1,9 +1,7 @@
 use std::f32::consts::PI;
 
 use bevy::{prelude::*, pbr::CascadeShadowConfigBuilder};
-use bevy_voxel::{MeshComponent, BevyVoxelResource, Center};
 use rapier3d::prelude::ColliderHandle;
-use utils::Utils;
 
 pub mod chunk_preview;
 mod player;
@@ -16,8 +14,7 @@ impl Plugin for CustomPlugin {
       .add_plugins(player::CustomPlugin)
       .add_plugins(chunk_preview::CustomPlugin)
       .add_systems(Startup, startup)
-      .add_systems(Update, toggle_showhide)
-      .add_systems(Update, remove);
+      .add_systems(Update, toggle_showhide);
   }
 }
 
@@ -80,39 +77,6 @@ fn toggle_showhide(
   }
 }
 
-fn remove(
-  mut commands: Commands,
-
-  chunk_graphics: Query<(Entity, &ChunkGraphics)>,
-  mesh_comps: Query<(&Center, &MeshComponent)>,
-  bevy_voxel_res: Res<BevyVoxelResource>,
-) {
-  let max_lod = bevy_voxel_res.chunk_manager.depth as usize;
-  for (center, _mesh_comp) in &mesh_comps {
-    for (entity, graphics) in &chunk_graphics {
-      for lod in 0..max_lod {
-        if bevy_voxel_res.in_range_by_lod(&center.key, &graphics.key, lod) {
-          if graphics.lod != lod {
-            for (_, g2) in &chunk_graphics {
-              if bevy_voxel_res.in_range_by_lod(&center.key, &g2.key, lod) {
-                if g2.lod == lod && graphics.key == g2.key {
-                  commands.entity(entity).despawn_recursive();
-                }
-              }
-            }
-          }
-        }
-      }
-
-      let r = bevy_voxel_res.ranges.clone();
-      let max_range = r[r.len() - 1] as i64;
-      if !Utils::in_range(&center.key, &graphics.key, max_range) {
-        commands.entity(entity).despawn_recursive();
-      }
-    }
-  }
-}
-
 #[derive(Resource)]
 pub struct GraphicsResource {
   pub show_preview: bool,
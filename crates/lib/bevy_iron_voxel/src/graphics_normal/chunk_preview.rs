@@ -4,7 +4,7 @@ use bevy::render::render_resource::PrimitiveTopology;
 use bevy_voxel::{BevyVoxelResource, Preview, PreviewGraphics, EditState};
 use voxels::data::voxel_octree::VoxelMode;
 
-use super::chunks::{CustomMaterial, VOXEL_COLOR};
+use super::chunks::{CustomMaterial, VOXEL_COLOR, VOXEL_AO, VOXEL_LIGHT, get_ao, get_light};
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
@@ -58,6 +58,8 @@ fn update(
     render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals.clone());
     render_mesh.set_indices(Some(Indices::U32(data.indices.clone())));
     render_mesh.insert_attribute(VOXEL_COLOR, data.colors.clone());
+    render_mesh.insert_attribute(VOXEL_AO, get_ao(&data));
+    render_mesh.insert_attribute(VOXEL_LIGHT, get_light(&data));
 
     let mesh_handle = meshes.add(render_mesh);
     let material_handle = custom_materials.add(CustomMaterial {
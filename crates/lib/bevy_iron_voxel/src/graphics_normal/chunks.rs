@@ -1,13 +1,17 @@
 use bevy::{prelude::*, render::{mesh::{MeshVertexAttribute, MeshVertexBufferLayout, Indices}, render_resource::{VertexFormat, AsBindGroup, ShaderRef, SpecializedMeshPipelineError, RenderPipelineDescriptor, PrimitiveTopology}}, reflect::TypeUuid, pbr::{MaterialPipeline, MaterialPipelineKey}};
-use bevy_voxel::{BevyVoxelResource, MeshComponent};
+use bevy_voxel::{BevyVoxelResource, MeshComponent, debris::Debris};
+use voxels::data::voxel_octree::MeshData;
 use crate::graphics::ChunkGraphics;
+use super::voxel_materials::{self, ChunkTexture};
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
   fn build(&self, app: &mut App) {
     app
       .add_plugins(MaterialPlugin::<CustomMaterial>::default())
-      .add_systems(Update, add);
+      .add_plugins(MaterialPlugin::<TransparentMaterial>::default())
+      .add_plugins(voxel_materials::CustomPlugin)
+      .add_systems(Update, (add, add_debris, apply_textures));
 
 
 /*     // Test code
@@ -22,20 +26,34 @@ fn add(
   mut commands: Commands,
   mut meshes: ResMut<Assets<Mesh>>,
   mut custom_materials: ResMut<Assets<CustomMaterial>>,
+  mut transparent_materials: ResMut<Assets<TransparentMaterial>>,
   mut _images: ResMut<Assets<Image>>,
 
   chunk_graphics: Query<(Entity, &ChunkGraphics)>,
 
   mut chunk_query: Query<(Entity, &mut MeshComponent), Changed<MeshComponent>>,
   mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  chunk_texture: Res<ChunkTexture>,
 ) {
   for (_, mut mesh_comp) in &mut chunk_query {
+    for key in mesh_comp.removed.iter() {
+      for (entity, graphics) in &chunk_graphics {
+        if graphics.key == *key {
+          commands.entity(entity).despawn_recursive();
+          if graphics.lod == 0 {
+            bevy_voxel_res.physics.remove_collider(graphics.collider);
+          }
+        }
+      }
+    }
+    mesh_comp.removed.clear();
+
     for (data, collider_handle) in mesh_comp.added.iter() {
 
       // This is for removing the duplicates
       'graphics: for (entity, graphics) in &chunk_graphics {
         if graphics.key == data.key {
-          commands.entity(entity).despawn();
+          commands.entity(entity).despawn_recursive();
           if graphics.lod == 0 {
             bevy_voxel_res.physics.remove_collider(graphics.collider);
           }
@@ -43,37 +61,141 @@ fn add(
         }
       }
 
-      let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
-      render_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.positions.clone());
-      render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals.clone());
-      render_mesh.set_indices(Some(Indices::U32(data.indices.clone())));
-      render_mesh.insert_attribute(VOXEL_COLOR, data.colors.clone());
-
-      let mesh_handle = meshes.add(render_mesh);
-      let material_handle = custom_materials.add(CustomMaterial {
-        base_color: Color::rgb(1.0, 1.0, 1.0),
-      });
-
-      let pos = bevy_voxel_res.get_pos(data.key);
-      commands
-        .spawn(MaterialMeshBundle {
-          mesh: mesh_handle,
-          material: material_handle,
-          transform: Transform::from_translation(pos),
+      let transform = Transform::from_translation(bevy_voxel_res.get_pos(data.key));
+      let mut entity_commands = match &chunk_texture.material {
+        // Only transparent voxels, the chunk entity just holds them
+        _ if data.indices.is_empty() => commands.spawn(SpatialBundle::from_transform(transform)),
+        Some(material) => commands.spawn(MaterialMeshBundle {
+          mesh: meshes.add(render_mesh(data)),
+          material: material.clone(),
+          transform: transform,
+          ..default()
+        }),
+        None => commands.spawn(MaterialMeshBundle {
+          mesh: meshes.add(render_mesh(data)),
+          material: custom_materials.add(CustomMaterial {
+            base_color: Color::rgb(1.0, 1.0, 1.0),
+          }),
+          transform: transform,
           ..default()
-        })
+        }),
+      };
+      entity_commands
         .insert(ChunkGraphics { 
           key: data.key, 
           lod: data.lod as usize,
           collider: *collider_handle,
         });
 
+      // Drawn after the opaque meshes, seen from both sides
+      let materials = &bevy_voxel_res.chunk_manager.materials;
+      entity_commands.with_children(|parent| {
+        for mesh in data.transparent.iter() {
+          let voxel = mesh.types.first().map(|t| t[0] as u8).unwrap_or(0);
+          let opacity = materials.get(voxel).map(|m| m.opacity).unwrap_or(1.0);
+          parent.spawn(MaterialMeshBundle {
+            mesh: meshes.add(render_mesh(mesh)),
+            material: transparent_materials.add(TransparentMaterial {
+              base_color: Color::rgba(1.0, 1.0, 1.0, opacity),
+            }),
+            ..default()
+          });
+        }
+      });
+
       // println!("data.lod {}", data.lod);
     }
     mesh_comp.added.clear();
   }
 }
 
+fn add_debris(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut custom_materials: ResMut<Assets<CustomMaterial>>,
+
+  debris: Query<(Entity, &Debris), Added<Debris>>,
+) {
+  for (entity, d) in &debris {
+    // Flat colors, the triplanar textures are projected in world space and
+    // would slide over the moving debris
+    let mesh_handle = meshes.add(render_mesh(&d.mesh));
+    let material_handle = custom_materials.add(CustomMaterial {
+      base_color: Color::rgb(1.0, 1.0, 1.0),
+    });
+
+    commands
+      .entity(entity)
+      .insert((mesh_handle, material_handle));
+  }
+}
+
+/// Chunks spawned with flat colors switch to the textures once they are loaded
+fn apply_textures(
+  mut commands: Commands,
+  chunk_texture: Res<ChunkTexture>,
+  flat_chunks: Query<Entity, (With<ChunkGraphics>, With<Handle<CustomMaterial>>)>,
+) {
+  if !chunk_texture.is_changed() {
+    return;
+  }
+  let material = match &chunk_texture.material {
+    Some(material) => material,
+    None => return,
+  };
+  for entity in &flat_chunks {
+    commands
+      .entity(entity)
+      .remove::<Handle<CustomMaterial>>()
+      .insert(material.clone());
+  }
+}
+
+/// Render mesh with the attributes of both the flat and the textured material
+pub fn render_mesh(data: &MeshData) -> Mesh {
+  let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+  render_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.positions.clone());
+  render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals.clone());
+  render_mesh.set_indices(Some(Indices::U32(data.indices.clone())));
+  render_mesh.insert_attribute(VOXEL_COLOR, data.colors.clone());
+  render_mesh.insert_attribute(VOXEL_AO, get_ao(data));
+  render_mesh.insert_attribute(VOXEL_LIGHT, get_light(data));
+  render_mesh.insert_attribute(VOXEL_WEIGHT, get_weights(data));
+  render_mesh.insert_attribute(VOXEL_TYPES, get_types(data));
+  render_mesh
+}
+
+/// Meshes without ambient occlusion are unoccluded
+pub fn get_ao(data: &MeshData) -> Vec<f32> {
+  if data.ao.len() == data.positions.len() {
+    return data.ao.clone();
+  }
+  vec![1.0; data.positions.len()]
+}
+
+/// Meshes without light are fully lit by the sky
+pub fn get_light(data: &MeshData) -> Vec<[f32; 2]> {
+  if data.light.len() == data.positions.len() {
+    return data.light.clone();
+  }
+  vec![[1.0, 0.0]; data.positions.len()]
+}
+
+/// Meshes without material weights only show their flat color
+pub fn get_weights(data: &MeshData) -> Vec<[f32; 4]> {
+  if data.weights.len() == data.positions.len() {
+    return data.weights.clone();
+  }
+  vec![[0.0; 4]; data.positions.len()]
+}
+
+pub fn get_types(data: &MeshData) -> Vec<[u32; 4]> {
+  if data.types.len() == data.positions.len() {
+    return data.types.clone();
+  }
+  vec![[0; 4]; data.positions.len()]
+}
+
 /* fn delete_main_octrees_outside_range(
   mut commands: Commands,
   mut bevy_voxel_res: ResMut<BevyVoxelResource>,
@@ -102,6 +224,18 @@ fn add(
 pub const VOXEL_COLOR: MeshVertexAttribute =
   MeshVertexAttribute::new("VOXEL_COLOR", 988540918, VertexFormat::Float32x3);
 
+pub const VOXEL_AO: MeshVertexAttribute =
+  MeshVertexAttribute::new("VOXEL_AO", 988540919, VertexFormat::Float32);
+
+pub const VOXEL_LIGHT: MeshVertexAttribute =
+  MeshVertexAttribute::new("VOXEL_LIGHT", 988540920, VertexFormat::Float32x2);
+
+pub const VOXEL_WEIGHT: MeshVertexAttribute =
+  MeshVertexAttribute::new("VOXEL_WEIGHT", 988540921, VertexFormat::Float32x4);
+
+pub const VOXEL_TYPES: MeshVertexAttribute =
+  MeshVertexAttribute::new("VOXEL_TYPES", 988540922, VertexFormat::Uint32x4);
+
 #[derive(AsBindGroup, Reflect, Debug, Clone, TypeUuid)]
 #[uuid = "2f3d7f74-4bf7-4f32-98cd-858edafa5ca2"]
 pub struct CustomMaterial {
@@ -125,6 +259,8 @@ impl Material for CustomMaterial {
       Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
       Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
       VOXEL_COLOR.at_shader_location(2),
+      VOXEL_AO.at_shader_location(3),
+      VOXEL_LIGHT.at_shader_location(4),
     ])?;
     descriptor.vertex.buffers = vec![vertex_layout];
 
@@ -132,11 +268,40 @@ impl Material for CustomMaterial {
   }
 }
 
+/// Transparent and liquid voxels, the alpha of base_color is the opacity
+#[derive(AsBindGroup, Reflect, Debug, Clone, TypeUuid)]
+#[uuid = "5b0e9c3a-8d47-4f16-b2a1-3c6f0e7d9a58"]
+pub struct TransparentMaterial {
+  #[uniform(0)]
+  pub base_color: Color,
+}
 
+impl Material for TransparentMaterial {
+  fn vertex_shader() -> ShaderRef {
+    "shaders/color_vertex.wgsl".into()
+  }
+  fn fragment_shader() -> ShaderRef {
+    "shaders/transparent_fragment.wgsl".into()
+  }
+  fn alpha_mode(&self) -> AlphaMode {
+    AlphaMode::Blend
+  }
+  fn specialize(
+    _pipeline: &MaterialPipeline<Self>,
+    descriptor: &mut RenderPipelineDescriptor,
+    layout: &MeshVertexBufferLayout,
+    _key: MaterialPipelineKey<Self>,
+  ) -> Result<(), SpecializedMeshPipelineError> {
+    let vertex_layout = layout.get_layout(&[
+      Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+      Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+      VOXEL_COLOR.at_shader_location(2),
+      VOXEL_AO.at_shader_location(3),
+      VOXEL_LIGHT.at_shader_location(4),
+    ])?;
+    descriptor.vertex.buffers = vec![vertex_layout];
+    descriptor.primitive.cull_mode = None;
 
-
-
-
-
-
-
+    Ok(())
+  }
+}
@@ -2,6 +2,7 @@ use bevy::prelude::*;
 
 pub mod chunks;
 pub mod chunk_preview;
+pub mod voxel_materials;
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
@@ -0,0 +1,211 @@
+use bevy::{prelude::*, asset::{AssetLoader, LoadContext, LoadedAsset, LoadState}, utils::BoxedFuture, render::{mesh::MeshVertexBufferLayout, render_resource::{AsBindGroup, ShaderRef, ShaderType, SpecializedMeshPipelineError, RenderPipelineDescriptor}}, reflect::{TypeUuid, TypePath}, pbr::{MaterialPipeline, MaterialPipelineKey}};
+use serde::{Deserialize, Serialize};
+use bevy_voxel::BevyVoxelResource;
+use voxels::data::materials::MaterialRegistry;
+use super::chunks::{VOXEL_COLOR, VOXEL_AO, VOXEL_LIGHT, VOXEL_WEIGHT, VOXEL_TYPES};
+
+/// Palette entries without a texture layer keep their flat color
+pub const NO_LAYER: u32 = u32::MAX;
+
+pub const MATERIALS_PATH: &str = "materials/voxels.materials.ron";
+
+pub struct CustomPlugin;
+impl Plugin for CustomPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_asset::<VoxelMaterials>()
+      .init_asset_loader::<VoxelMaterialsLoader>()
+      .add_plugins(MaterialPlugin::<TriplanarMaterial>::default())
+      .add_systems(Startup, startup)
+      .add_systems(Update, init_textures);
+  }
+}
+
+/**
+  Material definition file, maps palette entries to the layers of an array
+  texture on top of the textures of the voxel materials. The layers are
+  stacked vertically in the albedo image
+*/
+#[derive(Serialize, Deserialize, TypeUuid, TypePath, Debug, Clone)]
+#[uuid = "7c1f3a52-3d2e-4b8a-9a43-1f0a3b6e2d71"]
+pub struct VoxelMaterials {
+  pub albedo: String,
+  pub layers: u32,
+  /// World units per texture repeat
+  #[serde(default = "default_uv_scale")]
+  pub uv_scale: f32,
+  /// Higher values narrow the blending between the projection axes
+  #[serde(default = "default_sharpness")]
+  pub sharpness: f32,
+  #[serde(default)]
+  pub materials: Vec<VoxelMaterialEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoxelMaterialEntry {
+  /// Voxel value, palette index + 1
+  pub voxel: u8,
+  pub layer: u32,
+}
+
+fn default_uv_scale() -> f32 { 1.0 }
+fn default_sharpness() -> f32 { 8.0 }
+
+impl VoxelMaterials {
+  /// Texture layer of each voxel value, NO_LAYER for air and unmapped values.
+  /// The entries of the file replace the textures of the registry
+  pub fn layer_table(&self, registry: &MaterialRegistry) -> [u32; 256] {
+    let mut table = [NO_LAYER; 256];
+    for voxel in 1..=255 {
+      let layer = registry.get(voxel).and_then(|m| m.texture);
+      if let Some(layer) = layer.filter(|l| *l < self.layers) {
+        table[voxel as usize] = layer;
+      }
+    }
+    for entry in self.materials.iter() {
+      if entry.voxel > 0 && entry.layer < self.layers {
+        table[entry.voxel as usize] = entry.layer;
+      }
+    }
+    table
+  }
+}
+
+#[derive(Default)]
+pub struct VoxelMaterialsLoader;
+
+impl AssetLoader for VoxelMaterialsLoader {
+  fn load<'a>(
+    &'a self,
+    bytes: &'a [u8],
+    load_context: &'a mut LoadContext,
+  ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+    Box::pin(async move {
+      let materials: VoxelMaterials = ron::de::from_bytes(bytes)?;
+      load_context.set_default_asset(LoadedAsset::new(materials));
+      Ok(())
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["materials.ron"]
+  }
+}
+
+/// Triplanar material once the definition and its texture are loaded, the
+/// chunks render with flat colors until then or when loading fails
+#[derive(Resource)]
+pub struct ChunkTexture {
+  pub definition: Handle<VoxelMaterials>,
+  pub albedo: Option<Handle<Image>>,
+  pub material: Option<Handle<TriplanarMaterial>>,
+  pub failed: bool,
+}
+
+fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
+  commands.insert_resource(ChunkTexture {
+    definition: asset_server.load(MATERIALS_PATH),
+    albedo: None,
+    material: None,
+    failed: false,
+  });
+}
+
+fn init_textures(
+  asset_server: Res<AssetServer>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
+  definitions: Res<Assets<VoxelMaterials>>,
+  mut images: ResMut<Assets<Image>>,
+  mut materials: ResMut<Assets<TriplanarMaterial>>,
+  mut chunk_texture: ResMut<ChunkTexture>,
+) {
+  if chunk_texture.material.is_some() || chunk_texture.failed {
+    return;
+  }
+  let definition = match definitions.get(&chunk_texture.definition) {
+    Some(d) => d,
+    None => return,
+  };
+
+  let albedo = match &chunk_texture.albedo {
+    Some(albedo) => albedo.clone(),
+    None => {
+      chunk_texture.albedo = Some(asset_server.load(definition.albedo.as_str()));
+      return;
+    }
+  };
+  match asset_server.get_load_state(albedo.clone()) {
+    LoadState::Loaded => {}
+    LoadState::Failed => {
+      warn!("Failed to load {}, using flat colors", definition.albedo);
+      chunk_texture.failed = true;
+      return;
+    }
+    _ => return,
+  }
+
+  let image = images.get_mut(&albedo).unwrap();
+  image.reinterpret_stacked_2d_as_array(definition.layers);
+
+  let table = definition.layer_table(&bevy_voxel_res.chunk_manager.materials);
+  let mut layers = [UVec4::ZERO; 64];
+  for (i, layer) in layers.iter_mut().enumerate() {
+    *layer = UVec4::new(table[i * 4], table[i * 4 + 1], table[i * 4 + 2], table[i * 4 + 3]);
+  }
+
+  chunk_texture.material = Some(materials.add(TriplanarMaterial {
+    settings: TriplanarSettings {
+      uv_scale: definition.uv_scale,
+      sharpness: definition.sharpness,
+      layers: layers,
+    },
+    albedo: albedo,
+  }));
+}
+
+
+#[derive(ShaderType, Debug, Clone)]
+pub struct TriplanarSettings {
+  pub uv_scale: f32,
+  pub sharpness: f32,
+  /// Texture layer by voxel value, 4 values per entry
+  pub layers: [UVec4; 64],
+}
+
+#[derive(AsBindGroup, TypeUuid, TypePath, Debug, Clone)]
+#[uuid = "a3b7e0f4-5c61-4d0e-8f2a-6e9d4c1b7a30"]
+pub struct TriplanarMaterial {
+  #[uniform(0)]
+  pub settings: TriplanarSettings,
+  #[texture(1, dimension = "2d_array")]
+  #[sampler(2)]
+  pub albedo: Handle<Image>,
+}
+
+impl Material for TriplanarMaterial {
+  fn vertex_shader() -> ShaderRef {
+    "shaders/triplanar_vertex.wgsl".into()
+  }
+  fn fragment_shader() -> ShaderRef {
+    "shaders/triplanar_fragment.wgsl".into()
+  }
+  fn specialize(
+    _pipeline: &MaterialPipeline<Self>,
+    descriptor: &mut RenderPipelineDescriptor,
+    layout: &MeshVertexBufferLayout,
+    _key: MaterialPipelineKey<Self>,
+  ) -> Result<(), SpecializedMeshPipelineError> {
+    let vertex_layout = layout.get_layout(&[
+      Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+      Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+      VOXEL_COLOR.at_shader_location(2),
+      VOXEL_AO.at_shader_location(3),
+      VOXEL_LIGHT.at_shader_location(4),
+      VOXEL_WEIGHT.at_shader_location(5),
+      VOXEL_TYPES.at_shader_location(6),
+    ])?;
+    descriptor.vertex.buffers = vec![vertex_layout];
+
+    Ok(())
+  }
+}
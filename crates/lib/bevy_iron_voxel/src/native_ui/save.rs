@@ -13,6 +13,7 @@ use std::io::Write;
 use std::path::PathBuf;
 use crate::data::GameState;
 use futures_lite::future;
+use bevy_voxel::BevyVoxelResource;
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
@@ -27,6 +28,7 @@ impl Plugin for CustomPlugin {
 
 fn enter(
   game_res: Res<GameResource>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
   players: Query<&Transform, With<Player>>,
 ) {
   let mut terrains = Terrains { keys: Vec::new(), voxels: Vec::new() };
@@ -44,7 +46,8 @@ fn enter(
     status: Status {
       position: pos.into(),
     },
-    terrains: terrains
+    terrains: terrains,
+    materials: Some(bevy_voxel_res.chunk_manager.materials.clone()),
   };
 
   let str = toml::to_string_pretty(&data).unwrap();
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 use bevy_flycam::FlyCam;
-use voxels::{data::voxel_octree::VoxelOctree, chunk::chunk_manager::Chunk};
+use bevy_voxel::BevyVoxelResource;
+use voxels::{data::voxel_octree::VoxelOctree, chunk::{chunk_manager::Chunk, chunk_mode}};
 use crate::{data::{GameResource, GameState, UIState}, physics::Physics, graphics::ChunkGraphics, components::player::Player};
 
 
@@ -15,6 +16,7 @@ impl Plugin for CustomPlugin {
 fn enter(
   mut commands: Commands,
   mut game_res: ResMut<GameResource>,
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
   mut physics: ResMut<Physics>,
   player_query: Query<(Entity, &Player)>,
   mut game_state_next: ResMut<NextState<GameState>>,
@@ -42,6 +44,9 @@ fn enter(
 
 
   let data = game_res.data.clone();
+  if let Some(materials) = data.materials.clone() {
+    bevy_voxel_res.set_materials(materials);
+  }
   for i in 0..data.terrains.keys.len() {
     let key = &data.terrains.keys[i];
     let voxels_str = &data.terrains.voxels[i];
@@ -49,8 +54,10 @@ fn enter(
     if voxels_res.is_ok() {
       let data = voxels_res.unwrap();
       let octree = VoxelOctree::new_from_bytes(data);
+      let mode = chunk_mode(&octree, &bevy_voxel_res.chunk_manager.materials);
       let chunk = Chunk {
         key: key.clone(),
+        mode: mode,
         octree: octree,
         is_default: false,
         ..Default::default()
@@ -277,7 +277,8 @@ mod tests {
       status: Status {
         position: pos.into(),
       },
-      terrains: terrains
+      terrains: terrains,
+      materials: None,
     };
 
     let str = toml::to_string_pretty(&data).unwrap();
@@ -109,6 +109,8 @@ fn startup(
   render_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.positions.clone());
   render_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals.clone());
   render_mesh.insert_attribute(VOXEL_COLOR, data.colors.clone());
+  render_mesh.insert_attribute(VOXEL_AO, vec![1.0_f32; data.positions.len()]);
+  render_mesh.insert_attribute(VOXEL_LIGHT, vec![[1.0_f32, 0.0]; data.positions.len()]);
   render_mesh.set_indices(Some(Indices::U32(data.indices.clone())));
 
   let mesh_handle = meshes.add(render_mesh);
@@ -195,6 +197,12 @@ fn show_diagnostic_texts(
 pub const VOXEL_COLOR: MeshVertexAttribute =
   MeshVertexAttribute::new("VOXEL_COLOR", 988540918, VertexFormat::Float32x3);
 
+pub const VOXEL_AO: MeshVertexAttribute =
+  MeshVertexAttribute::new("VOXEL_AO", 988540919, VertexFormat::Float32);
+
+pub const VOXEL_LIGHT: MeshVertexAttribute =
+  MeshVertexAttribute::new("VOXEL_LIGHT", 988540920, VertexFormat::Float32x2);
+
 #[derive(AsBindGroup, Reflect, FromReflect, Debug, Clone, TypeUuid)]
 #[uuid = "2f3d7f74-4bf7-4f32-98cd-858edafa5ca2"]
 pub struct CustomMaterial {
@@ -218,6 +226,8 @@ impl Material for CustomMaterial {
       Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
       Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
       VOXEL_COLOR.at_shader_location(2),
+      VOXEL_AO.at_shader_location(3),
+      VOXEL_LIGHT.at_shader_location(4),
     ])?;
     descriptor.vertex.buffers = vec![vertex_layout];
 
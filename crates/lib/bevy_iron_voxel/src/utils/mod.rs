@@ -5,7 +5,7 @@ use bevy::math::{Vec3, Quat};
 use bevy::prelude::Transform;
 use voxels::chunk::chunk_manager::ChunkManager;
 use voxels::chunk::adjacent_keys_i64;
-use voxels::chunk::voxel_pos_to_key;
+use voxels::chunk::coords::{ChunkLayout, WorldVoxelPos};
 
 pub struct Math;
 
@@ -46,9 +46,8 @@ impl Math {
   }
 }
 
-pub fn to_key(translation: &Vec3, seamless_size: u32) -> [i64; 3] {
-  let pos = [translation.x as i64, translation.y as i64, translation.z as i64];
-  voxel_pos_to_key(&pos, seamless_size)
+pub fn to_key(translation: &Vec3, layout: &ChunkLayout) -> [i64; 3] {
+  WorldVoxelPos::from_world(translation.to_array(), layout).key(layout).0
 }
 
 #[derive(Clone)]
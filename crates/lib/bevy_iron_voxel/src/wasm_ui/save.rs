@@ -3,6 +3,7 @@ use voxels::chunk::chunk_manager::Chunk;
 use crate::components::chunk::Chunks;
 use crate::data::{Terrains, Data, Status, GameState, GameResource};
 use super::html_body;
+use bevy_voxel::BevyVoxelResource;
 use wasm_bindgen::JsCast;
 use web_sys::HtmlElement;
 
@@ -18,7 +19,10 @@ impl Plugin for CustomPlugin {
   }
 }
 
-fn enter(local_res: Res<LocalResource>,) {
+fn enter(
+  local_res: Res<LocalResource>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
+) {
   let body = html_body();
   let res = body.query_selector("#download");
   
@@ -41,7 +45,8 @@ fn enter(local_res: Res<LocalResource>,) {
       status: Status {
         position: [0.0, 1.0, 0.0],
       },
-      terrains: terrains
+      terrains: terrains,
+      materials: Some(bevy_voxel_res.chunk_manager.materials.clone()),
     };
     let str = toml::to_string_pretty(&data).unwrap();
 
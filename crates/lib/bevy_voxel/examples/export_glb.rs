@@ -0,0 +1,16 @@
+use bevy_voxel::BevyVoxelResource;
+
+/// Exports the chunks around the origin without a window:
+/// cargo run --example export_glb -- world.glb [merge]
+fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  let path = args.get(1).cloned().unwrap_or("world.glb".to_string());
+  let merge = args.iter().any(|a| a == "merge");
+
+  let mut res = BevyVoxelResource::default();
+  res.load_adj_chunks([0, 0, 0]);
+
+  let bytes = res.export_glb(None, merge);
+  std::fs::write(&path, bytes).expect("write failed");
+  println!("Exported {} chunks to {}", res.chunk_manager.len(), path);
+}
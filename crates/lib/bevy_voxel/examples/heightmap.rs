@@ -0,0 +1,40 @@
+use voxels::chunk::chunk_manager::ChunkManager;
+use voxels::formats::{heightmap::{Heightmap, HeightmapOptions}, vox::VoxFile};
+
+/// Renders the terrain around the origin to a top down PNG, or turns a
+/// grayscale PNG into a .vox terrain:
+/// cargo run --example heightmap -- export map.png [size]
+/// cargo run --example heightmap -- import map.png terrain.vox [scale]
+fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  match args.get(1).map(|a| a.as_str()) {
+    Some("export") if args.len() > 2 => {
+      let size = args.get(3).and_then(|v| v.parse().ok()).unwrap_or(128);
+      let half = size / 2;
+      let chunk_manager = ChunkManager::default();
+      let map = chunk_manager
+        .export_heightmap([-half, -64, -half], [half - 1, 63, half - 1])
+        .expect("export failed");
+      std::fs::write(&args[2], map.write_png()).expect("write failed");
+      println!("Exported {}x{} heightmap to {}", map.width, map.height, args[2]);
+    }
+    Some("import") if args.len() > 3 => {
+      let bytes = std::fs::read(&args[2]).expect("read failed");
+      let map = Heightmap::read_png(&bytes).expect("invalid png");
+      let options = HeightmapOptions {
+        scale: args.get(4).and_then(|v| v.parse().ok()).unwrap_or(32.0),
+        depth: 0,
+        clear_above: false,
+        ..Default::default()
+      };
+
+      options.check().expect("invalid options");
+      let chunk_manager = ChunkManager::default();
+      let voxels = chunk_manager.place_heightmap(&map, &options, [0, 0], [map.width, map.height]);
+      let vox = VoxFile::from_voxels(&voxels, &chunk_manager.colors).expect("terrain too large");
+      std::fs::write(&args[3], vox.write()).expect("write failed");
+      println!("Imported {} voxels to {}", voxels.len(), args[3]);
+    }
+    _ => println!("Usage: heightmap export <map.png> [size] | import <map.png> <terrain.vox> [scale]"),
+  }
+}
@@ -0,0 +1,35 @@
+use voxels::chunk::chunk_manager::ChunkManager;
+use voxels::formats::{obj::read_obj, gltf::read_gltf, vox::VoxFile, voxelize::VoxelizeOptions};
+
+/// Voxelizes an OBJ, glTF or glb mesh into a MagicaVoxel file:
+/// cargo run --example voxelize -- model.obj model.vox [voxel_size] [hollow]
+fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  if args.len() < 3 {
+    println!("Usage: voxelize <mesh.obj|mesh.gltf|mesh.glb> <out.vox> [voxel_size] [hollow]");
+    return;
+  }
+  let input = &args[1];
+  let output = &args[2];
+  let voxel_size = args.get(3).and_then(|v| v.parse().ok()).unwrap_or(1.0);
+  let fill = !args.iter().any(|a| a == "hollow");
+
+  let mesh = if input.to_lowercase().ends_with(".obj") {
+    let obj = std::fs::read_to_string(input).expect("read failed");
+    let mtl = std::path::Path::new(input).with_extension("mtl");
+    read_obj(&obj, std::fs::read_to_string(mtl).ok().as_deref())
+  } else {
+    read_gltf(&std::fs::read(input).expect("read failed"))
+  }.expect("invalid mesh");
+
+  let chunk_manager = ChunkManager::default();
+  let options = VoxelizeOptions {
+    voxel_size: voxel_size,
+    fill: fill,
+    ..Default::default()
+  };
+  let voxels = chunk_manager.place_mesh(&mesh, &options).expect("voxelize failed");
+  let vox = VoxFile::from_voxels(&voxels, &chunk_manager.colors).expect("export failed");
+  std::fs::write(output, vox.write()).expect("write failed");
+  println!("Voxelized {} triangles into {} voxels in {}", mesh.indices.len() / 3, voxels.len(), output);
+}
@@ -0,0 +1,333 @@
+use bevy::{prelude::*, utils::HashMap};
+use rapier3d::prelude::{RigidBodyHandle, ColliderHandle, Collider};
+use voxels::{chunk::chunk_manager::Chunk, data::{voxel_octree::{VoxelOctree, VoxelMode, MeshData}, surface_nets::VoxelReuse}};
+use crate::{BevyVoxelResource, Chunks, editstate::DetachedIslandEvent, remesh::RemeshQueue, physics::debris_collider};
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+  if #[cfg(not(target_arch = "wasm32"))] {
+    use bevy::tasks::{AsyncComputeTaskPool, Task};
+    use futures_lite::future;
+  }
+}
+
+pub struct CustomPlugin;
+impl Plugin for CustomPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(DebrisSettings::default())
+      .add_systems(Update, (spawn_debris, insert_debris, update_debris).chain());
+  }
+}
+
+#[derive(Resource, Clone)]
+pub struct DebrisSettings {
+  pub enabled: bool,
+  /// Bigger islands are left in place
+  pub max_voxels: usize,
+  /// Bigger islands collide as their convex hull instead of a convex
+  /// decomposition
+  pub max_decomposition_voxels: usize,
+  /// Write the voxels back into the world once the body is at rest
+  pub merge_on_rest: bool,
+  /// Seconds the body has to stay still before merging
+  pub rest_secs: f32,
+  /// Seconds before a body that never rests is removed
+  pub lifetime_secs: f32,
+}
+
+impl Default for DebrisSettings {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      max_voxels: 4096,
+      max_decomposition_voxels: 512,
+      merge_on_rest: true,
+      rest_secs: 1.0,
+      lifetime_secs: 30.0,
+    }
+  }
+}
+
+/// Detached voxels simulated as a rigid body, the handles are invalid until
+/// the collider is built. Octree coord [0, 0, 0] is at the entity's Transform
+#[derive(Component, Clone)]
+pub struct Debris {
+  pub octree: VoxelOctree,
+  pub mesh: MeshData,
+  pub body: RigidBodyHandle,
+  pub collider: ColliderHandle,
+  pub rest_time: f32,
+  pub life_time: f32,
+}
+
+fn spawn_debris(
+  mut commands: Commands,
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  settings: Res<DebrisSettings>,
+  mut remesh: ResMut<RemeshQueue>,
+  mut chunks: Query<&mut Chunks>,
+
+  mut island_reader: EventReader<DetachedIslandEvent>,
+) {
+  for island in island_reader.iter() {
+    if !settings.enabled || island.voxels.len() > settings.max_voxels {
+      continue;
+    }
+
+    let (octree, origin) = island_octree(&island.voxels);
+    let mesh = octree.compute_mesh(
+      VoxelMode::SurfaceNets,
+      &mut VoxelReuse::new(octree.get_depth() as u32, 3),
+      &bevy_voxel_res.chunk_manager.colors,
+      bevy_voxel_res.chunk_manager.voxel_scale,
+      [0; 3],
+      0
+    );
+    if mesh.indices.is_empty() {
+      continue;
+    }
+
+    let mut res = HashMap::new();
+    for (pos, _) in island.voxels.iter() {
+      for (key, chunk) in bevy_voxel_res.set_voxel_default(*pos, 0) {
+        res.insert(key, chunk);
+      }
+    }
+    update_chunks(&mut bevy_voxel_res, &mut remesh, &mut chunks, res);
+
+    let pos = bevy_voxel_res.get_voxel_world_pos(origin);
+    let surface = bevy_voxel_res.chunk_manager.materials.surface_properties(&mesh);
+    let decompose = island.voxels.len() <= settings.max_decomposition_voxels;
+    let collider = build_collider(&mesh, surface, decompose);
+
+    commands.spawn((
+      Debris {
+        octree: octree,
+        mesh: mesh,
+        body: RigidBodyHandle::invalid(),
+        collider: ColliderHandle::invalid(),
+        rest_time: 0.0,
+        life_time: 0.0,
+      },
+      collider,
+      SpatialBundle::from_transform(Transform::from_translation(pos)),
+    ));
+  }
+}
+
+/// Adds the body once the collider is built
+fn insert_debris(
+  mut commands: Commands,
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  mut debris: Query<(Entity, &mut Debris, &Transform, &mut DebrisCollider)>,
+) {
+  for (entity, mut d, trans, mut collider) in &mut debris {
+    if let Some(collider) = poll_collider(&mut collider) {
+      let t = trans.translation;
+      let (body, handle) = bevy_voxel_res.physics.add_debris([t.x, t.y, t.z], collider);
+      d.body = body;
+      d.collider = handle;
+      commands.entity(entity).remove::<DebrisCollider>();
+    }
+  }
+}
+
+fn update_debris(
+  mut commands: Commands,
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  settings: Res<DebrisSettings>,
+  time: Res<Time>,
+  mut remesh: ResMut<RemeshQueue>,
+  mut chunks: Query<&mut Chunks>,
+
+  mut debris: Query<(Entity, &mut Debris, &mut Transform), Without<DebrisCollider>>,
+) {
+  for (entity, mut d, mut trans) in &mut debris {
+    let body = match bevy_voxel_res.physics.rigid_body_set.get(d.body) {
+      Some(b) => b,
+      None => {
+        commands.entity(entity).despawn_recursive();
+        continue;
+      }
+    };
+
+    let iso = body.position();
+    let t = iso.translation.vector;
+    let r = iso.rotation;
+    trans.translation = Vec3::new(t.x, t.y, t.z);
+    trans.rotation = Quat::from_xyzw(r.i, r.j, r.k, r.w);
+
+    let resting = body.is_sleeping()
+      || (body.linvel().norm() < 0.05 && body.angvel().norm() < 0.05);
+    d.rest_time = if resting { d.rest_time + time.delta_seconds() } else { 0.0 };
+    d.life_time += time.delta_seconds();
+
+    let merge = settings.merge_on_rest && d.rest_time >= settings.rest_secs;
+    if !merge && d.life_time < settings.lifetime_secs {
+      continue;
+    }
+
+    if merge {
+      let mut res = HashMap::new();
+      for (pos, voxel) in debris_world_voxels(&d.octree, &trans, &bevy_voxel_res) {
+        if bevy_voxel_res.chunk_manager.get_voxel(&pos) != 0 {
+          continue;
+        }
+        for (key, chunk) in bevy_voxel_res.set_voxel_default(pos, voxel) {
+          res.insert(key, chunk);
+        }
+      }
+      update_chunks(&mut bevy_voxel_res, &mut remesh, &mut chunks, res);
+    }
+
+    bevy_voxel_res.physics.remove_rigid_body(d.body);
+    commands.entity(entity).despawn_recursive();
+  }
+}
+
+cfg_if! {
+  if #[cfg(target_arch = "wasm32")] {
+    /// Built right away, big islands use the cheaper convex hull
+    #[derive(Component)]
+    struct DebrisCollider(Option<Collider>);
+
+    fn build_collider(mesh: &MeshData, surface: [f32; 2], decompose: bool) -> DebrisCollider {
+      DebrisCollider(Some(debris_collider(&mesh.positions, &mesh.indices, surface, decompose)))
+    }
+
+    fn poll_collider(collider: &mut DebrisCollider) -> Option<Collider> {
+      collider.0.take()
+    }
+  } else {
+    /// Collider being built off the main thread
+    #[derive(Component)]
+    struct DebrisCollider(Task<Collider>);
+
+    fn build_collider(mesh: &MeshData, surface: [f32; 2], decompose: bool) -> DebrisCollider {
+      let positions = mesh.positions.clone();
+      let indices = mesh.indices.clone();
+      DebrisCollider(AsyncComputeTaskPool::get().spawn(async move {
+        debris_collider(&positions, &indices, surface, decompose)
+      }))
+    }
+
+    fn poll_collider(collider: &mut DebrisCollider) -> Option<Collider> {
+      future::block_on(future::poll_once(&mut collider.0))
+    }
+  }
+}
+
+/// Octree holding the voxels with one voxel of air around them,
+/// returned with the world voxel coord of octree coord [0, 0, 0]
+pub fn island_octree(voxels: &Vec<([i64; 3], u8)>) -> (VoxelOctree, [i64; 3]) {
+  let mut min = [i64::MAX; 3];
+  let mut max = [i64::MIN; 3];
+  for (pos, _) in voxels.iter() {
+    for i in 0..3 {
+      min[i] = min[i].min(pos[i]);
+      max[i] = max[i].max(pos[i]);
+    }
+  }
+  let origin = [min[0] - 1, min[1] - 1, min[2] - 1];
+
+  let mut extent = 1;
+  for i in 0..3 {
+    extent = extent.max(max[i] - min[i] + 3);
+  }
+  let mut depth = 1;
+  while 2_i64.pow(depth) < extent {
+    depth += 1;
+  }
+
+  let mut octree = VoxelOctree::new(0, depth as u8);
+  for (pos, voxel) in voxels.iter() {
+    octree.set_voxel(
+      (pos[0] - origin[0]) as u32,
+      (pos[1] - origin[1]) as u32,
+      (pos[2] - origin[2]) as u32,
+      *voxel
+    );
+  }
+  (octree, origin)
+}
+
+/// World voxel coords the debris voxels are nearest to
+fn debris_world_voxels(
+  octree: &VoxelOctree,
+  trans: &Transform,
+  bevy_voxel_res: &BevyVoxelResource,
+) -> Vec<([i64; 3], u8)> {
+  let scale = bevy_voxel_res.chunk_manager.voxel_scale;
+  let size = octree.get_size();
+  let mut voxels = Vec::new();
+  for x in 0..size {
+    for y in 0..size {
+      for z in 0..size {
+        let voxel = octree.get_voxel(x, y, z);
+        if voxel == 0 {
+          continue;
+        }
+
+        let local = Vec3::new(x as f32, y as f32, z as f32) * scale;
+        let p = trans.transform_point(local) / scale;
+        voxels.push((
+          [p.x.round() as i64, p.y.round() as i64, p.z.round() as i64],
+          voxel
+        ));
+      }
+    }
+  }
+  voxels
+}
+
+/// Remeshed off the main thread like the other edits
+fn update_chunks(
+  bevy_voxel_res: &mut BevyVoxelResource,
+  remesh: &mut RemeshQueue,
+  chunks: &mut Query<&mut Chunks>,
+  mut res: HashMap<[i64; 3], Chunk>,
+) {
+  if res.is_empty() {
+    return;
+  }
+  bevy_voxel_res.relight_edits(&mut res);
+
+  for mut chunks in chunks.iter_mut() {
+    for (key, chunk) in res.iter() {
+      chunks.data.insert(*key, chunk.clone());
+    }
+  }
+  remesh.mark_dirty(&res);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::island_octree;
+
+  #[test]
+  fn test_island_octree() -> Result<(), String> {
+    let voxels = vec![
+      ([-3, 10, 5], 1),
+      ([-3, 11, 5], 2),
+      ([2, 11, 5], 3),
+    ];
+    let (octree, origin) = island_octree(&voxels);
+    assert_eq!(origin, [-4, 9, 4]);
+    assert_eq!(octree.get_size(), 8);
+
+    for (pos, voxel) in voxels.iter() {
+      let v = octree.get_voxel(
+        (pos[0] - origin[0]) as u32,
+        (pos[1] - origin[1]) as u32,
+        (pos[2] - origin[2]) as u32,
+      );
+      assert_eq!(v, *voxel);
+    }
+    assert_eq!(octree.get_voxel(0, 0, 0), 0);
+    assert_eq!(octree.get_voxel(7, 2, 1), 0);
+    Ok(())
+  }
+}
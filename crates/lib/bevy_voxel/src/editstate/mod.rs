@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 
-use crate::{BevyVoxelResource, Preview, Chunks, MeshComponent};
+use crate::{BevyVoxelResource, Preview, Chunks, remesh::RemeshQueue};
 
 mod add_normal;
 mod add_dist;
@@ -18,6 +18,7 @@ impl Plugin for CustomPlugin {
   fn build(&self, app: &mut App) {
     app
       .add_event::<EditEvents>()
+      .add_event::<DetachedIslandEvent>()
       .add_plugins(add_normal::CustomPlugin)
       .add_plugins(add_dist::CustomPlugin)
       .add_plugins(add_snap::CustomPlugin)
@@ -35,55 +36,52 @@ impl Plugin for CustomPlugin {
 
 fn modify_voxels(
   mut bevy_voxel_res: ResMut<BevyVoxelResource>,
-  mut chunks: Query<(&Preview, &mut Chunks, &mut MeshComponent)>,
+  mut chunks: Query<(&Preview, &mut Chunks)>,
+  mut remesh: ResMut<RemeshQueue>,
 
   mut edit_event_reader: EventReader<EditEvents>,
+  mut island_writer: EventWriter<DetachedIslandEvent>,
 ) {
   for e in edit_event_reader.iter() {
     if e.event == EditEvent::RemoveCube {
-      for (preview, mut chunks, mut mesh_comp) in &mut chunks {
+      for (preview, mut chunks) in &mut chunks {
         if preview.pos.is_none() {
           continue;
         }
 
         let p = preview.pos.unwrap();
-        let res = bevy_voxel_res.set_voxel_cube_default(p, preview.size, 0);
+        let mut res = bevy_voxel_res.set_voxel_cube_default(p, preview.size, 0);
+        bevy_voxel_res.relight_edits(&mut res);
+        let extent = preview.size as i64 / 2 + 1;
+        for island in bevy_voxel_res.get_detached_islands(p, extent) {
+          island_writer.send(DetachedIslandEvent { voxels: island.voxels });
+        }
 
-        let mut all_chunks = Vec::new();
         for (key, chunk) in res.iter() {
-          all_chunks.push(chunk.clone());
           chunks.data.insert(*key, chunk.clone());
         }
-
-        let data = bevy_voxel_res.load_mesh_data(&all_chunks);
-        for (mesh_data, handle) in data.iter() {
-          
-          mesh_comp.data.insert(mesh_data.key.clone(), mesh_data.clone());
-          mesh_comp.added.push((mesh_data.clone(), *handle));
-        }
+        remesh.mark_dirty(&res);
       }
     }
 
     if e.event == EditEvent::RemoveSphere {
-      for (preview, mut chunks, mut mesh_comp) in &mut chunks {
+      for (preview, mut chunks) in &mut chunks {
         if preview.pos.is_none() {
           continue;
         }
 
         let p = preview.pos.unwrap();
-        let res = bevy_voxel_res.set_voxel_sphere_default(p, preview.sphere_size, 0);
+        let mut res = bevy_voxel_res.set_voxel_sphere_default(p, preview.sphere_size, 0);
+        bevy_voxel_res.relight_edits(&mut res);
+        let extent = preview.sphere_size.ceil() as i64 + 1;
+        for island in bevy_voxel_res.get_detached_islands(p, extent) {
+          island_writer.send(DetachedIslandEvent { voxels: island.voxels });
+        }
 
-        let mut all_chunks = Vec::new();
         for (key, chunk) in res.iter() {
-          all_chunks.push(chunk.clone());
           chunks.data.insert(*key, chunk.clone());
         }
-
-        let data = bevy_voxel_res.load_mesh_data(&all_chunks);
-        for (mesh_data, handle) in data.iter() {
-          mesh_comp.data.insert(mesh_data.key.clone(), mesh_data.clone());
-          mesh_comp.added.push((mesh_data.clone(), *handle));
-        }
+        remesh.mark_dirty(&res);
       }
     }
   }
@@ -96,6 +94,13 @@ pub struct EditEvents {
   pub event: EditEvent
 }
 
+/// Solid voxels and their values left floating by a remove edit or collapsing
+/// under their stress, the game decides whether to drop, delete or convert them
+#[derive(Event, Debug, Clone)]
+pub struct DetachedIslandEvent {
+  pub voxels: Vec<([i64; 3], u8)>,
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum EditEvent {
   AddCube,
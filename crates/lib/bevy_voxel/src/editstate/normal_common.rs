@@ -1,5 +1,5 @@
 use bevy::prelude::*;
-use crate::{EditState, Preview, BevyVoxelResource, Chunks, MeshComponent};
+use crate::{EditState, Preview, BevyVoxelResource, Chunks, remesh::RemeshQueue};
 use super::{EditEvents, EditEvent};
 
 pub struct CustomPlugin;
@@ -21,88 +21,54 @@ fn preview_position(
   bevy_voxel_res: Res<BevyVoxelResource>,
 ) {
   for (cam_trans, mut preview) in &mut cam {
-    let hit = bevy_voxel_res.get_raycast_hit(cam_trans);
-
-    if hit.is_none() {
-      if preview.pos.is_some() {
-        preview.pos = None;
-      }
-      continue;
-    }
-    let point = hit.unwrap();
-    let pos = bevy_voxel_res.get_nearest_voxel_air(point);
-    if pos.is_none() && preview.pos.is_some() {
+    let hit = bevy_voxel_res.get_voxel_raycast_hit(cam_trans);
+    let pos = hit.map(|hit| bevy_voxel_res.get_voxel_world_pos(hit.air));
+    if preview.pos != pos {
       preview.pos = pos;
     }
-
-    if pos.is_some() {
-      if preview.pos.is_some() {
-        let p = pos.unwrap();
-        let current = preview.pos.unwrap();
-        if current != p {
-          preview.pos = pos;
-        }
-      }
-      
-      if preview.pos.is_none() {
-        preview.pos = pos;
-      }
-    }
   }
 }
 
 
 fn modify_voxels(
   mut bevy_voxel_res: ResMut<BevyVoxelResource>,
-  mut chunks: Query<(&Preview, &mut Chunks, &mut MeshComponent)>,
+  mut chunks: Query<(&Preview, &mut Chunks)>,
+  mut remesh: ResMut<RemeshQueue>,
 
   mut edit_event_reader: EventReader<EditEvents>,
 ) {
   for e in edit_event_reader.iter() {
     if e.event == EditEvent::AddCube {
-      for (preview, mut chunks, mut mesh_comp) in &mut chunks {
+      for (preview, mut chunks) in &mut chunks {
         if preview.pos.is_none() {
           continue;
         }
 
         let p = preview.pos.unwrap();
-        let res = bevy_voxel_res.set_voxel_cube(p, preview);
+        let mut res = bevy_voxel_res.set_voxel_cube(p, preview);
+        bevy_voxel_res.relight_edits(&mut res);
 
-        let mut all_chunks = Vec::new();
         for (key, chunk) in res.iter() {
-          all_chunks.push(chunk.clone());
           chunks.data.insert(*key, chunk.clone());
         }
-
-        let data = bevy_voxel_res.load_mesh_data(&all_chunks);
-        for (mesh_data, handle) in data.iter() {
-          
-          mesh_comp.data.insert(mesh_data.key.clone(), mesh_data.clone());
-          mesh_comp.added.push((mesh_data.clone(), *handle));
-        }
+        remesh.mark_dirty(&res);
       }
     }
 
     if e.event == EditEvent::AddSphere {
-      for (preview, mut chunks, mut mesh_comp) in &mut chunks {
+      for (preview, mut chunks) in &mut chunks {
         if preview.pos.is_none() {
           continue;
         }
 
         let p = preview.pos.unwrap();
-        let res = bevy_voxel_res.set_voxel_sphere(p, preview);
+        let mut res = bevy_voxel_res.set_voxel_sphere(p, preview);
+        bevy_voxel_res.relight_edits(&mut res);
 
-        let mut all_chunks = Vec::new();
         for (key, chunk) in res.iter() {
-          all_chunks.push(chunk.clone());
           chunks.data.insert(*key, chunk.clone());
         }
-
-        let data = bevy_voxel_res.load_mesh_data(&all_chunks);
-        for (mesh_data, handle) in data.iter() {
-          mesh_comp.data.insert(mesh_data.key.clone(), mesh_data.clone());
-          mesh_comp.added.push((mesh_data.clone(), *handle));
-        }
+        remesh.mark_dirty(&res);
       }
     }
   }
@@ -1,98 +1,153 @@
 use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task}};
-use voxels::{chunk::chunk_manager::{ChunkManager, Chunk}, data::{voxel_octree::{VoxelMode, MeshData}, surface_nets::VoxelReuse}};
+use voxels::{chunk::chunk_manager::{ChunkManager, Chunk}, data::{voxel_octree::{VoxelMode, MeshData}, surface_nets::VoxelReuse, lod_transition::add_transition_skirts}};
 use futures_lite::future;
-use crate::BevyVoxelResource;
+use crate::{BevyVoxelResource, physics::{ChunkColliders, chunk_colliders}};
+use crate::lod::{LodManager, queue::LoadQueue};
 
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
   fn build(&self, app: &mut App) {
     app
-      .add_systems(Update, recv_keys)
-      .add_systems(Update, recv_chunk)
-      .add_systems(Update, recv_process_mesh)
-      .add_systems(Update, recv_mesh);
+      .add_systems(Update, (
+        queue_jobs,
+        spawn_jobs,
+        recv_chunk,
+        recv_mesh,
+      ).chain());
   }
 }
 
 
-fn recv_keys(
+/**
+  Collapses the requested keys and chunks into the queue, dropping the ones
+  the LodManager stopped waiting for
+*/
+fn queue_jobs(
+  bevy_voxel_res: Res<BevyVoxelResource>,
+  lod_manager: Res<LodManager>,
+  mut queue: ResMut<LoadQueue>,
+) {
+  for (key, lod) in bevy_voxel_res.recv_key.drain() {
+    queue.push_key(key, lod);
+  }
+  for chunk in bevy_voxel_res.recv_process_mesh.drain() {
+    queue.push_chunk(chunk);
+  }
+  queue.retain(|key, lod| lod_manager.pending.get(key) == Some(&lod));
+}
+
+fn spawn_jobs(
   mut commands: Commands,
   bevy_voxel_res: Res<BevyVoxelResource>,
+  mut queue: ResMut<LoadQueue>,
+  chunk_tasks: Query<&LoadChunk>,
+  mesh_tasks: Query<&LoadMeshData>,
 ) {
   let thread_pool = AsyncComputeTaskPool::get();
 
-  let depth = bevy_voxel_res.chunk_manager.depth as u8;
+  let depth = bevy_voxel_res.chunk_manager.depth;
+  let size = bevy_voxel_res.chunk_manager.chunk_size;
+  let scale = bevy_voxel_res.chunk_manager.voxel_scale;
   let noise = bevy_voxel_res.chunk_manager.noise;
+  let center = bevy_voxel_res.lod_center;
+
+  queue.in_flight = chunk_tasks.iter().len() + mesh_tasks.iter().len();
+  let free = queue.max_in_flight.saturating_sub(queue.in_flight);
+
+  // Meshing first, the chunks are already waiting in memory
+  let chunks = queue.pop_chunks(&center, free);
+  let free = free - chunks.len();
+  for chunk in chunks.into_iter() {
+    let materials = bevy_voxel_res.chunk_manager.materials.clone();
+    let key = chunk.key;
+    let lod = chunk.lod;
+    // The skirts are built in, so the mesh never shows up without them
+    let neighbor_lods = bevy_voxel_res.neighbor_lods(&center, &key, lod);
+    // The neighbors are sampled at full resolution
+    let apron = (lod == 0).then(|| bevy_voxel_res.chunk_manager.apron(&key));
+    let pos = bevy_voxel_res.get_pos(key);
+    let task = thread_pool.spawn(async move {
+      let mut data = chunk.octree.compute_mesh_passes(
+        VoxelMode::SurfaceNets,
+        &mut VoxelReuse::new(depth, 3),
+        &materials,
+        scale,
+        chunk.key,
+        chunk.lod
+      );
+      let mut colliders = None;
+      if let Some(apron) = apron {
+        apron.update_border_ao(&chunk.octree, &mut data);
+        apron.update_border_normals(&chunk.octree, &mut data);
+        if !data.is_empty() {
+          colliders = Some(chunk_colliders(pos, &data, &materials));
+        }
+      }
+      // Skirts are only for rendering
+      add_transition_skirts(&mut data, size, scale, &neighbor_lods);
+      (data, colliders)
+    });
+    commands.spawn(LoadMeshData { key: key, lod: lod, task: task });
+    queue.in_flight += 1;
+  }
 
-  for (key, lod) in bevy_voxel_res.recv_key.drain() {
-    let key = key.clone();
+  for (key, lod) in queue.pop_keys(&center, free).into_iter() {
     let task = thread_pool.spawn(async move {
-      let chunk = ChunkManager::new_chunk(&key, depth, lod, noise);
-      chunk
+      ChunkManager::new_chunk(&key, depth as u8, lod, noise)
     });
-  
-    // Spawn new entity and add our new task as a component
-    commands.spawn(LoadChunk(task));
+    commands.spawn(LoadChunk { key: key, lod: lod, task: task });
+    queue.in_flight += 1;
   }
-  
 }
 
 fn recv_chunk(
   mut commands: Commands,
   bevy_voxel_res: Res<BevyVoxelResource>,
+  mut queue: ResMut<LoadQueue>,
   mut tasks: Query<(Entity, &mut LoadChunk)>,
+  lod_manager: Res<LodManager>,
 ) {
   for (entity, mut task) in &mut tasks {
-    if let Some(chunk) = future::block_on(future::poll_once(&mut task.0)) {
-      let _ = bevy_voxel_res.send_chunk.send(chunk);
-
-      // Task is complete, so remove task component from entity
-      commands.entity(entity).remove::<LoadChunk>();
+    // Dropping the task cancels it
+    if lod_manager.pending.get(&task.key) != Some(&task.lod) {
+      queue.cancelled += 1;
+      commands.entity(entity).despawn();
+      continue;
     }
-  }
-}
 
+    if let Some(chunk) = future::block_on(future::poll_once(&mut task.task)) {
+      let _ = bevy_voxel_res.send_chunk.send(chunk);
 
-fn recv_process_mesh(
-  mut commands: Commands,
-  bevy_voxel_res: Res<BevyVoxelResource>,
-) {
-  let thread_pool = AsyncComputeTaskPool::get();
-
-  let depth = bevy_voxel_res.chunk_manager.depth;
-  let scale = bevy_voxel_res.chunk_manager.voxel_scale;
-
-  for chunk in bevy_voxel_res.recv_process_mesh.drain() {
-    let colors = bevy_voxel_res.chunk_manager.colors.clone();
-    let task = thread_pool.spawn(async move {
-      chunk.octree.compute_mesh(
-        VoxelMode::SurfaceNets, 
-        &mut VoxelReuse::new(depth, 3), 
-        &colors, 
-        scale, 
-        chunk.key,
-        chunk.lod
-      )
-    });
-  
-    // Spawn new entity and add our new task as a component
-    commands.spawn(LoadMeshData(task));
+      queue.completed += 1;
+      commands.entity(entity).despawn();
+    }
   }
-  
 }
 
 fn recv_mesh(
   mut commands: Commands,
   bevy_voxel_res: Res<BevyVoxelResource>,
+  mut queue: ResMut<LoadQueue>,
   mut tasks: Query<(Entity, &mut LoadMeshData)>,
+  lod_manager: Res<LodManager>,
 ) {
   for (entity, mut task) in &mut tasks {
-    if let Some(data) = future::block_on(future::poll_once(&mut task.0)) {
+    // Dropping the task cancels it
+    if lod_manager.pending.get(&task.key) != Some(&task.lod) {
+      queue.cancelled += 1;
+      commands.entity(entity).despawn();
+      continue;
+    }
+
+    if let Some((data, colliders)) = future::block_on(future::poll_once(&mut task.task)) {
+      if let Some(colliders) = colliders {
+        queue.colliders.insert(data.key, colliders);
+      }
       let _ = bevy_voxel_res.send_mesh.send(data);
 
-      // Task is complete, so remove task component from entity
-      commands.entity(entity).remove::<LoadMeshData>();
+      queue.completed += 1;
+      commands.entity(entity).despawn();
     }
   }
 }
@@ -101,8 +156,15 @@ fn recv_mesh(
 
 
 #[derive(Component)]
-struct LoadChunk(Task<Chunk>);
+struct LoadChunk {
+  key: [i64; 3],
+  lod: usize,
+  task: Task<Chunk>,
+}
 
 #[derive(Component)]
-struct LoadMeshData(Task<MeshData>);
-
+struct LoadMeshData {
+  key: [i64; 3],
+  lod: usize,
+  task: Task<(MeshData, Option<ChunkColliders>)>,
+}
@@ -0,0 +1,31 @@
+use bevy::prelude::*;
+use voxels::chunk::chunk_manager::ChunkManager;
+use crate::simulation::{Simulation, TickSettings, tick_simulation};
+
+pub struct CustomPlugin;
+impl Plugin for CustomPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(GranularSettings::default())
+      .add_systems(Update, tick_simulation::<GranularSettings>);
+  }
+}
+
+#[derive(Resource, Clone)]
+pub struct GranularSettings(pub TickSettings);
+
+impl Default for GranularSettings {
+  fn default() -> Self {
+    Self(TickSettings::new(0.05))
+  }
+}
+
+impl Simulation for GranularSettings {
+  fn ticks(&mut self) -> &mut TickSettings {
+    &mut self.0
+  }
+
+  fn tick(chunk_manager: &mut ChunkManager) -> Vec<[i64; 3]> {
+    chunk_manager.tick_grains()
+  }
+}
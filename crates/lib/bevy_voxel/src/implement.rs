@@ -1,16 +1,16 @@
 use bevy::{prelude::*, utils::HashMap};
-use rapier3d::{prelude::{Vector, ColliderHandle, Ray, QueryFilter}, na::Point3};
-use utils::{RayUtils, Utils};
-use voxels::{chunk::{chunk_manager::{ChunkManager, Chunk}, adjacent_keys}, data::{voxel_octree::{VoxelMode, MeshData}, surface_nets::VoxelReuse}};
+use rapier3d::prelude::ColliderHandle;
+use utils::Utils;
+use voxels::{chunk::{chunk_manager::{ChunkManager, Chunk}, adjacent_keys, coords::WorldVoxelPos, raycast::RaycastHit, connectivity::Island, integrity::StressMap}, formats::{vox::{VoxFile, PaletteMode}, voxelize::{TriangleMesh, VoxelizeOptions}, heightmap::{Heightmap, HeightmapOptions}, MeshNode, gltf::write_glb, weld::WorldMesh}, data::{voxel_octree::{VoxelMode, MeshData}, surface_nets::VoxelReuse, lod_transition::{SIDES, add_transition_skirts}, materials::MaterialRegistry}};
 use voxels::utils::key_to_world_coord_f32;
-use crate::{BevyVoxelResource, physics::Physics, Preview, ShapeState, EditState, ChunkMesh};
+use crate::{BevyVoxelResource, physics::{Physics, chunk_colliders}, Preview, ShapeState, EditState, ChunkMesh};
 use crate::util::*;
 
 use cfg_if::cfg_if;
 
 cfg_if! {
   if #[cfg(target_arch = "wasm32")] {
-    use multithread::plugin::send_colors;
+    use multithread::plugin::send_materials;
   }
 }
 
@@ -38,7 +38,7 @@ impl BevyVoxelResource {
       ranges: ranges,
       ..Default::default()
     };
-    res.update_colors();
+    res.update_materials();
     res
   }
 
@@ -58,22 +58,43 @@ impl BevyVoxelResource {
       chunks.push(load_chunk(self, *key, 0));
       
     }
+    // Already meshed neighbors keep their light until they are remeshed
+    self.chunk_manager.light_chunks(&keys);
 
     chunks
   }
 
   /// Return mesh data needed for collision or rendering
   pub fn compute_mesh(&self, mode: VoxelMode, chunk: &Chunk) -> MeshData {
-    chunk
+    let mut data = chunk
       .octree
-      .compute_mesh(
+      .compute_mesh_passes(
         mode, 
         &mut VoxelReuse::new(self.chunk_manager.depth, 3),
-        &self.chunk_manager.colors,
+        &self.chunk_manager.materials,
         self.chunk_manager.voxel_scale,
         chunk.key,
         chunk.lod
-      )
+      );
+    // The neighbors are sampled at full resolution
+    if chunk.lod == 0 {
+      self.chunk_manager.update_border_ao(&chunk.octree, &mut data);
+      self.chunk_manager.update_border_normals(&chunk.octree, &mut data);
+    }
+    self.chunk_manager.apply_light(&mut data);
+    data
+  }
+
+  /// Adds skirts on the sides facing a chunk of another lod, the neighbor
+  /// lods are taken around lod_center
+  pub fn apply_lod_transitions(&self, data: &mut MeshData) {
+    let neighbor_lods = self.neighbor_lods(&self.lod_center, &data.key, data.lod);
+    add_transition_skirts(
+      data,
+      self.chunk_manager.chunk_size,
+      self.chunk_manager.voxel_scale,
+      &neighbor_lods
+    );
   }
 
   /// Return a world position based on chunk size(depth) and voxel scale
@@ -89,128 +110,31 @@ impl BevyVoxelResource {
     Vec3::new(pos[0], pos[1], pos[2])
   }
 
-  pub fn get_raycast_hit(&self, trans: &Transform) -> Option<Vec3> {
+  /// Voxel traversal from the camera, doesn't need colliders so chunks
+  /// without one(lod chunks) can be picked too. Limited to the last lod range
+  pub fn get_voxel_raycast_hit(&self, trans: &Transform) -> Option<RaycastHit> {
     let start_pos = trans.translation;
     let dir = trans.forward();
-    let ray = Ray::new(
-      Point3::new(start_pos.x, start_pos.y, start_pos.z), 
-      Vector::new(dir.x, dir.y, dir.z)
-    );
-    let max_toi = f32::MAX;
-    let solid = true;
-    let filter = QueryFilter::only_fixed();
-
-    let mut point = None;
-    if let Some((_handle, toi)) = self.physics.query_pipeline.cast_ray(
-      &self.physics.rigid_body_set, 
-      &self.physics.collider_set, 
-      &ray, 
-      max_toi, 
-      solid, 
-      filter
-    ) {
-      let hit = ray.point_at(toi);
-      point = Some(Vec3::new(hit[0], hit[1], hit[2]));
-    }
-    point
-  }
-
-  pub fn get_hit_voxel_pos(&self, point: Vec3) -> Option<Vec3> {
-    let voxel_scale = self.chunk_manager.voxel_scale;
-    let mul = 1.0 / voxel_scale;
-    let mut nearest_dist = f32::MAX;
-
-    let mut pos = None;
-    let n_c = RayUtils::get_nearest_coord(
-      [point.x, point.y, point.z], voxel_scale
-    );
-
-    let tmp_point = Vec3::new(n_c[0], n_c[1], n_c[2]);
-    let near_pos = get_near_positions(tmp_point, voxel_scale);
-    for n in near_pos.iter() {
-      let dist = point.distance(*n);
-      let tmp_pos = [
-        (n[0] * mul) as i64, 
-        (n[1] * mul) as i64, 
-        (n[2] * mul) as i64, 
-      ];
-
-      let res = self.chunk_manager.get_voxel_safe(&tmp_pos);
-      if res.is_some() && res.unwrap() != 0 {
-        if dist < nearest_dist {
-          nearest_dist = dist;
-          pos = Some(*n);
-        }
-      }
-    }
-
-    pos
-  }
-
-  pub fn get_nearest_voxel_air(&self, point: Vec3) -> Option<Vec3> {
-    let voxel_scale = self.chunk_manager.voxel_scale;
-
-    let mul = 1.0 / voxel_scale;
-    let mut nearest_dist = f32::MAX;
-
-    let mut pos = None;
-    let n_c = RayUtils::get_nearest_coord(
-      [point.x, point.y, point.z], voxel_scale
-    );
-
-    let tmp_point = Vec3::new(n_c[0], n_c[1], n_c[2]);
-    let near_pos = get_near_positions(tmp_point, voxel_scale);
-    
-    for n in near_pos.iter() {
-      let dist = point.distance(*n);
-      let tmp_pos = [
-        (n[0] * mul) as i64, 
-        (n[1] * mul) as i64, 
-        (n[2] * mul) as i64, 
-      ];
-
-      let res = self.chunk_manager.get_voxel_safe(&tmp_pos);
-      if res.is_some() && res.unwrap() == 0 {
-        if dist < nearest_dist {
-          nearest_dist = dist;
-          pos = Some(*n);
-        }
-      }
-    }
-
-    // println!("point {:?} : {:?}", point, pos);
-    pos
+    let range = *self.ranges.last().unwrap_or(&1) as f32 + 1.0;
+    let max_dist = range
+      * self.chunk_manager.seamless_size() as f32
+      * self.chunk_manager.voxel_scale;
+
+    self.chunk_manager.raycast(
+      [start_pos.x, start_pos.y, start_pos.z],
+      [dir.x, dir.y, dir.z],
+      max_dist
+    )
   }
 
-  pub fn get_nearest_voxel_by_unit(&self, point: Vec3, unit: f32) -> Option<Vec3> {
-    let mul = 1.0 / unit;
-    let mut nearest_dist = f32::MAX;
-
-    let mut pos = None;
-    let n_c = RayUtils::get_nearest_coord(
-      [point.x, point.y, point.z], unit
-    );
-
-    let tmp_point = Vec3::new(n_c[0], n_c[1], n_c[2]);
-    let near_pos = get_near_positions(tmp_point, unit);
-    for n in near_pos.iter() {
-      let dist = point.distance(*n);
-      let tmp_pos = [
-        (n[0] * mul) as i64, 
-        (n[1] * mul) as i64, 
-        (n[2] * mul) as i64, 
-      ];
-
-      let res = self.chunk_manager.get_voxel_safe(&tmp_pos);
-      if res.is_some() && res.unwrap() > 0 {
-        if dist < nearest_dist {
-          nearest_dist = dist;
-          pos = Some(*n);
-        }
-      }
-    }
-
-    pos
+  /// World position of a voxel coordinate
+  pub fn get_voxel_world_pos(&self, voxel: [i64; 3]) -> Vec3 {
+    let scale = self.chunk_manager.voxel_scale;
+    Vec3::new(
+      voxel[0] as f32 * scale,
+      voxel[1] as f32 * scale,
+      voxel[2] as f32 * scale,
+    )
   }
 
   pub fn get_preview(&self, pos: Vec3, preview: &Preview) -> Chunk {
@@ -299,22 +223,49 @@ impl BevyVoxelResource {
 
 
   pub fn set_voxel(&mut self, pos: Vec3, voxel: u8) {
-    let mul = 1.0 / self.chunk_manager.voxel_scale;
-    let p = [
-      (pos.x * mul) as i64,
-      (pos.y * mul) as i64,
-      (pos.z * mul) as i64,
-    ];
+    let p = self.voxel_pos(pos);
 
     self.chunk_manager.set_voxel2(&p, voxel);
+    self.light_edits.push(p);
   }
 
   pub fn set_voxel_default(
     &mut self, coord: [i64; 3], voxel: u8
   ) -> Vec<([i64; 3], Chunk)> {
+    self.light_edits.push(coord);
     self.chunk_manager.set_voxel2(&coord, voxel)
   }
 
+  /// Updates the light around the voxels set since the last call, adding the
+  /// chunks with changed light to the chunks to remesh. Wakes the liquids
+  /// and grains around them too
+  pub fn relight_edits(&mut self, chunks: &mut HashMap<[i64; 3], Chunk>) {
+    let edits = std::mem::take(&mut self.light_edits);
+    self.chunk_manager.wake_fluids(&edits);
+    self.chunk_manager.wake_grains(&edits);
+    for key in self.chunk_manager.update_light(&edits) {
+      if chunks.contains_key(&key) {
+        continue;
+      }
+      if let Some(chunk) = self.chunk_manager.get_chunk(&key) {
+        chunks.insert(key, chunk.clone());
+      }
+    }
+  }
+
+  /// Chunks to remesh after a simulation tick changed the voxels
+  pub fn simulated_chunks(&mut self, changed: Vec<[i64; 3]>) -> HashMap<[i64; 3], Chunk> {
+    let mut chunks = HashMap::new();
+    for key in self.chunk_manager.voxel_keys(&changed) {
+      if let Some(chunk) = self.chunk_manager.get_chunk(&key) {
+        chunks.insert(key, chunk.clone());
+      }
+    }
+    self.light_edits.extend(changed);
+    self.relight_edits(&mut chunks);
+    chunks
+  }
+
   pub fn set_voxel_cube(
     &mut self, pos: Vec3, preview: &Preview
   ) -> HashMap<[i64; 3], Chunk> {
@@ -456,6 +407,164 @@ impl BevyVoxelResource {
   }
 
 
+  /// Solid voxels around an edit that are no longer anchored.
+  /// extent is the edit's half size in voxels
+  pub fn get_detached_islands(&self, pos: Vec3, extent: i64) -> Vec<Island> {
+    let p = self.voxel_pos(pos);
+    let range = extent + self.island_margin;
+    let min = [p[0] - range, p[1] - range, p[2] - range];
+    let max = [p[0] + range, p[1] + range, p[2] + range];
+    self.chunk_manager.detached_islands(min, max, self.anchor_rule)
+  }
+
+  /// Stress of the voxels around an edit, margin voxels past its extent
+  pub fn get_stress_map(&self, pos: Vec3, extent: i64, margin: i64) -> StressMap {
+    let p = self.voxel_pos(pos);
+    let range = extent + margin;
+    let min = [p[0] - range, p[1] - range, p[2] - range];
+    let max = [p[0] + range, p[1] + range, p[2] + range];
+    self.chunk_manager.stress_map(min, max, self.anchor_rule)
+  }
+
+  /// Places the .vox models with their min corner at pos, returns the
+  /// chunks to remesh
+  pub fn import_vox(
+    &mut self,
+    vox: &VoxFile,
+    pos: Vec3,
+    mode: PaletteMode
+  ) -> HashMap<[i64; 3], Chunk> {
+    let voxels = self.chunk_manager.place_vox(vox, self.voxel_pos(pos), mode);
+    self.place_voxels(&voxels)
+  }
+
+  /**
+    Voxelizes the mesh placed in the world by the transform, a voxel per
+    voxel_scale. None for voxel maps the colors onto the materials
+  */
+  pub fn import_mesh(
+    &mut self,
+    mesh: &TriangleMesh,
+    transform: &Transform,
+    fill: bool,
+    voxel: Option<u8>
+  ) -> Result<HashMap<[i64; 3], Chunk>, String> {
+    let options = self.voxelize_options(transform, fill, voxel);
+    let voxels = self.chunk_manager.place_mesh(mesh, &options)?;
+    Ok(self.place_voxels(&voxels))
+  }
+
+  /// Voxelizes in voxel_scale voxels, the mesh placed by the transform
+  pub fn voxelize_options(
+    &self,
+    transform: &Transform,
+    fill: bool,
+    voxel: Option<u8>
+  ) -> VoxelizeOptions {
+    VoxelizeOptions {
+      voxel_size: self.chunk_manager.voxel_scale,
+      transform: transform.compute_matrix().to_cols_array_2d(),
+      fill: fill,
+      voxel: voxel,
+    }
+  }
+
+  /// Writes the heightmap into the terrain, see ChunkManager::import_heightmap
+  pub fn import_heightmap(
+    &mut self,
+    map: &Heightmap,
+    options: &HeightmapOptions
+  ) -> Result<HashMap<[i64; 3], Chunk>, String> {
+    options.check()?;
+    let mut res = HashMap::new();
+    for (min, max) in map.tiles() {
+      let voxels = self.chunk_manager.place_heightmap(map, options, min, max);
+      res.extend(self.place_voxels(&voxels));
+    }
+    Ok(res)
+  }
+
+  /// Sets the voxels and relights around them, returns the changed chunks
+  pub fn place_voxels(&mut self, voxels: &[([i64; 3], u8)]) -> HashMap<[i64; 3], Chunk> {
+    self.light_edits.extend(voxels.iter().map(|(pos, _)| *pos));
+
+    let mut res = HashMap::new();
+    for key in self.chunk_manager.set_voxels(voxels) {
+      if let Some(chunk) = self.chunk_manager.get_chunk(&key) {
+        res.insert(key, chunk.clone());
+      }
+    }
+    self.relight_edits(&mut res);
+    res
+  }
+
+  /**
+    Binary glTF of the chunk meshes with a node per chunk at get_pos, or a
+    single mesh when merging. None exports every loaded chunk
+  */
+  pub fn export_glb(&self, keys: Option<&Vec<[i64; 3]>>, merge: bool) -> Vec<u8> {
+    let meshes = self.export_meshes(keys);
+    write_glb(&self.mesh_nodes(&meshes), &self.chunk_manager.materials, merge)
+  }
+
+  /// Chunk meshes in world space for the OBJ, STL and PLY writers, welding
+  /// the chunk seams into a single mesh. None exports every loaded chunk
+  pub fn export_world_mesh(&self, keys: Option<&Vec<[i64; 3]>>, weld: bool) -> WorldMesh {
+    let meshes = self.export_meshes(keys);
+    WorldMesh::new(&self.mesh_nodes(&meshes), weld)
+  }
+
+  fn export_meshes(&self, keys: Option<&Vec<[i64; 3]>>) -> Vec<([i64; 3], MeshData)> {
+    let mut keys: Vec<[i64; 3]> = match keys {
+      Some(keys) => keys.clone(),
+      None => self.chunk_manager.chunks.keys().cloned().collect(),
+    };
+    keys.sort();
+
+    let mut meshes = Vec::new();
+    for key in keys.iter() {
+      let chunk = match self.chunk_manager.get_chunk(key) {
+        Some(chunk) => chunk,
+        None => continue,
+      };
+      if !chunk.mode.has_surface() {
+        continue;
+      }
+      let data = self.compute_mesh(VoxelMode::SurfaceNets, chunk);
+      if !data.is_empty() {
+        meshes.push((*key, data));
+      }
+    }
+    meshes
+  }
+
+  fn mesh_nodes<'a>(&self, meshes: &'a [([i64; 3], MeshData)]) -> Vec<MeshNode<'a>> {
+    meshes
+      .iter()
+      .map(|(key, data)| MeshNode {
+        name: format!("chunk {} {} {}", key[0], key[1], key[2]),
+        translation: self.get_pos(*key).into(),
+        mesh: data,
+      })
+      .collect()
+  }
+
+  /// Voxels between the world positions as a .vox model
+  pub fn export_vox(&self, min: Vec3, max: Vec3) -> Result<VoxFile, String> {
+    self.chunk_manager.export_vox(self.voxel_pos(min), self.voxel_pos(max))
+  }
+
+  /// Top down heightmap of the surface between the world positions
+  pub fn export_heightmap(&self, min: Vec3, max: Vec3) -> Result<Heightmap, String> {
+    self.chunk_manager.export_heightmap(self.voxel_pos(min), self.voxel_pos(max))
+  }
+
+  /// Voxel nearest to the world position
+  pub fn voxel_pos(&self, pos: Vec3) -> [i64; 3] {
+    WorldVoxelPos::from_world(pos.into(), &self.chunk_manager.layout()).0
+  }
+
+
   /// Load Chunks, MeshData then create Collider for MeshData
   pub fn load_adj_mesh_data(&mut self, key: [i64; 3]) -> Vec<([i64; 3], MeshData)> {
     let mut mesh_data = Vec::new();
@@ -469,8 +578,12 @@ impl BevyVoxelResource {
     self.colliders_cache.clear();
 
     for chunk in chunks.iter() {
+      if !chunk.mode.has_surface() {
+        continue;
+      }
+
       let data = self.compute_mesh(VoxelMode::SurfaceNets, chunk);
-      if data.positions.len() == 0 {
+      if data.is_empty() {
         continue;
       }
 
@@ -494,8 +607,12 @@ impl BevyVoxelResource {
     self.colliders_cache.clear();
 
     for chunk in chunks.iter() {
+      if !chunk.mode.has_surface() {
+        continue;
+      }
+
       let data = self.compute_mesh(VoxelMode::SurfaceNets, chunk);
-      if data.positions.len() == 0 {
+      if data.is_empty() {
         continue;
       }
 
@@ -513,9 +630,8 @@ impl BevyVoxelResource {
     pos: Vec3, 
     data: &MeshData
   ) -> ColliderHandle {
-    self.physics.add_collider(
-      [pos.x, pos.y, pos.z], &data.positions, &data.indices
-    )
+    let colliders = chunk_colliders(pos, data, &self.chunk_manager.materials);
+    self.physics.insert_chunk_colliders(colliders)
   }
 
   pub fn remove_collider(&mut self, handle: ColliderHandle) {
@@ -648,12 +764,16 @@ impl BevyVoxelResource {
     let keys = self.get_keys_by_lod(key, lod);
     for k in keys.iter() {
       let chunk = load_chunk_with_lod(self, *k, lod);
-      let data = self.compute_mesh(VoxelMode::SurfaceNets, &chunk);
-      if data.positions.len() == 0 {
+      if !chunk.mode.has_surface() {
         continue;
       }
 
-      
+      let mut data = self.compute_mesh(VoxelMode::SurfaceNets, &chunk);
+      if data.is_empty() {
+        continue;
+      }
+      self.apply_lod_transitions(&mut data);
+
       chunk_meshes.push(ChunkMesh { key: *k, mesh: data });
     }
 
@@ -683,6 +803,7 @@ impl BevyVoxelResource {
     // for key in keys.iter() {
     //   chunks.push(load_chunk(self, *key, lod));
     // }
+    self.chunk_manager.light_chunks(keys);
     chunks
   }
 
@@ -699,13 +820,19 @@ impl BevyVoxelResource {
     // self.colliders_cache.clear();
 
     for chunk in chunks.iter() {
-      let data = self.compute_mesh(VoxelMode::SurfaceNets, chunk);
-      if data.positions.len() == 0 {
+      if !chunk.mode.has_surface() {
+        continue;
+      }
+
+      let mut data = self.compute_mesh(VoxelMode::SurfaceNets, chunk);
+      if data.is_empty() {
         continue;
       }
 
       let pos = self.get_pos(chunk.key);
       let c = self.add_collider(pos, &data);
+      // Skirts are only for rendering
+      self.apply_lod_transitions(&mut data);
       // self.colliders_cache.push(c);
       mesh_data.push((data, c));
     }
@@ -714,6 +841,63 @@ impl BevyVoxelResource {
   }
 
 
+  /// Lod ring of the key around the center, None outside of the last range
+  pub fn get_lod(&self, center: &[i64; 3], key: &[i64; 3]) -> Option<usize> {
+    (0..self.ranges.len() - 1).find(|lod| {
+      Utils::in_range_by_lod(center, key, &self.ranges, *lod)
+    })
+  }
+
+  /// Lods of the adjacent chunks in the order of lod_transition::SIDES,
+  /// unloaded sides count as the same lod
+  pub fn neighbor_lods(
+    &self, center: &[i64; 3], key: &[i64; 3], lod: usize
+  ) -> [usize; 6] {
+    let mut lods = [lod; 6];
+    for (i, side) in SIDES.iter().enumerate() {
+      let k = [key[0] + side[0], key[1] + side[1], key[2] + side[2]];
+      if let Some(l) = self.get_lod(center, &k) {
+        lods[i] = l;
+      }
+    }
+    lods
+  }
+
+  /**
+    Keys keeping their lod while the center moves whose neighbor lods change,
+    their skirts have to be remeshed
+  */
+  pub fn get_transition_keys(
+    &self, prev_key: &[i64; 3], key: &[i64; 3]
+  ) -> Vec<([i64; 3], usize)> {
+    let mut res = Vec::new();
+    if prev_key == key {
+      return res;
+    }
+
+    for lod in 0..self.ranges.len() - 1 {
+      for delta in self.get_delta_keys_by_lod(prev_key, key, lod).iter() {
+        for side in SIDES.iter() {
+          let k = [delta[0] + side[0], delta[1] + side[1], delta[2] + side[2]];
+          let l = match self.get_lod(key, &k) {
+            Some(l) => l,
+            None => continue,
+          };
+          if self.get_lod(prev_key, &k) != Some(l) {
+            continue;
+          }
+
+          let prev = self.neighbor_lods(prev_key, &k, l);
+          let current = self.neighbor_lods(key, &k, l);
+          if prev != current && !res.contains(&(k, l)) {
+            res.push((k, l));
+          }
+        }
+      }
+    }
+    res
+  }
+
   pub fn get_delta_keys_by_lod(
     &self, prev_key: &[i64; 3], key: &[i64; 3], lod: usize
   ) -> Vec<[i64; 3]> {
@@ -735,10 +919,27 @@ impl BevyVoxelResource {
 
 
 
-  pub fn update_colors(&self) {
+  /// Replaces the voxel materials, the meshes keep their colors until they
+  /// are remeshed
+  pub fn set_materials(&mut self, materials: MaterialRegistry) {
+    self.chunk_manager.set_materials(materials);
+    self.update_materials();
+  }
+
+  /// Copies the edited palette into the materials and sends them
+  pub fn update_colors(&mut self) {
+    let colors = self.chunk_manager.colors.clone();
+    for (material, color) in self.chunk_manager.materials.materials.iter_mut().zip(colors) {
+      material.color = color;
+    }
+    self.update_materials();
+  }
+
+  /// Sends the materials to the wasm mesh workers
+  pub fn update_materials(&self) {
     cfg_if! {
       if #[cfg(target_arch = "wasm32")] {
-        send_colors(&self.chunk_manager.colors);
+        send_materials(&self.chunk_manager.materials);
       }
     }
   }
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task}};
+use futures_lite::future;
+use voxels::formats::{obj::read_obj, gltf::read_gltf, voxelize::{TriangleMesh, voxelize_materials}, heightmap::{Heightmap, HeightmapOptions}};
+use crate::{BevyVoxelResource, Preview, Chunks, remesh::RemeshQueue};
+
+pub struct CustomPlugin;
+impl Plugin for CustomPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(ImportSettings::default())
+      .add_systems(Update, (import_dropped, recv_import).chain());
+  }
+}
+
+/// How the meshes and heightmaps dropped on the window are imported
+#[derive(Resource, Clone)]
+pub struct ImportSettings {
+  pub enabled: bool,
+  /// World size of a mesh unit
+  pub scale: f32,
+  /// Fills the closed meshes, else only their surface
+  pub fill: bool,
+  /// Sets every voxel to the value, None maps the colors onto the materials
+  pub voxel: Option<u8>,
+  /// The origin and offset follow the preview
+  pub heightmap: HeightmapOptions,
+}
+
+impl Default for ImportSettings {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      scale: 1.0,
+      fill: true,
+      voxel: None,
+      heightmap: HeightmapOptions::default(),
+    }
+  }
+}
+
+/// Triangles of an OBJ, glTF or glb file, the OBJ reads the MTL next to it
+pub fn read_mesh_file(path: &std::path::Path) -> Result<TriangleMesh, String> {
+  match extension(path).as_str() {
+    "obj" => {
+      let obj = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+      let mtl = std::fs::read_to_string(path.with_extension("mtl")).ok();
+      read_obj(&obj, mtl.as_deref())
+    }
+    "gltf" | "glb" => read_gltf(&std::fs::read(path).map_err(|e| e.to_string())?),
+    _ => Err(format!("Unsupported mesh file {:?}", path)),
+  }
+}
+
+/// Heights of a PNG, or of a square .r16 or .raw file
+pub fn read_heightmap_file(path: &std::path::Path) -> Result<Heightmap, String> {
+  let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+  match extension(path).as_str() {
+    "png" => Heightmap::read_png(&bytes),
+    "r16" | "raw" => Heightmap::read_raw16(&bytes, ((bytes.len() / 2) as f64).sqrt() as u32),
+    _ => Err(format!("Unsupported heightmap file {:?}", path)),
+  }
+}
+
+fn extension(path: &std::path::Path) -> String {
+  path.extension()
+    .and_then(|e| e.to_str())
+    .unwrap_or("")
+    .to_lowercase()
+}
+
+/**
+  Reads and voxelizes the dropped meshes off the main thread, with their
+  origin at the preview. Dropped heightmaps start at the preview, black at
+  its height
+*/
+fn import_dropped(
+  mut commands: Commands,
+  mut drop_events: EventReader<FileDragAndDrop>,
+  settings: Res<ImportSettings>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
+  previews: Query<&Preview>,
+) {
+  let thread_pool = AsyncComputeTaskPool::get();
+  for e in drop_events.iter() {
+    let path_buf = match e {
+      FileDragAndDrop::DroppedFile { path_buf, .. } => path_buf.clone(),
+      _ => continue,
+    };
+    if !settings.enabled {
+      continue;
+    }
+    let is_heightmap = ["png", "r16", "raw"].contains(&extension(&path_buf).as_str());
+
+    for preview in previews.iter() {
+      let pos = match preview.pos {
+        Some(pos) => pos,
+        None => continue,
+      };
+      let path = path_buf.clone();
+      let task = match is_heightmap {
+        true => {
+          let p = bevy_voxel_res.voxel_pos(pos);
+          let options = HeightmapOptions {
+            origin: [p[0], p[2]],
+            offset: p[1],
+            ..settings.heightmap.clone()
+          };
+          thread_pool.spawn(async move {
+            read_heightmap_file(&path).map(|map| Dropped::Heightmap(map, options))
+          })
+        }
+        false => {
+          let transform = Transform::from_translation(pos).with_scale(Vec3::splat(settings.scale));
+          let options = bevy_voxel_res.voxelize_options(&transform, settings.fill, settings.voxel);
+          let materials = bevy_voxel_res.chunk_manager.materials.clone();
+          thread_pool.spawn(async move {
+            read_mesh_file(&path)
+              .and_then(|mesh| voxelize_materials(&mesh, &options, &materials))
+              .map(Dropped::Mesh)
+          })
+        }
+      };
+      commands.spawn(ImportTask { path: path_buf.clone(), task: task });
+    }
+  }
+}
+
+/// Writes the finished imports into the chunks
+fn recv_import(
+  mut commands: Commands,
+  mut tasks: Query<(Entity, &mut ImportTask)>,
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  mut chunks: Query<&mut Chunks>,
+  mut remesh: ResMut<RemeshQueue>,
+) {
+  for (entity, mut task) in &mut tasks {
+    let dropped = match future::block_on(future::poll_once(&mut task.task)) {
+      Some(dropped) => dropped,
+      None => continue,
+    };
+    commands.entity(entity).despawn();
+
+    let res = match dropped {
+      Ok(Dropped::Mesh(voxels)) => bevy_voxel_res.place_voxels(&voxels),
+      Ok(Dropped::Heightmap(map, options)) => match bevy_voxel_res.import_heightmap(&map, &options) {
+        Ok(res) => res,
+        Err(e) => {
+          info!("Could not import {:?}: {}", task.path, e);
+          continue;
+        }
+      },
+      Err(e) => {
+        info!("Could not import {:?}: {}", task.path, e);
+        continue;
+      }
+    };
+    for mut chunks in &mut chunks {
+      for (key, chunk) in res.iter() {
+        chunks.data.insert(*key, chunk.clone());
+      }
+    }
+    remesh.mark_dirty(&res);
+  }
+}
+
+/// Voxels of a dropped mesh, or a dropped heightmap with its options
+enum Dropped {
+  Mesh(Vec<([i64; 3], u8)>),
+  Heightmap(Heightmap, HeightmapOptions),
+}
+
+#[derive(Component)]
+struct ImportTask {
+  path: PathBuf,
+  task: Task<Result<Dropped, String>>,
+}
@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+use voxels::chunk::integrity::StressMap;
+use crate::{BevyVoxelResource, Preview, editstate::{EditEvents, EditEvent, DetachedIslandEvent}};
+
+pub struct CustomPlugin;
+impl Plugin for CustomPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(IntegritySettings::default())
+      .insert_resource(Integrity::default())
+      // After the edits of the frame are applied
+      .add_systems(PostUpdate, check_integrity)
+      .add_systems(Update, draw_heatmap);
+  }
+}
+
+#[derive(Resource, Clone)]
+pub struct IntegritySettings {
+  pub enabled: bool,
+  /// Voxels past the edit the stress is computed for, the longest span
+  /// that can fail is about this long
+  pub margin: i64,
+  /// Draws the load of the last checked voxels
+  pub show_heatmap: bool,
+}
+
+impl Default for IntegritySettings {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      margin: 16,
+      show_heatmap: false,
+    }
+  }
+}
+
+/// Stress of the voxels around the last edit, for queries and the heatmap
+#[derive(Resource, Default)]
+pub struct Integrity {
+  pub last: Option<StressMap>,
+}
+
+/// Sends the voxels the edits overloaded down the detached island path
+fn check_integrity(
+  settings: Res<IntegritySettings>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
+  mut integrity: ResMut<Integrity>,
+  previews: Query<&Preview>,
+
+  mut edit_event_reader: EventReader<EditEvents>,
+  mut island_writer: EventWriter<DetachedIslandEvent>,
+) {
+  for e in edit_event_reader.iter() {
+    if !settings.enabled {
+      continue;
+    }
+
+    for preview in &previews {
+      let p = match preview.pos {
+        Some(p) => p,
+        None => continue,
+      };
+      let extent = match e.event {
+        EditEvent::AddCube | EditEvent::RemoveCube => preview.size as i64 / 2 + 1,
+        EditEvent::AddSphere | EditEvent::RemoveSphere => preview.sphere_size.ceil() as i64 + 1,
+      };
+
+      let map = bevy_voxel_res.get_stress_map(p, extent, settings.margin);
+      for island in map.collapsing_islands() {
+        island_writer.send(DetachedIslandEvent { voxels: island.voxels });
+      }
+      integrity.last = Some(map);
+    }
+  }
+}
+
+/// Green voxels carry nothing, red ones are about to break
+fn draw_heatmap(
+  settings: Res<IntegritySettings>,
+  integrity: Res<Integrity>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
+  mut gizmos: Gizmos,
+) {
+  if !settings.show_heatmap {
+    return;
+  }
+  let map = match &integrity.last {
+    Some(map) => map,
+    None => return,
+  };
+
+  let scale = bevy_voxel_res.chunk_manager.voxel_scale;
+  for (pos, load) in map.loads() {
+    let load = load.min(1.0);
+    let translation = Vec3::new(pos[0] as f32, pos[1] as f32, pos[2] as f32) * scale;
+    let transform = Transform::from_translation(translation)
+      .with_scale(Vec3::splat(scale * 1.01));
+    gizmos.cuboid(transform, Color::rgb(load, 1.0 - load, 0.0));
+  }
+}
@@ -3,14 +3,21 @@ mod util;
 mod functions;
 mod implement;
 pub mod editstate;
-mod lod;
+pub mod debris;
+pub mod remesh;
+pub mod lod;
+pub mod fluid;
+pub mod granular;
+pub mod simulation;
+pub mod integrity;
+pub mod import;
 
 
 use bevy::{prelude::*, utils::HashMap};
 use flume::{Sender, Receiver};
 use physics::Physics;
 use rapier3d::prelude::ColliderHandle;
-use voxels::{chunk::chunk_manager::{ChunkManager, Chunk}, data::voxel_octree::MeshData};
+use voxels::{chunk::{chunk_manager::{ChunkManager, Chunk}, connectivity::AnchorRule}, data::voxel_octree::MeshData};
 
 use cfg_if::cfg_if;
 
@@ -29,7 +36,13 @@ impl Plugin for BevyVoxelPlugin {
       .add_state::<ShapeState>()
       .add_plugins(functions::CustomPlugin)
       .add_plugins(editstate::CustomPlugin)
-      .add_plugins(lod::CustomPlugin);
+      .add_plugins(debris::CustomPlugin)
+      .add_plugins(remesh::CustomPlugin)
+      .add_plugins(lod::CustomPlugin)
+      .add_plugins(fluid::CustomPlugin)
+      .add_plugins(granular::CustomPlugin)
+      .add_plugins(integrity::CustomPlugin)
+      .add_plugins(import::CustomPlugin);
 
     cfg_if! {
       if #[cfg(target_arch = "wasm32")] {
@@ -61,9 +74,17 @@ pub struct BevyVoxelResource {
   pub recv_mesh: Receiver<MeshData>,
 
   colliders_cache: Vec<ColliderHandle>,
+  light_edits: Vec<[i64; 3]>,
   shape_state: ShapeState,
   edit_state: EditState,
   pub ranges: Vec<u32>,
+  /// Key the lod rings are centered on, follows the loaded Center
+  pub lod_center: [i64; 3],
+
+  /// What keeps solid voxels from being detached after removing voxels
+  pub anchor_rule: AnchorRule,
+  /// Voxels around a remove edit searched for detached islands
+  pub island_margin: i64,
 }
 
 impl Default for BevyVoxelResource {
@@ -77,9 +98,13 @@ impl Default for BevyVoxelResource {
       chunk_manager: ChunkManager::default(),
       physics: Physics::default(),
       colliders_cache: Vec::new(),
+      light_edits: Vec::new(),
       shape_state: ShapeState::Cube,
       edit_state: EditState::AddNormal,
       ranges: vec![0, 1, 3, 5, 7],
+      lod_center: [0; 3],
+      anchor_rule: AnchorRule::Bounds,
+      island_margin: 8,
 
       send_key: send_key,
       recv_key: recv_key,
@@ -170,6 +195,8 @@ pub struct ChunkData {
 pub struct MeshComponent {
   pub data: HashMap<[i64; 3], MeshData>,
   pub added: Vec<(MeshData, ColliderHandle)>,
+  /// Keys whose mesh and collider have to be removed
+  pub removed: Vec<[i64; 3]>,
 }
 
 #[derive(Component, Debug, Clone)]
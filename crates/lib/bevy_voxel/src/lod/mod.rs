@@ -1,7 +1,292 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use rapier3d::prelude::ColliderHandle;
+use voxels::{utils::grid_hashmap::GridHashMap, chunk::chunk_manager::Chunk, data::voxel_octree::MeshData};
+use crate::{BevyVoxelResource, Chunks, Center, MeshComponent};
+use queue::LoadQueue;
+
+use cfg_if::cfg_if;
+
+pub mod queue;
 
 pub struct CustomPlugin;
 impl Plugin for CustomPlugin {
-  fn build(&self, _app: &mut App) {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(LodManager::default())
+      .insert_resource(LoadQueue::default())
+      .add_systems(Update, (
+        update_lods,
+        receive_chunks,
+        receive_mesh,
+      ).chain());
+  }
+}
+
+/// Tracks which lod each key around the Center is loaded at
+#[derive(Resource, Debug, Clone, Default)]
+pub struct LodManager {
+  /// Keys with their mesh in place, or without a surface to mesh
+  pub loaded: GridHashMap<usize, [i64; 3]>,
+  /// Keys waiting for their async chunk or mesh at the lod
+  pub pending: GridHashMap<usize, [i64; 3]>,
+
+  /// Counts since startup, for debugging
+  pub upgrades: usize,
+  pub downgrades: usize,
+  pub unloads: usize,
+}
+
+impl LodManager {
+  /// Lod the key is waiting for, otherwise the one it is loaded at
+  pub fn target_lod(&self, key: &[i64; 3]) -> Option<usize> {
+    self.pending.get(key).or(self.loaded.get(key)).copied()
+  }
+
+  /// Loaded keys by lod, pending keys are not counted
+  pub fn loaded_count(&self, lod: usize) -> usize {
+    self.loaded.values().filter(|l| **l == lod).count()
+  }
+
+  pub(crate) fn set_loaded(&mut self, key: [i64; 3], lod: usize) {
+    self.pending.remove(&key);
+    self.loaded.insert(key, lod);
+  }
+
+  fn request(&mut self, key: [i64; 3], lod: usize) {
+    match self.target_lod(&key) {
+      Some(prev) if lod < prev => self.upgrades += 1,
+      Some(prev) if lod > prev => self.downgrades += 1,
+      _ => {}
+    }
+    self.pending.insert(key, lod);
+  }
+}
+
+/// Lod of every key inside the rings around the center
+fn lod_targets(res: &BevyVoxelResource, center: &[i64; 3]) -> HashMap<[i64; 3], usize> {
+  let mut targets = HashMap::new();
+  for lod in 0..res.ranges.len() - 1 {
+    for key in res.get_keys_by_lod(*center, lod).iter() {
+      targets.entry(*key).or_insert(lod);
+    }
+  }
+  targets
+}
+
+/**
+  Diffs the rings around the moved Center against what is loaded: keys out of
+  range are unloaded and the others are requested from the async loaders
+*/
+fn update_lods(
+  mut res: ResMut<BevyVoxelResource>,
+  mut lod_manager: ResMut<LodManager>,
+  mut centers: Query<
+    (&Center, &mut Chunks, &mut MeshComponent),
+    Or<(Added<Chunks>, Changed<Center>)>
+  >,
+) {
+  for (center, mut chunks, mut mesh_comp) in &mut centers {
+    res.lod_center = center.key;
+    let targets = lod_targets(&res, &center.key);
+
+    let mut unloaded: Vec<[i64; 3]> = lod_manager
+      .loaded
+      .keys()
+      .chain(lod_manager.pending.keys())
+      .filter(|key| !targets.contains_key(*key))
+      .copied()
+      .collect();
+    unloaded.sort();
+    unloaded.dedup();
+    for key in unloaded.iter() {
+      lod_manager.loaded.remove(key);
+      lod_manager.pending.remove(key);
+      lod_manager.unloads += 1;
+
+      chunks.data.remove(key);
+      mesh_comp.data.remove(key);
+      mesh_comp.removed.push(*key);
+    }
+
+    let mut keys: Vec<([i64; 3], usize)> = targets
+      .iter()
+      .filter(|(key, lod)| lod_manager.target_lod(key) != Some(**lod))
+      .map(|(key, lod)| (*key, *lod))
+      .collect();
+    // Skirts facing the moved rings
+    for (key, lod) in res.get_transition_keys(&center.prev_key, &center.key) {
+      if !keys.contains(&(key, lod)) {
+        keys.push((key, lod));
+      }
+    }
+    keys.sort();
+
+    for (key, lod) in keys.iter() {
+      lod_manager.request(*key, *lod);
+
+      // Edited chunks are kept by the ChunkManager
+      match res.chunk_manager.get_chunk(key).or(chunks.data.get(key)) {
+        Some(c) => {
+          let mut chunk = c.clone();
+          chunk.lod = *lod;
+          let _ = res.send_chunk.send(chunk);
+        }
+        None => {
+          let _ = res.send_key.send((*key, *lod));
+        }
+      }
+    }
+  }
+}
+
+/// Lod 0 chunks are kept in the ChunkManager and lit before they are meshed
+fn receive_chunks(
+  mut res: ResMut<BevyVoxelResource>,
+  mut lod_manager: ResMut<LodManager>,
+  mut chunks: Query<&mut Chunks>,
+  mut queries: Query<&mut MeshComponent>,
+) {
+  let mut main_keys = Vec::new();
+  let mut received = Vec::new();
+  let new_chunks: Vec<Chunk> = res.recv_chunk.drain().collect();
+  for mut c in new_chunks.into_iter() {
+    // Stale request, the key moved to another lod or out of range
+    if lod_manager.pending.get(&c.key) != Some(&c.lod) {
+      continue;
+    }
+
+    if c.lod == 0 {
+      // Edits made while the chunk was generated are kept
+      res.chunk_manager.set_chunk(&c.key, &c);
+      if let Some(chunk) = res.chunk_manager.get_chunk(&c.key) {
+        c = Chunk { lod: 0, ..chunk.clone() };
+      }
+      main_keys.push(c.key);
+    }
+    received.push(c);
+  }
+
+  if !main_keys.is_empty() {
+    res.chunk_manager.light_chunks(&main_keys);
+    for mut chunks in &mut chunks {
+      chunks.added_keys.clear();
+      chunks.added_keys.extend(main_keys.iter());
+    }
+  }
+
+  for c in received.into_iter() {
+    if c.lod == 0 {
+      for mut chunks in &mut chunks {
+        chunks.data.insert(c.key, c.clone());
+      }
+    }
+
+    if !c.mode.has_surface() {
+      lod_manager.set_loaded(c.key, c.lod);
+      for mut mesh_comp in &mut queries {
+        mesh_comp.data.remove(&c.key);
+        mesh_comp.removed.push(c.key);
+      }
+      continue;
+    }
+    let _ = res.send_process_mesh.send(c);
+  }
+}
+
+/// Lod 0 meshes get their colliders
+fn receive_mesh(
+  mut res: ResMut<BevyVoxelResource>,
+  mut lod_manager: ResMut<LodManager>,
+  mut queue: ResMut<LoadQueue>,
+  mut queries: Query<&mut MeshComponent>,
+) {
+  let meshes: Vec<MeshData> = res.recv_mesh.drain().collect();
+  for mut data in meshes.into_iter() {
+    let colliders = queue.colliders.remove(&data.key);
+    if lod_manager.pending.get(&data.key) != Some(&data.lod) {
+      continue;
+    }
+    lod_manager.set_loaded(data.key, data.lod);
+
+    // The workers mesh without the adjacent chunks, the task pool samples
+    // them in the task
+    cfg_if! {
+      if #[cfg(target_arch = "wasm32")] {
+        if data.lod == 0 {
+          if let Some(chunk) = res.chunk_manager.get_chunk(&data.key) {
+            res.chunk_manager.update_border_ao(&chunk.octree, &mut data);
+            res.chunk_manager.update_border_normals(&chunk.octree, &mut data);
+          }
+        }
+      }
+    }
+    res.chunk_manager.apply_light(&mut data);
+
+    let mut handle = ColliderHandle::invalid();
+    if let Some(colliders) = colliders {
+      handle = res.physics.insert_chunk_colliders(colliders);
+    }
+    cfg_if! {
+      if #[cfg(target_arch = "wasm32")] {
+        // The workers only return the mesh
+        if data.lod == 0 && !data.is_empty() {
+          let pos = res.get_pos(data.key);
+          handle = res.add_collider(pos, &data);
+        }
+      }
+    }
+
+    for mut mesh_comp in &mut queries {
+      if !data.is_empty() {
+        mesh_comp.data.insert(data.key, data.clone());
+        mesh_comp.added.push((data.clone(), handle));
+      } else {
+        mesh_comp.data.remove(&data.key);
+        mesh_comp.removed.push(data.key);
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::BevyVoxelResource;
+  use super::{lod_targets, LodManager};
+
+  #[test]
+  fn test_lod_targets() -> Result<(), String> {
+    let res = BevyVoxelResource::default();
+    let center = [0, 0, 0];
+    let targets = lod_targets(&res, &center);
+
+    for (key, lod) in targets.iter() {
+      assert_eq!(res.get_lod(&center, key), Some(*lod), "key {:?}", key);
+    }
+    assert_eq!(targets.get(&[1, 1, 1]), Some(&0));
+    assert_eq!(targets.get(&[2, 0, 0]), Some(&1));
+
+    let last = *res.ranges.last().unwrap() as i64;
+    assert_eq!(targets.get(&[last + 1, 0, 0]), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_lod_manager_requests() -> Result<(), String> {
+    let mut manager = LodManager::default();
+    manager.request([0, 0, 0], 2);
+    assert_eq!(manager.target_lod(&[0, 0, 0]), Some(2));
+
+    manager.set_loaded([0, 0, 0], 2);
+    assert_eq!(manager.loaded_count(2), 1);
+    assert!(manager.pending.is_empty());
+
+    manager.request([0, 0, 0], 1);
+    manager.request([0, 0, 0], 3);
+    assert_eq!(manager.upgrades, 1);
+    assert_eq!(manager.downgrades, 1);
+    assert_eq!(manager.target_lod(&[0, 0, 0]), Some(3));
+    assert_eq!(manager.loaded.get(&[0, 0, 0]), Some(&2));
+    Ok(())
   }
-}
\ No newline at end of file
+}
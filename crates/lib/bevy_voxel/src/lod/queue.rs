@@ -0,0 +1,140 @@
+use bevy::{prelude::*, utils::HashMap};
+use voxels::chunk::chunk_manager::Chunk;
+use crate::physics::ChunkColliders;
+
+/// Chunk generation and meshing jobs waiting for a task
+#[derive(Resource, Debug, Clone)]
+pub struct LoadQueue {
+  /// Keys waiting for their chunk, a repeated key keeps the last lod
+  pub keys: HashMap<[i64; 3], usize>,
+  /// Chunks waiting for their mesh, a repeated key keeps the last chunk
+  pub chunks: HashMap<[i64; 3], Chunk>,
+  /// Colliders built along with the lod 0 meshes, taken with the mesh
+  pub colliders: HashMap<[i64; 3], ChunkColliders>,
+  /// Generation and meshing tasks running at once
+  pub max_in_flight: usize,
+
+  /// Stats, in_flight is updated every frame and the rest since startup
+  pub in_flight: usize,
+  pub completed: usize,
+  pub cancelled: usize,
+  pub collapsed: usize,
+}
+
+impl Default for LoadQueue {
+  fn default() -> Self {
+    Self {
+      keys: HashMap::new(),
+      chunks: HashMap::new(),
+      colliders: HashMap::new(),
+      max_in_flight: 16,
+      in_flight: 0,
+      completed: 0,
+      cancelled: 0,
+      collapsed: 0,
+    }
+  }
+}
+
+impl LoadQueue {
+  pub fn push_key(&mut self, key: [i64; 3], lod: usize) {
+    if self.keys.insert(key, lod).is_some() {
+      self.collapsed += 1;
+    }
+  }
+
+  pub fn push_chunk(&mut self, chunk: Chunk) {
+    if self.chunks.insert(chunk.key, chunk).is_some() {
+      self.collapsed += 1;
+    }
+  }
+
+  /// Drops the jobs that are not wanted anymore
+  pub fn retain<F: Fn(&[i64; 3], usize) -> bool>(&mut self, wanted: F) {
+    let len = self.keys.len() + self.chunks.len();
+    self.keys.retain(|key, lod| wanted(key, *lod));
+    self.chunks.retain(|key, chunk| wanted(key, chunk.lod));
+    self.cancelled += len - self.keys.len() - self.chunks.len();
+  }
+
+  /// Takes up to count keys, lower lods and closer keys first
+  pub fn pop_keys(&mut self, center: &[i64; 3], count: usize) -> Vec<([i64; 3], usize)> {
+    let mut keys: Vec<([i64; 3], usize)> = self.keys
+      .iter()
+      .map(|(key, lod)| (*key, *lod))
+      .collect();
+    keys.sort_by_key(|(key, lod)| priority(center, key, *lod));
+    keys.truncate(count);
+
+    for (key, _) in keys.iter() {
+      self.keys.remove(key);
+    }
+    keys
+  }
+
+  /// Takes up to count chunks, lower lods and closer keys first
+  pub fn pop_chunks(&mut self, center: &[i64; 3], count: usize) -> Vec<Chunk> {
+    let mut keys: Vec<([i64; 3], usize)> = self.chunks
+      .iter()
+      .map(|(key, chunk)| (*key, chunk.lod))
+      .collect();
+    keys.sort_by_key(|(key, lod)| priority(center, key, *lod));
+    keys.truncate(count);
+
+    keys
+      .iter()
+      .filter_map(|(key, _)| self.chunks.remove(key))
+      .collect()
+  }
+
+  pub fn len(&self) -> usize {
+    self.keys.len() + self.chunks.len()
+  }
+}
+
+fn priority(center: &[i64; 3], key: &[i64; 3], lod: usize) -> (usize, i64, [i64; 3]) {
+  let mut dist = 0;
+  for i in 0..3 {
+    let d = key[i] - center[i];
+    dist += d * d;
+  }
+  (lod, dist, *key)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::LoadQueue;
+
+  #[test]
+  fn test_load_queue_priority() -> Result<(), String> {
+    let mut queue = LoadQueue::default();
+    queue.push_key([5, 0, 0], 2);
+    queue.push_key([3, 0, 0], 1);
+    queue.push_key([2, 0, 0], 1);
+    queue.push_key([3, 0, 0], 2);
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.collapsed, 1);
+
+    let keys = queue.pop_keys(&[0, 0, 0], 2);
+    assert_eq!(keys, vec![([2, 0, 0], 1), ([3, 0, 0], 2)]);
+    assert_eq!(queue.len(), 1);
+    Ok(())
+  }
+
+  #[test]
+  fn test_load_queue_cancel() -> Result<(), String> {
+    let mut queue = LoadQueue::default();
+    for x in 0..10 {
+      queue.push_key([x, 0, 0], 1);
+    }
+    queue.retain(|key, _| key[0] < 4);
+    assert_eq!(queue.len(), 4);
+    assert_eq!(queue.cancelled, 6);
+
+    let keys = queue.pop_keys(&[9, 0, 0], 10);
+    assert_eq!(keys[0], ([3, 0, 0], 1));
+    assert_eq!(queue.len(), 0);
+    Ok(())
+  }
+}
@@ -1,5 +1,7 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use rapier3d::{prelude::{RigidBodySet, ColliderSet, PhysicsPipeline, ColliderBuilder, RigidBodyBuilder, Real, Vector, IntegrationParameters, IslandManager, MultibodyJointSet, ImpulseJointSet, NarrowPhase, BroadPhase, CCDSolver, RigidBodyHandle, Collider, ColliderHandle, InteractionGroups, QueryPipeline, Group, Point, Isometry}, na::Vector3};
+use voxels::data::{voxel_octree::MeshData, materials::MaterialRegistry};
+use crate::util::merge_meshes;
 
 pub struct Physics {
   pub pipeline: PhysicsPipeline,
@@ -14,6 +16,118 @@ pub struct Physics {
   pub impulse_joint_set: ImpulseJointSet,
   pub multibody_joint_set: MultibodyJointSet,
   pub ccd_solver: CCDSolver,
+  /// Liquid sensor of a chunk collider, removed along with it
+  pub sensors: HashMap<ColliderHandle, ColliderHandle>,
+}
+
+/// Colliders of a chunk mesh, built without the Physics so the trimesh can
+/// be computed off the main thread
+#[derive(Clone, Default)]
+pub struct ChunkColliders {
+  pub solid: Option<Collider>,
+  /// Liquid sensor, removed along with the solid collider
+  pub sensor: Option<Collider>,
+}
+
+impl std::fmt::Debug for ChunkColliders {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("ChunkColliders")
+      .field("solid", &self.solid.is_some())
+      .field("sensor", &self.sensor.is_some())
+      .finish()
+  }
+}
+
+/**
+  Transparent solids block like the opaque mesh, liquids only sense. pos is
+  the world position of the chunk
+*/
+pub fn chunk_colliders(pos: Vec3, data: &MeshData, materials: &MaterialRegistry) -> ChunkColliders {
+  let is_liquid = |m: &&MeshData| {
+    m.types.first().map(|t| materials.is_liquid(t[0] as u8)).unwrap_or(false)
+  };
+  let (positions, indices) = merge_meshes(
+    std::iter::once(data).chain(data.transparent.iter().filter(|m| !is_liquid(m)))
+  );
+  let (liquid_positions, liquid_indices) = merge_meshes(
+    data.transparent.iter().filter(is_liquid)
+  );
+  let surface = materials.surface_properties(data);
+
+  let pos = [pos.x, pos.y, pos.z];
+  let mut colliders = ChunkColliders::default();
+  if !indices.is_empty() {
+    colliders.solid = Some(trimesh_collider(pos, &positions, &indices, surface));
+  }
+  if !liquid_indices.is_empty() {
+    colliders.sensor = Some(trimesh_sensor(pos, &liquid_positions, &liquid_indices));
+  }
+  colliders
+}
+
+/// Static trimesh collider, surface is [friction, restitution]
+fn trimesh_collider(
+  pos: [f32; 3],
+  mesh_pos: &[[f32; 3]],
+  mesh_indices: &[u32],
+  surface: [f32; 2],
+) -> Collider {
+  let m_pos = mesh_pos.iter().map(|d| Point::from([d[0], d[1], d[2]])).collect();
+  let indices = mesh_indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect();
+
+  let mut collider = ColliderBuilder::trimesh(m_pos, indices)
+    .collision_groups(InteractionGroups::new(Group::GROUP_1, Group::GROUP_2))
+    .friction(surface[0])
+    .restitution(surface[1])
+    .build();
+  collider.set_position(Isometry::from(pos));
+  collider
+}
+
+/// Static trimesh sensor for the liquids, reports intersections instead of
+/// blocking
+fn trimesh_sensor(
+  pos: [f32; 3],
+  mesh_pos: &[[f32; 3]],
+  mesh_indices: &[u32],
+) -> Collider {
+  let m_pos = mesh_pos.iter().map(|d| Point::from([d[0], d[1], d[2]])).collect();
+  let indices = mesh_indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect();
+
+  let mut collider = ColliderBuilder::trimesh(m_pos, indices)
+    .collision_groups(InteractionGroups::new(Group::GROUP_1, Group::GROUP_2))
+    .sensor(true)
+    .build();
+  collider.set_position(Isometry::from(pos));
+  collider
+}
+
+/**
+  Debris collider, positions are local and surface is [friction, restitution].
+  The convex decomposition is slow for big meshes, without decompose the
+  convex hull is used instead. Built without the Physics so it can be computed
+  off the main thread
+*/
+pub fn debris_collider(
+  mesh_pos: &[[f32; 3]],
+  mesh_indices: &[u32],
+  surface: [f32; 2],
+  decompose: bool,
+) -> Collider {
+  let m_pos: Vec<Point<Real>> = mesh_pos.iter().map(|d| Point::from([d[0], d[1], d[2]])).collect();
+  let indices: Vec<[u32; 3]> = mesh_indices.chunks(3).map(|i| [i[0], i[1], i[2]]).collect();
+
+  let mut builder = None;
+  if !decompose {
+    builder = ColliderBuilder::convex_hull(&m_pos);
+  }
+  // A flat mesh has no hull
+  builder
+    .unwrap_or_else(|| ColliderBuilder::convex_decomposition(&m_pos, &indices))
+    .collision_groups(InteractionGroups::new(Group::GROUP_2, Group::GROUP_1 | Group::GROUP_2))
+    .friction(surface[0])
+    .restitution(surface[1])
+    .build()
 }
 
 impl Default for Physics {
@@ -34,6 +148,7 @@ impl Default for Physics {
       impulse_joint_set: ImpulseJointSet::new(),
       multibody_joint_set: MultibodyJointSet::new(),
       ccd_solver: CCDSolver::new(),
+      sensors: HashMap::new(),
     }
   }
 }
@@ -91,31 +206,49 @@ impl Physics {
 
   #[allow(dead_code)]
   pub fn remove_collider(&mut self, handle: ColliderHandle) {
+    if let Some(sensor) = self.sensors.remove(&handle) {
+      self
+        .collider_set
+        .remove(sensor, &mut self.island_manager, &mut self.rigid_body_set, true);
+    }
     self
       .collider_set
       .remove(handle, &mut self.island_manager, &mut self.rigid_body_set, true);
   }
 
-  pub fn add_collider(
-    &mut self,
-    pos: [f32; 3],
-    mesh_pos: &Vec<[f32; 3]>, 
-    mesh_indices: &Vec<u32>,
-  ) -> ColliderHandle {
-    let mut m_pos = Vec::new();
-    for d in mesh_pos.iter() {
-      m_pos.push(Point::from([d[0], d[1], d[2]]));
-    }
-    let mut indices = Vec::new();
-    for i in mesh_indices.chunks(3) {
-      indices.push([i[0], i[1], i[2]]);
-    }
-
-    let mut collider = ColliderBuilder::trimesh(m_pos, indices)
-      .collision_groups(InteractionGroups::new(Group::GROUP_1, Group::GROUP_2))
+  /// Dynamic body with the collider of debris_collider at pos
+  pub fn add_debris(&mut self, pos: [f32; 3], collider: Collider) -> (RigidBodyHandle, ColliderHandle) {
+    let rigid_body = RigidBodyBuilder::dynamic()
+      .translation(Vector3::from(pos))
       .build();
-    collider.set_position(Isometry::from(pos));
+    let body_handle = self.rigid_body_set.insert(rigid_body);
+    let collider_handle = self.insert_with_parent(collider, body_handle.clone());
+    (body_handle, collider_handle)
+  }
 
-    self.collider_set.insert(collider)
+  /// Removes the body with its colliders
+  pub fn remove_rigid_body(&mut self, handle: RigidBodyHandle) {
+    self.rigid_body_set.remove(
+      handle,
+      &mut self.island_manager,
+      &mut self.collider_set,
+      &mut self.impulse_joint_set,
+      &mut self.multibody_joint_set,
+      true
+    );
+  }
+
+  /// Inserts the chunk colliders, the handle is the solid one when there is
+  /// one, otherwise the sensor
+  pub fn insert_chunk_colliders(&mut self, colliders: ChunkColliders) -> ColliderHandle {
+    let sensor = colliders.sensor.map(|c| self.collider_set.insert(c));
+    let solid = match colliders.solid {
+      Some(c) => self.collider_set.insert(c),
+      None => return sensor.unwrap_or(ColliderHandle::invalid()),
+    };
+    if let Some(sensor) = sensor {
+      self.sensors.insert(solid, sensor);
+    }
+    solid
   }
 }
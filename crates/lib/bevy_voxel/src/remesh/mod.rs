@@ -0,0 +1,284 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use rapier3d::prelude::ColliderHandle;
+use voxels::{chunk::chunk_manager::Chunk, data::voxel_octree::MeshData};
+use crate::{BevyVoxelResource, MeshComponent, lod::LodManager, physics::ChunkColliders};
+
+use cfg_if::cfg_if;
+
+cfg_if! {
+  if #[cfg(target_arch = "wasm32")] {
+    use multithread::plugin::{PluginResource, send_remesh};
+  } else {
+    use bevy::tasks::{AsyncComputeTaskPool, Task};
+    use futures_lite::future;
+    use voxels::data::{voxel_octree::VoxelMode, surface_nets::VoxelReuse, lod_transition::add_transition_skirts};
+    use crate::physics::chunk_colliders;
+  }
+}
+
+pub struct CustomPlugin;
+impl Plugin for CustomPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(RemeshQueue::default())
+      .add_systems(Update, (spawn_remesh, recv_remesh, apply_remesh).chain());
+  }
+}
+
+/**
+  Edited chunks waiting to be remeshed off the main thread. Only one batch is
+  meshed at a time, edits made meanwhile are coalesced into the next batch and
+  a batch is applied in a single frame
+*/
+#[derive(Resource, Debug, Clone, Default)]
+pub struct RemeshQueue {
+  /// Chunks for the next batch, a later edit replaces the chunk
+  pub dirty: HashMap<[i64; 3], Chunk>,
+  /// Keys of the batch being meshed
+  pub in_flight: HashSet<[i64; 3]>,
+  /// Meshes of the batch that are done
+  pub done: Vec<MeshData>,
+  /// Colliders built along with the lod 0 meshes of the batch
+  pub colliders: HashMap<[i64; 3], ChunkColliders>,
+
+  /// Counts since startup, for debugging
+  pub batches: usize,
+  pub coalesced: usize,
+}
+
+impl RemeshQueue {
+  pub fn mark_dirty(&mut self, chunks: &HashMap<[i64; 3], Chunk>) {
+    for (key, chunk) in chunks.iter() {
+      if self.dirty.insert(*key, chunk.clone()).is_some() {
+        self.coalesced += 1;
+      }
+    }
+  }
+
+  /// Dirty chunks once the previous batch was applied
+  pub fn next_batch(&mut self) -> Vec<Chunk> {
+    if !self.in_flight.is_empty() || !self.done.is_empty() || self.dirty.is_empty() {
+      return Vec::new();
+    }
+
+    let mut chunks: Vec<Chunk> = self.dirty.drain().map(|(_, c)| c).collect();
+    chunks.sort_by_key(|c| c.key);
+    for chunk in chunks.iter() {
+      self.in_flight.insert(chunk.key);
+    }
+    self.batches += 1;
+    chunks
+  }
+
+  /// Keeps the mesh until the rest of the batch is done, false when the key
+  /// isn't part of the batch
+  pub fn finish(&mut self, data: MeshData) -> bool {
+    if !self.in_flight.remove(&data.key) {
+      return false;
+    }
+    self.done.push(data);
+    true
+  }
+
+  /// Meshes of the batch once all of them are done
+  pub fn take_done(&mut self) -> Vec<MeshData> {
+    if !self.in_flight.is_empty() {
+      return Vec::new();
+    }
+    std::mem::take(&mut self.done)
+  }
+}
+
+fn spawn_remesh(
+  mut commands: Commands,
+  bevy_voxel_res: Res<BevyVoxelResource>,
+  lod_manager: Res<LodManager>,
+  mut queue: ResMut<RemeshQueue>,
+) {
+  for mut chunk in queue.next_batch() {
+    // Meshed at the lod the LodManager shows the key at
+    if let Some(lod) = lod_manager.target_lod(&chunk.key) {
+      chunk.lod = lod;
+    }
+    if !chunk.mode.has_surface() {
+      queue.finish(MeshData { key: chunk.key, lod: chunk.lod, ..Default::default() });
+      continue;
+    }
+    mesh_chunk(&mut commands, &bevy_voxel_res, chunk);
+  }
+}
+
+cfg_if! {
+  if #[cfg(target_arch = "wasm32")] {
+    /// Meshed by the workers apart from the loaded chunks
+    fn mesh_chunk(_commands: &mut Commands, _res: &BevyVoxelResource, chunk: Chunk) {
+      send_remesh(chunk);
+    }
+
+    /// Meshes for keys outside of the batch are dropped by finish
+    fn recv_remesh(
+      plugin_res: Res<PluginResource>,
+      mut queue: ResMut<RemeshQueue>,
+    ) {
+      for data in plugin_res.recv_remesh.drain() {
+        queue.finish(data);
+      }
+    }
+  } else {
+    /// The lod 0 border normals and colliders and the skirts are built in the
+    /// task too
+    fn mesh_chunk(commands: &mut Commands, res: &BevyVoxelResource, chunk: Chunk) {
+      let depth = res.chunk_manager.depth;
+      let size = res.chunk_manager.chunk_size;
+      let scale = res.chunk_manager.voxel_scale;
+      let materials = res.chunk_manager.materials.clone();
+      let pos = res.get_pos(chunk.key);
+      let neighbor_lods = res.neighbor_lods(&res.lod_center, &chunk.key, chunk.lod);
+      // The neighbors are sampled at full resolution
+      let apron = (chunk.lod == 0).then(|| res.chunk_manager.apron(&chunk.key));
+      let task = AsyncComputeTaskPool::get().spawn(async move {
+        let mut data = chunk.octree.compute_mesh_passes(
+          VoxelMode::SurfaceNets,
+          &mut VoxelReuse::new(depth, 3),
+          &materials,
+          scale,
+          chunk.key,
+          chunk.lod
+        );
+        if let Some(apron) = apron {
+          apron.update_border_ao(&chunk.octree, &mut data);
+          apron.update_border_normals(&chunk.octree, &mut data);
+        }
+        let mut colliders = None;
+        if data.lod == 0 && !data.is_empty() {
+          colliders = Some(chunk_colliders(pos, &data, &materials));
+        }
+        // Skirts are only for rendering
+        add_transition_skirts(&mut data, size, scale, &neighbor_lods);
+        (data, colliders)
+      });
+      commands.spawn(RemeshTask(task));
+    }
+
+    fn recv_remesh(
+      mut commands: Commands,
+      mut queue: ResMut<RemeshQueue>,
+      mut tasks: Query<(Entity, &mut RemeshTask)>,
+    ) {
+      for (entity, mut task) in &mut tasks {
+        if let Some((data, colliders)) = future::block_on(future::poll_once(&mut task.0)) {
+          if let Some(colliders) = colliders {
+            queue.colliders.insert(data.key, colliders);
+          }
+          queue.finish(data);
+          commands.entity(entity).despawn();
+        }
+      }
+    }
+
+    #[derive(Component)]
+    struct RemeshTask(Task<(MeshData, Option<ChunkColliders>)>);
+  }
+}
+
+/// Applies a finished batch to every MeshComponent in the same frame
+fn apply_remesh(
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  mut lod_manager: ResMut<LodManager>,
+  mut queue: ResMut<RemeshQueue>,
+  mut mesh_comps: Query<&mut MeshComponent>,
+) {
+  for mut data in queue.take_done() {
+    let colliders = queue.colliders.remove(&data.key);
+    // Dropped once unloaded, meshed again when the lod changed meanwhile
+    match lod_manager.target_lod(&data.key) {
+      // Newer than a load still pending at the lod, which is dropped
+      Some(lod) if lod == data.lod => lod_manager.set_loaded(data.key, lod),
+      Some(_) => {
+        if let Some(chunk) = bevy_voxel_res.chunk_manager.get_chunk(&data.key) {
+          queue.dirty.entry(data.key).or_insert_with(|| chunk.clone());
+        }
+        continue;
+      }
+      None => continue,
+    }
+
+    cfg_if! {
+      if #[cfg(target_arch = "wasm32")] {
+        // The workers don't have the neighbors
+        if data.lod == 0 {
+          if let Some(chunk) = bevy_voxel_res.chunk_manager.get_chunk(&data.key) {
+            bevy_voxel_res.chunk_manager.update_border_ao(&chunk.octree, &mut data);
+            bevy_voxel_res.chunk_manager.update_border_normals(&chunk.octree, &mut data);
+          }
+        }
+      }
+    }
+    bevy_voxel_res.chunk_manager.apply_light(&mut data);
+
+    let mut handle = ColliderHandle::invalid();
+    if let Some(colliders) = colliders {
+      handle = bevy_voxel_res.physics.insert_chunk_colliders(colliders);
+    }
+    cfg_if! {
+      if #[cfg(target_arch = "wasm32")] {
+        // The workers only return the mesh
+        if data.lod == 0 && !data.is_empty() {
+          let pos = bevy_voxel_res.get_pos(data.key);
+          handle = bevy_voxel_res.add_collider(pos, &data);
+        }
+        bevy_voxel_res.apply_lod_transitions(&mut data);
+      }
+    }
+
+    for mut mesh_comp in &mut mesh_comps {
+      if !data.is_empty() {
+        mesh_comp.data.insert(data.key, data.clone());
+        mesh_comp.added.push((data.clone(), handle));
+      } else {
+        mesh_comp.data.remove(&data.key);
+        mesh_comp.removed.push(data.key);
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use bevy::utils::HashMap;
+  use voxels::{chunk::chunk_manager::Chunk, data::voxel_octree::MeshData};
+  use super::RemeshQueue;
+
+  fn chunk(key: [i64; 3]) -> Chunk {
+    Chunk { key: key, ..Default::default() }
+  }
+
+  #[test]
+  fn test_remesh_batches() -> Result<(), String> {
+    let mut queue = RemeshQueue::default();
+    let mut edit = HashMap::new();
+    edit.insert([0, 0, 0], chunk([0, 0, 0]));
+    edit.insert([1, 0, 0], chunk([1, 0, 0]));
+    queue.mark_dirty(&edit);
+    queue.mark_dirty(&edit);
+    assert_eq!(queue.coalesced, 2);
+
+    let batch = queue.next_batch();
+    assert_eq!(batch.len(), 2);
+
+    // Edited again while meshing, waits for the next batch
+    queue.mark_dirty(&edit);
+    assert!(queue.next_batch().is_empty());
+
+    assert!(queue.finish(MeshData { key: [0, 0, 0], ..Default::default() }));
+    assert!(!queue.finish(MeshData { key: [5, 0, 0], ..Default::default() }));
+    assert!(queue.take_done().is_empty());
+
+    assert!(queue.finish(MeshData { key: [1, 0, 0], ..Default::default() }));
+    assert_eq!(queue.take_done().len(), 2);
+    assert_eq!(queue.next_batch().len(), 2);
+    assert_eq!(queue.batches, 2);
+    Ok(())
+  }
+}
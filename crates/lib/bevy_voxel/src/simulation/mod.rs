@@ -0,0 +1,68 @@
+use bevy::prelude::*;
+use voxels::chunk::chunk_manager::ChunkManager;
+use crate::{BevyVoxelResource, Chunks, remesh::RemeshQueue};
+
+/// Fixed rate ticks of a voxel simulation
+#[derive(Clone)]
+pub struct TickSettings {
+  pub enabled: bool,
+  /// Seconds per simulation tick, independent of the frame rate
+  pub tick_secs: f32,
+  /// Ticks caught up in a single frame, the rest of a long frame is dropped
+  pub max_ticks_per_frame: u32,
+  /// Seconds not simulated yet
+  pub elapsed: f32,
+}
+
+impl TickSettings {
+  pub fn new(tick_secs: f32) -> Self {
+    Self {
+      enabled: true,
+      tick_secs: tick_secs,
+      max_ticks_per_frame: 4,
+      elapsed: 0.0,
+    }
+  }
+}
+
+/// Settings resource of a simulation ticked by tick_simulation
+pub trait Simulation: Resource {
+  fn ticks(&mut self) -> &mut TickSettings;
+  /// One tick, returns the changed voxels
+  fn tick(chunk_manager: &mut ChunkManager) -> Vec<[i64; 3]>;
+}
+
+/// Runs the ticks due this frame and remeshes the chunks they changed
+pub fn tick_simulation<S: Simulation>(
+  time: Res<Time>,
+  mut settings: ResMut<S>,
+  mut bevy_voxel_res: ResMut<BevyVoxelResource>,
+  mut remesh: ResMut<RemeshQueue>,
+  mut chunks: Query<&mut Chunks>,
+) {
+  let ticks = settings.ticks();
+  if !ticks.enabled {
+    return;
+  }
+  ticks.elapsed += time.delta_seconds();
+
+  let mut changed = Vec::new();
+  let mut count = 0;
+  while ticks.elapsed >= ticks.tick_secs && count < ticks.max_ticks_per_frame {
+    ticks.elapsed -= ticks.tick_secs;
+    changed.extend(S::tick(&mut bevy_voxel_res.chunk_manager));
+    count += 1;
+  }
+  ticks.elapsed = ticks.elapsed.min(ticks.tick_secs);
+  if changed.is_empty() {
+    return;
+  }
+
+  let res = bevy_voxel_res.simulated_chunks(changed);
+  for mut chunks in &mut chunks {
+    for (key, chunk) in res.iter() {
+      chunks.data.insert(*key, chunk.clone());
+    }
+  }
+  remesh.mark_dirty(&res);
+}
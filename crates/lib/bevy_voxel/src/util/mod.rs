@@ -1,5 +1,5 @@
 use bevy::prelude::*;
-use voxels::chunk::chunk_manager::{ChunkManager, Chunk};
+use voxels::{chunk::chunk_manager::{ChunkManager, Chunk}, data::voxel_octree::MeshData};
 use crate::BevyVoxelResource;
 
 pub fn set_voxel_default(
@@ -119,6 +119,20 @@ pub fn get_sphere_coords(size: f32) -> Vec<[i64; 3]> {
   coords
 }
 
+/// Positions and indices of the meshes as a single mesh
+pub fn merge_meshes<'a>(
+  meshes: impl Iterator<Item = &'a MeshData>
+) -> (Vec<[f32; 3]>, Vec<u32>) {
+  let mut positions = Vec::new();
+  let mut indices = Vec::new();
+  for mesh in meshes {
+    let offset = positions.len() as u32;
+    positions.extend_from_slice(&mesh.positions);
+    indices.extend(mesh.indices.iter().map(|i| i + offset));
+  }
+  (positions, indices)
+}
+
 
 
 
@@ -127,7 +141,7 @@ mod tests {
   use bevy::prelude::Vec3;
   use voxels::chunk::chunk_manager::ChunkManager;
   use crate::util::get_key;
-  use super::{get_near_positions, get_sphere_coords, get_keys_by_lod};
+  use super::{get_near_positions, get_sphere_coords};
 
   #[test]
   fn test_near_positions_1_0() -> Result<(), String> {
@@ -27,13 +27,14 @@ fn recv_keys(
   } 
 }
 
+/// Generated chunks go through lod::receive_chunks before they are meshed
 fn recv_chunk(
   plugin_res: Res<PluginResource>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
 ) {
   for chunk in plugin_res.recv_chunk.drain() {
-    send_chunk(chunk);
+    let _ = bevy_voxel_res.send_chunk.send(chunk);
   }
-  
 }
 
 fn recv_process_mesh(
@@ -48,9 +49,11 @@ fn recv_process_mesh(
 
 fn load_mesh(
   plugin_res: Res<PluginResource>,
-  bevy_voxel_res: ResMut<BevyVoxelResource>,
+  bevy_voxel_res: Res<BevyVoxelResource>,
 ) {
-  for data in plugin_res.recv_mesh.drain() {
+  for mut data in plugin_res.recv_mesh.drain() {
+    // The worker only gets the chunk, the skirts are added here
+    bevy_voxel_res.apply_lod_transitions(&mut data);
     let _ = bevy_voxel_res.send_mesh.send(data);
   }
 }
\ No newline at end of file
@@ -6,13 +6,14 @@ use wasm_mt_pool::prelude::*;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use wasm_mt::utils::{console_ln, fetch_as_arraybuffer};
-use voxels::{chunk::chunk_manager::*, data::{voxel_octree::{MeshData, VoxelMode}, surface_nets::VoxelReuse}};
+use voxels::{chunk::chunk_manager::*, data::{voxel_octree::{MeshData, VoxelMode}, surface_nets::VoxelReuse, materials::MaterialRegistry}};
 use flume::{Sender, Receiver};
 use web_sys::{CustomEvent, HtmlInputElement, CustomEventInit};
 
 
 use std::sync::RwLock;
-static COLORS: RwLock<Vec<[f32; 3]>> = RwLock::new(Vec::new());
+/// Sent by the app, None until then and the default palette is used
+static MATERIALS: RwLock<Option<MaterialRegistry>> = RwLock::new(None);
 
 pub mod plugin;
 
@@ -23,14 +24,11 @@ pub fn app() {
   // recv_key_from_wasm(send_queue);
   // recv_chunk_from_wasm(send_chunk);
 
-  for _ in 0..500 {
-    COLORS.write().unwrap().push([0.0, 0.0, 0.0]);
-  }
-
   let (send, recv) = flume::unbounded();
   recv_data_key_from_wasm(send.clone());
-  recv_data_chunk_from_wasm(send.clone());
-  recv_colors_from_wasm();
+  recv_data_chunk_from_wasm(send.clone(), EventType::ChunkSend);
+  recv_data_chunk_from_wasm(send.clone(), EventType::RemeshSend);
+  recv_materials_from_wasm();
 
   spawn_local(async move {
     let ab_js = fetch_as_arraybuffer("./wasm/multithread/multithread.js").await.unwrap();
@@ -76,7 +74,12 @@ fn recv_data_key_from_wasm(send: Sender<WasmMessage>) {
   callback.forget();
 }
 
-fn recv_data_chunk_from_wasm(send: Sender<WasmMessage>) {
+fn recv_data_chunk_from_wasm(send: Sender<WasmMessage>, event_type: EventType) {
+  // The mesh is sent back with the matching event
+  let recv_type = match event_type {
+    EventType::RemeshSend => EventType::RemeshRecv,
+    _ => EventType::ChunkRecv,
+  };
   let callback = Closure::wrap(Box::new(move |event: CustomEvent | {
     let data = event.detail().as_string().unwrap();
     let bytes = array_bytes::hex2bytes(data).unwrap();
@@ -84,7 +87,7 @@ fn recv_data_chunk_from_wasm(send: Sender<WasmMessage>) {
 
     // console_ln!("from wasm chunk {:?}", chunk.key);
     let msg = WasmMessage {
-      chunk: Some(chunk),
+      chunk: Some((chunk, recv_type.clone())),
       ..Default::default()
     };
 
@@ -93,26 +96,25 @@ fn recv_data_chunk_from_wasm(send: Sender<WasmMessage>) {
 
   let window = web_sys::window().unwrap();
   let _ = window.add_event_listener_with_callback(
-    &EventType::ChunkSend.to_string(),
+    &event_type.to_string(),
     callback.as_ref().unchecked_ref()
   );
 
   callback.forget();
 }
 
-fn recv_colors_from_wasm() {
+fn recv_materials_from_wasm() {
   let callback = Closure::wrap(Box::new(move |event: CustomEvent | {
     let data = event.detail().as_string().unwrap();
     let bytes = array_bytes::hex2bytes(data).unwrap();
-    let colors: Vec<[f32; 3]> = bincode::deserialize(&bytes).unwrap();
+    let materials: MaterialRegistry = bincode::deserialize(&bytes).unwrap();
 
-    COLORS.write().unwrap().clear();
-    COLORS.write().unwrap().append(&mut colors.clone());
+    *MATERIALS.write().unwrap() = Some(materials);
   }) as Box<dyn FnMut(CustomEvent)>);
 
   let window = web_sys::window().unwrap();
   let _ = window.add_event_listener_with_callback(
-    &EventType::SendColors.to_string(),
+    &EventType::SendMaterials.to_string(),
     callback.as_ref().unchecked_ref()
   );
 
@@ -124,8 +126,6 @@ async fn load_data_from_wasm(
   recv: Receiver<WasmMessage>
 ) {
 
-  
-  console_ln!("COLORS.len() 1 {}", COLORS.read().unwrap().len());
 
   while let Ok(msg) = recv.recv_async().await {
     // console_ln!("load_data_from_wasm {:?}", );
@@ -156,10 +156,14 @@ async fn load_data_from_wasm(
     }
 
     if msg.chunk.is_some() {
-      let chunk = msg.chunk.unwrap();
+      let (chunk, recv_type) = msg.chunk.unwrap();
       let c = chunk.clone();
 
-      let colors = COLORS.read().unwrap().clone();
+      let materials = MATERIALS
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| MaterialRegistry::from_colors(&DEFAULT_COLOR_PALETTE));
       // console_ln!("load_chunk {:?}", chunk.clone().key);
 
       let cb = move |result: Result<JsValue, JsValue>| {
@@ -174,7 +178,7 @@ async fn load_data_from_wasm(
   
         
         let e = CustomEvent::new_with_event_init_dict(
-          &EventType::ChunkRecv.to_string(), CustomEventInit::new().detail(&JsValue::from_str(&str))
+          &recv_type.to_string(), CustomEventInit::new().detail(&JsValue::from_str(&str))
         ).unwrap();
   
         let window = web_sys::window().unwrap();
@@ -182,7 +186,7 @@ async fn load_data_from_wasm(
       };
   
       pool_exec!(pool, move || {
-        let mesh = compute_mesh(chunk, &colors);
+        let mesh = compute_mesh(chunk, &materials);
 
         let r = bincode::serialize(&mesh);
         if r.is_err() {
@@ -203,28 +207,13 @@ fn compute_chunk(key: Key) -> Chunk {
   ChunkManager::new_chunk(&key.key, 4, key.lod, manager.noise)
 }
 
-fn compute_mesh(chunk: Chunk, colors: &Vec<[f32; 3]>) -> MeshData {
-  // let mut v = Vec::new();
-  // for _ in 0..500 {
-  //   v.push([0.0, 0.0, 0.0]);
-  // }
-
-  // console_ln!("COLORS.len() {}", COLORS.read().unwrap().len());
-  // if COLORS.read().unwrap().len() == 0 {
-  //   console_ln!("Testing");
-  //   // for _ in 0..500 {
-  //   //   COLORS.write().unwrap().push([0.0, 0.0, 0.0]);
-  //   // }  
-  // }
-
-  // console_ln!("colors.len() {}", colors.len());
-
-  chunk.octree.compute_mesh(
+/// Every pass, the transparent and liquid meshes come back in
+/// MeshData.transparent
+fn compute_mesh(chunk: Chunk, materials: &MaterialRegistry) -> MeshData {
+  chunk.octree.compute_mesh_passes(
     VoxelMode::SurfaceNets, 
     &mut VoxelReuse::default(), 
-    colors,
-    // &v, 
-    // &COLORS.read().unwrap(),
+    materials,
     1.0, 
     chunk.key,
     chunk.lod
@@ -237,7 +226,9 @@ pub enum EventType {
   KeyRecv,
   ChunkSend,
   ChunkRecv,
-  SendColors,
+  RemeshSend,
+  RemeshRecv,
+  SendMaterials,
 }
 
 impl ToString for EventType {
@@ -247,7 +238,9 @@ impl ToString for EventType {
       EventType::KeyRecv => String::from("KeyRecv"),
       EventType::ChunkSend => String::from("ChunkSend"),
       EventType::ChunkRecv => String::from("ChunkRecv"),
-      EventType::SendColors => String::from("SendColors"),
+      EventType::RemeshSend => String::from("RemeshSend"),
+      EventType::RemeshRecv => String::from("RemeshRecv"),
+      EventType::SendMaterials => String::from("SendMaterials"),
     }
   }
 }
@@ -305,5 +298,6 @@ struct Res {
 #[derive(Default)]
 struct WasmMessage {
   key: Option<Key>,
-  chunk: Option<Chunk>,
+  /// With the event the mesh is sent back with
+  chunk: Option<(Chunk, EventType)>,
 }
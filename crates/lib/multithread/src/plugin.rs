@@ -3,6 +3,7 @@ use flume;
 use flume::{Sender, Receiver};
 use voxels::chunk::chunk_manager::Chunk;
 use voxels::data::voxel_octree::MeshData;
+use voxels::data::materials::MaterialRegistry;
 use web_sys::{CustomEvent, CustomEventInit};
 use wasm_bindgen::prelude::*;
 
@@ -19,7 +20,8 @@ fn init(
   local_res: ResMut<PluginResource>,
 ) {
   receive_chunk(local_res.send_chunk.clone());
-  receive_mesh(local_res.send_mesh.clone());
+  receive_mesh(local_res.send_mesh.clone(), EventType::ChunkRecv);
+  receive_mesh(local_res.send_remesh.clone(), EventType::RemeshRecv);
 }
 
 
@@ -41,7 +43,8 @@ pub fn receive_chunk(send: Sender<Chunk>) {
   callback.forget();
 }
 
-pub fn receive_mesh(send: Sender<MeshData>) {
+/// Remeshed edits come back as RemeshRecv, apart from the loaded chunks
+pub fn receive_mesh(send: Sender<MeshData>, event_type: EventType) {
   let callback = Closure::wrap(Box::new(move |event: CustomEvent | {
     // info!("receive_mesh()");
 
@@ -53,7 +56,7 @@ pub fn receive_mesh(send: Sender<MeshData>) {
 
   let window = web_sys::window().unwrap();
   let _ = window.add_event_listener_with_callback(
-    &EventType::ChunkRecv.to_string(),
+    &event_type.to_string(),
     callback.as_ref().unchecked_ref()
   );
 
@@ -81,23 +84,33 @@ pub fn send_key(key: Key) {
 }
 
 pub fn send_chunk(chunk: Chunk) {
+  dispatch_chunk(chunk, EventType::ChunkSend);
+}
+
+/// Meshed like send_chunk, the mesh is received in PluginResource.recv_remesh
+pub fn send_remesh(chunk: Chunk) {
+  dispatch_chunk(chunk, EventType::RemeshSend);
+}
+
+fn dispatch_chunk(chunk: Chunk, event_type: EventType) {
   let encoded: Vec<u8> = bincode::serialize(&chunk).unwrap();
   let str = array_bytes::bytes2hex("", &encoded);
 
   let e = CustomEvent::new_with_event_init_dict(
-    &EventType::ChunkSend.to_string(), CustomEventInit::new().detail(&JsValue::from_str(&str))
+    &event_type.to_string(), CustomEventInit::new().detail(&JsValue::from_str(&str))
   ).unwrap();
 
   let window = web_sys::window().unwrap();
   let _ = window.dispatch_event(&e);
 }
 
-pub fn send_colors(colors: &Vec<[f32; 3]>) {
-  let encoded: Vec<u8> = bincode::serialize(colors).unwrap();
+/// The workers mesh the transparent and liquid passes with these
+pub fn send_materials(materials: &MaterialRegistry) {
+  let encoded: Vec<u8> = bincode::serialize(materials).unwrap();
   let str = array_bytes::bytes2hex("", &encoded);
 
   let e = CustomEvent::new_with_event_init_dict(
-    &EventType::SendColors.to_string(), CustomEventInit::new().detail(&JsValue::from_str(&str))
+    &EventType::SendMaterials.to_string(), CustomEventInit::new().detail(&JsValue::from_str(&str))
   ).unwrap();
 
   let window = web_sys::window().unwrap();
@@ -113,12 +126,16 @@ pub struct PluginResource {
 
   send_mesh: Sender<MeshData>,
   pub recv_mesh: Receiver<MeshData>,
+
+  send_remesh: Sender<MeshData>,
+  pub recv_remesh: Receiver<MeshData>,
 }
 
 impl Default for PluginResource {
   fn default() -> Self {
     let (send_chunk, recv_chunk) = flume::unbounded();
     let (send_mesh, recv_mesh) = flume::unbounded();
+    let (send_remesh, recv_remesh) = flume::unbounded();
     Self {
       // timer: Timer::from_seconds(100.0, TimerMode::Repeating),
 
@@ -126,6 +143,8 @@ impl Default for PluginResource {
       recv_chunk: recv_chunk,
       send_mesh: send_mesh,
       recv_mesh: recv_mesh,
+      send_remesh: send_remesh,
+      recv_remesh: recv_remesh,
     }
   }
 }
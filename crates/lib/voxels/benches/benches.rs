@@ -1,4 +1,4 @@
-use voxels::{data::{voxel_octree::{VoxelOctree, VoxelMode, ParentValueType}, surface_nets::{VoxelReuse, GridPosition}}, utils::get_length};
+use voxels::{chunk::chunk_manager::DEFAULT_COLOR_PALETTE, data::{voxel_octree::{VoxelOctree, VoxelMode, ParentValueType}, surface_nets::VoxelReuse}, utils::grid_hashmap::GridHashMap};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 pub fn bench_get_surface_nets(c: &mut Criterion) {
@@ -24,10 +24,13 @@ pub fn bench_get_surface_nets(c: &mut Criterion) {
   );
 
   let mut voxel_reuse = VoxelReuse::new(depth as u32, 3);
+  let colors = DEFAULT_COLOR_PALETTE.to_vec();
 
   c.bench_function("get_surface_nets", |b| {
     b.iter(|| {
-      octree.compute_mesh2(VoxelMode::SurfaceNets, &mut voxel_reuse);
+      octree.compute_mesh(
+        VoxelMode::SurfaceNets, &mut voxel_reuse, &colors, 1.0, [0, 0, 0], 0
+      );
     })
   });
 }
@@ -63,9 +66,52 @@ pub fn bench_octree_get_voxel(c: &mut Criterion) {
   });
 }
 
+/// Chunk keys of a 128 x 16 x 128 world, about 260k keys
+fn grid_keys() -> Vec<[i64; 3]> {
+  let mut keys = Vec::new();
+  for x in -64..64 {
+    for y in -8..8 {
+      for z in -64..64 {
+        keys.push([x, y, z]);
+      }
+    }
+  }
+  keys
+}
+
+pub fn bench_grid_hashmap(c: &mut Criterion) {
+  let keys = grid_keys();
+  let map: GridHashMap<usize, [i64; 3]> = keys.iter().map(|k| (*k, 0)).collect();
+
+  c.bench_function("grid_hashmap_insert", |b| {
+    b.iter(|| {
+      let mut map = GridHashMap::<usize, [i64; 3]>::default();
+      for key in keys.iter() {
+        map.insert(*key, 0);
+      }
+      black_box(map.len())
+    })
+  });
+
+  c.bench_function("grid_hashmap_get", |b| {
+    b.iter(|| {
+      for key in keys.iter() {
+        black_box(map.get(key));
+      }
+    })
+  });
+
+  c.bench_function("grid_hashmap_range", |b| {
+    b.iter(|| {
+      black_box(map.range([-8, -2, -8], [8, 2, 8]).count())
+    })
+  });
+}
+
 criterion_group!(
   benches,
   bench_get_surface_nets,
-  bench_octree_get_voxel
+  bench_octree_get_voxel,
+  bench_grid_hashmap
 );
 criterion_main!(benches);
@@ -0,0 +1,174 @@
+use hashbrown::HashMap;
+use noise::OpenSimplex;
+use crate::data::{voxel_octree::{VoxelOctree, MeshData}, ambient_occlusion::{vertex_cell, cell_ao, cell_ao_in_bounds}, normals::{cell_normal, cell_normal_in_bounds}, materials::MaterialRegistry};
+use super::{adjacent_keys, generated_voxel};
+use super::chunk_manager::ChunkManager;
+use super::coords::{ChunkLayout, WorldVoxelPos};
+
+/**
+  Copy of the chunks around a key, so the border ambient occlusion and normals
+  can be computed off the main thread with the same result as the ChunkManager
+*/
+#[derive(Clone)]
+pub struct ChunkApron {
+  key: [i64; 3],
+  layout: ChunkLayout,
+  noise: OpenSimplex,
+  materials: MaterialRegistry,
+  /// Loaded adjacent chunks, the others are sampled from the generator
+  octrees: HashMap<[i64; 3], VoxelOctree>,
+}
+
+impl ChunkManager {
+  pub fn apron(&self, key: &[i64; 3]) -> ChunkApron {
+    let mut octrees = HashMap::new();
+    for k in adjacent_keys(key, 1, false).iter() {
+      if let Some(chunk) = self.get_chunk(k) {
+        octrees.insert(*k, chunk.octree.clone());
+      }
+    }
+    ChunkApron {
+      key: *key,
+      layout: self.layout(),
+      noise: self.noise,
+      materials: self.materials.clone(),
+      octrees: octrees,
+    }
+  }
+}
+
+impl ChunkApron {
+  /// Same as ChunkManager::update_border_ao, octree is the chunk of the key
+  pub fn update_border_ao(&self, octree: &VoxelOctree, mesh: &mut MeshData) {
+    let is_solid = self.is_solid(octree);
+    update_border_ao(octree, mesh, self.layout.voxel_scale, &is_solid);
+  }
+
+  /// Same as ChunkManager::update_border_normals
+  pub fn update_border_normals(&self, octree: &VoxelOctree, mesh: &mut MeshData) {
+    let is_solid = self.is_solid(octree);
+    update_border_normals(octree, mesh, self.layout.voxel_scale, &is_solid);
+  }
+
+  fn is_solid<'a>(&'a self, octree: &'a VoxelOctree) -> impl Fn(i64, i64, i64) -> bool + 'a {
+    let size = octree.get_size() as i64;
+    let seamless_size = self.layout.seamless_size as i64;
+    let start = self.key.map(|v| v * seamless_size);
+    move |x: i64, y: i64, z: i64| {
+      let inside = [x, y, z].iter().all(|v| *v >= 0 && *v < size);
+      let voxel = if inside {
+        octree.get_voxel(x as u32, y as u32, z as u32)
+      } else {
+        let pos = WorldVoxelPos([start[0] + x, start[1] + y, start[2] + z]);
+        match self.octrees.get(&pos.key(&self.layout).0) {
+          Some(o) => {
+            let l = pos.local(&self.layout).0;
+            o.get_voxel(l[0], l[1], l[2])
+          }
+          None => generated_voxel(&pos.0, self.noise),
+        }
+      };
+      voxel > 0 && !self.materials.separate_pass(voxel)
+    }
+  }
+}
+
+/// Ambient occlusion of the vertices sampling outside of the octree
+pub(crate) fn update_border_ao<F: Fn(i64, i64, i64) -> bool>(
+  octree: &VoxelOctree,
+  mesh: &mut MeshData,
+  scale: f32,
+  is_solid: &F,
+) {
+  if mesh.ao.len() != mesh.positions.len() {
+    return;
+  }
+
+  let size = octree.get_size() as i64;
+  let mut cache = HashMap::new();
+  for (i, pos) in mesh.positions.iter().enumerate() {
+    let cell = vertex_cell(pos, scale);
+    if cell_ao_in_bounds(&cell, size) {
+      continue;
+    }
+    mesh.ao[i] = *cache
+      .entry(cell)
+      .or_insert_with(|| cell_ao(&cell, is_solid));
+  }
+}
+
+/// Normals of the vertices sampling outside of the octree
+pub(crate) fn update_border_normals<F: Fn(i64, i64, i64) -> bool>(
+  octree: &VoxelOctree,
+  mesh: &mut MeshData,
+  scale: f32,
+  is_solid: &F,
+) {
+  if mesh.normals.len() != mesh.positions.len() {
+    return;
+  }
+
+  let size = octree.get_size() as i64;
+  let mut cache = HashMap::new();
+  for (i, pos) in mesh.positions.iter().enumerate() {
+    let cell = vertex_cell(pos, scale);
+    if cell_normal_in_bounds(&cell, size) {
+      continue;
+    }
+    let normal = *cache
+      .entry(cell)
+      .or_insert_with(|| cell_normal(&cell, is_solid));
+    if normal != [0.0; 3] {
+      mesh.normals[i] = normal;
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use crate::data::{voxel_octree::VoxelMode, surface_nets::VoxelReuse};
+
+  #[test]
+  fn test_apron_matches_chunk_manager() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let seamless_size = chunk_manager.seamless_size() as i64;
+    let key = [0, 10, 0];
+    let start_y = key[1] * seamless_size;
+    for x in -2..32 {
+      for z in -2..18 {
+        let h = 4 + (x + z) / 3;
+        for y in 0..h {
+          chunk_manager.set_voxel2(&[x, start_y + y, z], 1);
+        }
+      }
+    }
+
+    let chunk = chunk_manager.get_chunk(&key).unwrap().clone();
+    let mesh = chunk.octree.compute_mesh(
+      VoxelMode::SurfaceNets,
+      &mut VoxelReuse::new(chunk_manager.depth, 3),
+      &chunk_manager.colors,
+      chunk_manager.voxel_scale,
+      key,
+      0
+    );
+
+    let mut expected = mesh.clone();
+    chunk_manager.update_border_ao(&chunk.octree, &mut expected);
+    chunk_manager.update_border_normals(&chunk.octree, &mut expected);
+
+    let apron = chunk_manager.apron(&key);
+    // Edits after the copy are not seen
+    chunk_manager.set_voxel2(&[17, start_y + 10, 5], 0);
+    let mut data = mesh.clone();
+    apron.update_border_ao(&chunk.octree, &mut data);
+    apron.update_border_normals(&chunk.octree, &mut data);
+
+    assert_ne!(data.ao, mesh.ao);
+    assert_eq!(data.ao, expected.ao);
+    assert_eq!(data.normals, expected.normals);
+    Ok(())
+  }
+}
@@ -1,6 +1,12 @@
-use crate::{data::voxel_octree::{VoxelOctree, ParentValueType}, utils::get_chunk_coords};
+use crate::{data::{voxel_octree::{VoxelOctree, ParentValueType, MeshData}, materials::MaterialRegistry}};
 use super::*;
-use hashbrown::HashMap;
+use super::apron::{update_border_ao, update_border_normals};
+use super::light::LightMap;
+use super::fluid::FluidMap;
+use super::simulation::ActiveSet;
+use super::coords::{WorldVoxelPos, LocalPos};
+use crate::utils::grid_hashmap::GridHashMap;
+use hashbrown::{HashMap, HashSet};
 use noise::*;
 use serde::{Serialize, Deserialize};
 
@@ -51,15 +57,33 @@ pub struct SubscribeData {
   pub rays: Vec<[f32; 3]>,
 }
 
+/// Variant order is kept stable since chunks are also sent through bincode
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
 pub enum ChunkMode {
   None,
-  Loaded,
+  /// Has air, transparent or liquid voxels next to other ones, the only
+  /// mode that produces a mesh
+  #[serde(alias = "Loaded")]
+  Surface,
   Unloaded,
-  Air,
+  /// All voxels are air
+  #[serde(alias = "Air")]
+  Empty,
+  /// All voxels are opaque
   Inner,
 }
 
+impl ChunkMode {
+  /// False only when the chunk is known to produce no surface,
+  /// unclassified chunks still have to be meshed
+  pub fn has_surface(&self) -> bool {
+    match self {
+      ChunkMode::Empty | ChunkMode::Inner => false,
+      _ => true,
+    }
+  }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Deployment {
   Production,
@@ -87,9 +111,54 @@ impl Default for Chunk {
   }
 }
 
+impl Chunk {
+  /**
+    Chunk with every voxel set to value, its octree is only the root node
+    until a write changes a voxel. The air above and the rock below the
+    terrain are kept like this. Any value but air is classified as opaque
+  */
+  pub fn uniform(key: &[i64; 3], lod: usize, depth: u8, value: u8) -> Chunk {
+    let octree = VoxelOctree::new(value, depth);
+    Chunk {
+      key: *key,
+      lod: lod,
+      mode: chunk_mode(&octree, &MaterialRegistry::default()),
+      octree: octree,
+      is_default: true,
+    }
+  }
+
+  /// Value of every voxel while the octree is only the root node
+  pub fn uniform_value(&self) -> Option<u8> {
+    match self.octree.is_empty() {
+      true => Some(self.octree.data[1]),
+      false => None,
+    }
+  }
+
+  /// Writes the voxel, the nodes of a uniform chunk are only created when
+  /// the value changes
+  pub fn set_voxel(&mut self, local: LocalPos, voxel: u8) {
+    if self.uniform_value() == Some(voxel) {
+      return;
+    }
+    let l = local.0;
+    self.octree.set_voxel(l[0], l[1], l[2], voxel);
+  }
+
+  /// Classifies the chunk after writes, octrees left with only air shrink
+  /// back to the root node
+  pub fn update_mode(&mut self, materials: &MaterialRegistry) {
+    self.mode = chunk_mode(&self.octree, materials);
+    if self.mode == ChunkMode::Empty && !self.octree.is_empty() {
+      self.octree = VoxelOctree::new(0, self.octree.get_depth());
+    }
+  }
+}
+
 #[derive(Clone)]
 pub struct ChunkManager {
-  pub chunks: HashMap<[i64; 3], Chunk>,
+  pub chunks: GridHashMap<Chunk, [i64; 3]>,
   pub depth: u32,
   pub chunk_size: u32,
   pub offset: u32,
@@ -99,7 +168,12 @@ pub struct ChunkManager {
 
   pub voxel_scale: f32,
   pub range: u8,
+  /// Palette passed to the meshers, the colors of the materials
   pub colors: Vec<[f32; 3]>,
+  pub materials: MaterialRegistry,
+  pub light: LightMap,
+  pub fluid: FluidMap,
+  pub granular: ActiveSet,
 }
 
 impl Default for ChunkManager {
@@ -113,7 +187,7 @@ impl Default for ChunkManager {
     let chunk_size = 2_i32.pow(depth) as u32;
 
     ChunkManager {
-      chunks: HashMap::new(),
+      chunks: GridHashMap::default(),
       depth: depth,
       chunk_size: chunk_size,
       offset: offset,
@@ -123,6 +197,10 @@ impl Default for ChunkManager {
       voxel_scale: 1.0,
       range: 1,
       colors: DEFAULT_COLOR_PALETTE.to_vec(),
+      materials: MaterialRegistry::from_colors(&DEFAULT_COLOR_PALETTE),
+      light: LightMap::default(),
+      fluid: FluidMap::new(chunk_size - offset),
+      granular: ActiveSet::default(),
     }
   }
 }
@@ -140,7 +218,7 @@ impl ChunkManager {
     let chunk_size = 2_i32.pow(depth) as u32;
 
     ChunkManager {
-      chunks: HashMap::new(),
+      chunks: GridHashMap::default(),
       depth: depth,
       chunk_size: chunk_size,
       offset: offset,
@@ -149,9 +227,19 @@ impl ChunkManager {
       frequency: 0.0125,
       voxel_scale: voxel_scale,
       range: range,
+      materials: MaterialRegistry::from_colors(&colors),
       colors: colors,
+      light: LightMap::default(),
+      fluid: FluidMap::new(chunk_size - offset),
+      granular: ActiveSet::default(),
     }
   }
+
+  /// Replaces the materials, the palette follows their colors
+  pub fn set_materials(&mut self, materials: MaterialRegistry) {
+    self.colors = materials.colors();
+    self.materials = materials;
+  }
 /* 
   /* TODO: Remove later */
   pub fn set_voxel1(&mut self, pos: &[i64; 3], voxel: u8) -> Vec<[i64; 3]> {
@@ -219,86 +307,80 @@ impl ChunkManager {
  */
   pub fn set_voxel2(&mut self, pos: &[i64; 3], voxel: u8) -> Vec<([i64; 3], Chunk)> {
     let mut chunks = Vec::new();
-    let chunk_size = self.chunk_size;
-    let seamless_size = self.seamless_size();
+    for (key, local) in WorldVoxelPos(*pos).chunks(&self.layout()) {
+      let key = key.0;
+      if !self.chunks.contains_key(&key) {
+        let chunk = ChunkManager::new_chunk(&key, self.depth as u8, 0, self.noise);
+        self.set_chunk(&key, &chunk);
+      }
+      let chunk = self.chunks.get_mut(&key).unwrap();
+      chunk.set_voxel(local, voxel);
+      chunk.update_mode(&self.materials);
+      chunks.push((key, chunk.clone()));
+    }
+    chunks
+  }
 
-    let coords = get_chunk_coords(pos, chunk_size, seamless_size);
-    for coord in coords.iter() {
-      let key = &coord.key;
-      let local = &coord.local;
+  /// Whether every chunk holding the voxel is loaded
+  pub fn voxel_loaded(&self, pos: &[i64; 3]) -> bool {
+    WorldVoxelPos(*pos)
+      .chunks(&self.layout())
+      .all(|(key, _)| self.chunks.contains_key(&key.0))
+  }
 
-      // Refactor: Chunk already have keys, remove mapping here later
+  /// Sets the voxel in the loaded chunks holding it, without creating the
+  /// missing chunks. The chunk modes are left to the caller
+  pub fn set_loaded_voxel(&mut self, pos: &[i64; 3], voxel: u8) {
+    for (key, local) in WorldVoxelPos(*pos).chunks(&self.layout()) {
+      if let Some(chunk) = self.chunks.get_mut(&key.0) {
+        chunk.set_voxel(local, voxel);
+      }
+    }
+  }
 
-      if let Some(chunk) = self.get_chunk_mut(key) {
-        chunk.octree.set_voxel(local[0], local[1], local[2], voxel);
-        chunks.push((key.clone(), chunk.clone()));
-      } else {
-        let mut chunk = ChunkManager::new_chunk(
-          &key, self.depth as u8, 0, self.noise
-        );
-        chunk.octree.set_voxel(local[0], local[1], local[2], voxel);
-        self.set_chunk(key, &chunk);
-        chunks.push((key.clone(), chunk.clone()));
+  /// Sets the voxels, creating the missing chunks like set_voxel2. Returns
+  /// the keys of the changed chunks, sorted
+  pub fn set_voxels(&mut self, voxels: &[([i64; 3], u8)]) -> Vec<[i64; 3]> {
+    let mut keys = HashSet::new();
+    let layout = self.layout();
+    for (pos, voxel) in voxels.iter() {
+      for (key, local) in WorldVoxelPos(*pos).chunks(&layout) {
+        if !self.chunks.contains_key(&key.0) {
+          let chunk = ChunkManager::new_chunk(&key.0, self.depth as u8, 0, self.noise);
+          self.set_chunk(&key.0, &chunk);
+        }
+        let chunk = self.chunks.get_mut(&key.0).unwrap();
+        chunk.set_voxel(local, *voxel);
+        keys.insert(key.0);
       }
     }
-    chunks
+
+    let mut keys: Vec<[i64; 3]> = keys.into_iter().collect();
+    keys.sort();
+    for key in keys.iter() {
+      if let Some(chunk) = self.chunks.get_mut(key) {
+        chunk.update_mode(&self.materials);
+      }
+    }
+    keys
   }
 
   /**
     Returns 0 if the chunk is not loaded containing the coordinate
    */
   pub fn get_voxel(&self, pos: &[i64; 3]) -> u8 {
-    let seamless_size = self.seamless_size();
-    let key = voxel_pos_to_key(pos, seamless_size);
-    // let key = world_pos_to_key(pos, seamless_size);
-    
-    let octree = match self.get_octree(&pos) {
-      Some(o) => o,
-      None => return 0
-    };
-
-    let sizei64 = seamless_size as i64;
-    let local_x = pos[0] - (key[0] * sizei64);
-    let local_y = pos[1] - (key[1] * sizei64);
-    let local_z = pos[2] - (key[2] * sizei64);
-
-    // println!("key1 {:?} local {} {} {}", key, local_x, local_y, local_z);
-
-    octree.get_voxel(local_x as u32, local_y as u32, local_z as u32)
+    self.get_voxel_safe(pos).unwrap_or(0)
   }
 
   /**
    * Returns None if the chunk is not loaded containing the coordinate 
    */
   pub fn get_voxel_safe(&self, pos: &[i64; 3]) -> Option<u8> {
-    let seamless_size = self.seamless_size();
-    let key = voxel_pos_to_key(pos, seamless_size);
-    
-    let octree = match self.get_octree(&pos) {
-      Some(o) => o,
-      None => return None
-    };
-
-    let sizei64 = seamless_size as i64;
-    let local_x = pos[0] - (key[0] * sizei64);
-    let local_y = pos[1] - (key[1] * sizei64);
-    let local_z = pos[2] - (key[2] * sizei64);
-
-    // println!("key1 {:?} local {} {} {}", key, local_x, local_y, local_z);
-
-    Some(octree.get_voxel(local_x as u32, local_y as u32, local_z as u32))
-  }
-
-  fn get_octree(&self, pos: &[i64; 3]) -> Option<&VoxelOctree> {
-    let seamless_size = self.seamless_size();
-    let key = &voxel_pos_to_key(pos, seamless_size);
-    // let key = &world_pos_to_key(pos, seamless_size);
-    // println!("get_octree() Key {:?}", key);
-    let chunk = match self.get_chunk(key) {
-      Some(o) => o,
-      None => return None,
-    };
-    Some(&chunk.octree)
+    let layout = self.layout();
+    let pos = WorldVoxelPos(*pos);
+    let chunk = self.get_chunk(&pos.key(&layout).0)?;
+    let l = pos.local(&layout).0;
+    Some(chunk.octree.get_voxel(l[0], l[1], l[2]))
   }
 
   pub fn seamless_size(&self) -> u32 {
@@ -333,71 +415,48 @@ impl ChunkManager {
       is_default: true,
     };
 
-    let mut has_air = false;
-    let mut has_value = false;
-    let mut data = Vec::new();
-
     let start = 0;
     let end = size;
+
+    // The elevation only changes per column, chunks fully above or below
+    // the surface are kept uniform without building their octree
+    let mut elevations = Vec::with_capacity((size * size) as usize);
+    for octree_x in start..end {
+      for octree_z in start..end {
+        let x = start_x + octree_x;
+        let z = start_z + octree_z;
+        elevations.push(noise_elevation(&x, &z, &region_middle_pos, noise));
+      }
+    }
+    let bottom = start_y as i64 - region_middle_pos;
+    let top = bottom + size as i64 - 1;
+    if elevations.iter().all(|elevation| *elevation <= bottom) {
+      return Chunk::uniform(key, lod, depth, 0);
+    }
+    if elevations.iter().all(|elevation| *elevation > top) {
+      return Chunk::uniform(key, lod, depth, 1);
+    }
+
+    let mut data = Vec::new();
     for octree_x in start..end {
       for octree_y in start..end {
         for octree_z in start..end {
-          let x = start_x + octree_x;
           let y = start_y + octree_y;
-          let z = start_z + octree_z;
-          
-          let elevation = noise_elevation(&x, &z, &region_middle_pos, noise);
+
+          let elevation = elevations[(octree_x * size + octree_z) as usize];
           let mid_y = y as i64 - region_middle_pos;
 
           /* Uncomment this later, testing for now */
           let voxel = if mid_y < elevation { 1 } else { 0 };
           // let voxel = if mid_y < 0 { 1 } else { 0 };
           data.push([octree_x, octree_y, octree_z, voxel]);
-
-          /*
-            TODO:
-              Conditions to determine if Chunk is needed to be rendered and create collider
-                Mode:
-                  Empty/Air
-                  Inner
-                  Visible
-                Air
-                  If all values are 0
-                Inner
-                  If all values are 1
-                Visible
-                  ?
-          */
-          if octree_x <= end - 1
-            && octree_y <= end - 1
-            && octree_z <= end - 1
-          {
-            if voxel == 0 {
-              has_air = true;
-              // println!("Air {} {} {}", octree_x, octree_y, octree_z);
-            }
-            if voxel == 1 {
-              has_value = true;
-              // println!("Voxel {} {} {}", octree_x, octree_y, octree_z);
-            }
-          }
         }
       }
     }
 
+    // Generated terrain is voxel 1, classified as opaque like Chunk::uniform
     chunk.octree = VoxelOctree::new_from_3d_array(0, depth, &data, ParentValueType::Lod);
-    // chunk.mode = chunk_mode(&chunk.octree);
-
-    /*
-      TODO: Have to update mode detector
-    */
-    if (!has_air && has_value) || (has_air && !has_value) {
-      chunk.mode = ChunkMode::Air;  // Should be renamed as empty
-    }
-    if has_air && has_value {
-      chunk.mode = ChunkMode::Loaded;
-    }
-    // println!("{} {} {}", has_air, has_value, end - 2);
+    chunk.mode = chunk_mode(&chunk.octree, &MaterialRegistry::default());
     chunk
   }
 
@@ -418,11 +477,71 @@ impl ChunkManager {
     mode
   }
 
+  /**
+    Recomputes the ambient occlusion of the vertices near the chunk border
+    using the adjacent chunks, mesh.key is the chunk key of the octree
+  */
+  pub fn update_border_ao(&self, octree: &VoxelOctree, mesh: &mut MeshData) {
+    let is_solid = self.apron_is_solid(octree, mesh.key);
+    update_border_ao(octree, mesh, self.voxel_scale, &is_solid);
+  }
+
+  /**
+    Recomputes the normals of the vertices near the chunk border using the
+    adjacent chunks, so both chunks have the same normals on their shared
+    border vertices
+  */
+  pub fn update_border_normals(&self, octree: &VoxelOctree, mesh: &mut MeshData) {
+    let is_solid = self.apron_is_solid(octree, mesh.key);
+    update_border_normals(octree, mesh, self.voxel_scale, &is_solid);
+  }
+
+  /// Solid check in local voxel coords of the chunk octree, outside of the
+  /// octree it samples the adjacent chunks
+  fn apron_is_solid<'a>(
+    &'a self,
+    octree: &'a VoxelOctree,
+    key: [i64; 3]
+  ) -> impl Fn(i64, i64, i64) -> bool + 'a {
+    let size = octree.get_size() as i64;
+    let seamless_size = self.seamless_size() as i64;
+    let start = [
+      key[0] * seamless_size,
+      key[1] * seamless_size,
+      key[2] * seamless_size,
+    ];
+    move |x: i64, y: i64, z: i64| {
+      let inside = [x, y, z].iter().all(|v| *v >= 0 && *v < size);
+      let voxel = if inside {
+        octree.get_voxel(x as u32, y as u32, z as u32)
+      } else {
+        self.voxel_or_generated(&[start[0] + x, start[1] + y, start[2] + z])
+      };
+      // Air for the opaque mesh, like in get_surface_nets_passes
+      voxel > 0 && !self.materials.separate_pass(voxel)
+    }
+  }
+
   pub fn get_chunk(&self, key: &[i64; 3]) -> Option<&Chunk> {
     /* Later on, implement Spatial Partition or R-trees? */
     self.chunks.get(key)
   }
 
+  /// Keys of the loaded chunks holding the voxels, sorted
+  pub fn voxel_keys(&self, positions: &[[i64; 3]]) -> Vec<[i64; 3]> {
+    let mut keys = Vec::new();
+    for pos in positions.iter() {
+      for (key, _) in WorldVoxelPos(*pos).chunks(&self.layout()) {
+        if self.chunks.contains_key(&key.0) {
+          keys.push(key.0);
+        }
+      }
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+  }
+
   pub fn get_chunk_mut(&mut self, key: &[i64; 3]) -> Option<&mut Chunk> {
     /* Later on, implement Spatial Partition or R-trees? */
     self.chunks.get_mut(key)
@@ -437,6 +556,7 @@ impl ChunkManager {
     } else {
       self.chunks.insert(key.clone(), chunk.clone());
     }
+    self.wake_simulations(key);
   }
 
   pub fn remove_chunk(&mut self, key: &[i64; 3]) {
@@ -445,6 +565,8 @@ impl ChunkManager {
       let chunk = chunk_op.unwrap();
       if chunk.is_default {
         self.chunks.remove(key);
+        self.remove_light(key);
+        self.unload_simulations(key);
       }
     }
   }
@@ -471,6 +593,7 @@ impl ChunkManager {
         );
         chunks.push(c.clone());
         self.chunks.insert(*key, c);
+        self.wake_simulations(key);
       }
     }
 
@@ -482,7 +605,7 @@ impl ChunkManager {
 
 #[cfg(test)]
 mod tests {
-  use crate::{data::{surface_nets::{GridPosition, VoxelReuse}, voxel_octree::VoxelMode}, utils::get_length};
+  use crate::data::{surface_nets::VoxelReuse, voxel_octree::VoxelMode, ambient_occlusion::vertex_cell};
   use super::*;
 
   #[test]
@@ -498,7 +621,7 @@ mod tests {
         for z in start..end {
           new_value = if new_value == 255 { 0 } else { new_value + 1 };
           let pos = &[x, y, z];
-          chunk_manager.set_voxel1(pos, new_value);
+          chunk_manager.set_voxel2(pos, new_value);
         }
       }
     }
@@ -522,41 +645,37 @@ mod tests {
 
   #[test]
   fn test_chunk_mode() -> Result<(), String> {
-    let depth = 4;
-    let len = get_length(depth as u8);
-    let mut voxels = vec![0; len];
-    let size = (2 as u32).pow(depth as u32);
-    
-    let grid_pos_len = get_length(size as u8 - 1);
-    let mut grid_pos = Vec::new();
-    for i in 0..grid_pos_len {
-      grid_pos.push(GridPosition ::default());
-    }
-
-    let mut voxel_reuse = VoxelReuse {
-      voxels: voxels,
-      grid_pos: grid_pos,
-      size: size,
-    };
-
-    let chunk_size = 16;
-    let mut chunk_manager = ChunkManager::default();
-
-    let color = vec![[0.0, 0.0, 0.0]];
+    let chunk_manager = ChunkManager::default();
+    let mut voxel_reuse = VoxelReuse::new(chunk_manager.depth, 3);
+    let colors = DEFAULT_COLOR_PALETTE.to_vec();
 
     let keys = adjacent_keys(&[0, 0, 0], 5, true);
     for key in keys.iter() {
-      let chunk = chunk_manager.new_chunk3(key, chunk_manager.depth as u8);
+      let chunk = ChunkManager::new_chunk(
+        key, chunk_manager.depth as u8, 0, chunk_manager.noise
+      );
       let d = chunk.octree.compute_mesh(
         VoxelMode::SurfaceNets, 
         &mut voxel_reuse,
-        &color,
-        1.0
+        &colors,
+        1.0,
+        *key,
+        0
       );
+      // Mixed chunks can still mesh to nothing when the only change is on
+      // the seam border, but skipped chunks must never have a mesh
+      if !chunk.mode.has_surface() {
+        assert_eq!(d.indices.len(), 0, "key {:?}", key);
+      }
       if d.indices.len() != 0 {
-        assert_eq!(chunk.mode, ChunkMode::Loaded, "key {:?}", key);
-      } else {
-        assert_eq!(chunk.mode, ChunkMode::Air, "key {:?}", key);
+        assert_eq!(chunk.mode, ChunkMode::Surface, "key {:?}", key);
+      }
+
+      if key[1] > 1 {
+        assert_eq!(chunk.mode, ChunkMode::Empty, "key {:?}", key);
+      }
+      if key[1] < -2 {
+        assert_eq!(chunk.mode, ChunkMode::Inner, "key {:?}", key);
       }
     }
 
@@ -567,6 +686,224 @@ mod tests {
 
     Ok(())
   }
+
+  #[test]
+  fn test_set_voxel_updates_chunk_mode() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let key = [0, 10, 0];
+    let chunk = ChunkManager::new_chunk(
+      &key, chunk_manager.depth as u8, 0, chunk_manager.noise
+    );
+    assert_eq!(chunk.mode, ChunkMode::Empty);
+    chunk_manager.set_chunk(&key, &chunk);
+
+    let seamless_size = chunk_manager.seamless_size() as i64;
+    let pos = [4, key[1] * seamless_size + 4, 4];
+    for (_, chunk) in chunk_manager.set_voxel2(&pos, 7).iter() {
+      assert_eq!(chunk.mode, ChunkMode::Surface);
+    }
+
+    for (_, chunk) in chunk_manager.set_voxel2(&pos, 0).iter() {
+      assert_eq!(chunk.mode, ChunkMode::Empty);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_chunk_mode_materials() -> Result<(), String> {
+    let mut materials = MaterialRegistry::from_colors(&DEFAULT_COLOR_PALETTE);
+    materials.get_mut(2).unwrap().liquid = true;
+    materials.get_mut(3).unwrap().transparent = true;
+
+    // Water over stone without any air
+    let depth = 4;
+    let size = 2_u32.pow(depth as u32);
+    let mut data = Vec::new();
+    for x in 0..size {
+      for y in 0..size {
+        for z in 0..size {
+          data.push([x, y, z, if y < size / 2 { 1 } else { 2 }]);
+        }
+      }
+    }
+    let octree = VoxelOctree::new_from_3d_array(0, depth, &data, ParentValueType::Lod);
+    assert_eq!(chunk_mode(&octree, &MaterialRegistry::default()), ChunkMode::Inner);
+    assert_eq!(chunk_mode(&octree, &materials), ChunkMode::Surface);
+
+    let mut voxel_reuse = VoxelReuse::new(depth as u32, 3);
+    let mesh = octree.compute_mesh_passes(
+      VoxelMode::SurfaceNets, &mut voxel_reuse, &materials, 1.0, [0, 0, 0], 0
+    );
+    assert!(mesh.indices.len() > 0);
+
+    // Only glass, kept by update_mode instead of shrinking to air
+    let mut chunk = Chunk::uniform(&[0, 0, 0], 0, depth, 3);
+    assert_eq!(chunk.mode, ChunkMode::Inner);
+    chunk.update_mode(&materials);
+    assert_eq!(chunk.mode, ChunkMode::Surface);
+    assert_eq!(chunk.uniform_value(), Some(3));
+
+    chunk.octree = VoxelOctree::new(0, depth);
+    chunk.update_mode(&materials);
+    assert_eq!(chunk.mode, ChunkMode::Empty);
+    Ok(())
+  }
+
+  #[test]
+  fn test_uniform_chunks() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let depth = chunk_manager.depth as u8;
+    let layout = chunk_manager.layout();
+
+    // A tall column, only the chunks near the surface build their octree
+    let mut full = 0;
+    for y in -20..20 {
+      let key = [1, y, -2];
+      let chunk = ChunkManager::new_chunk(&key, depth, 0, chunk_manager.noise);
+      match chunk.uniform_value() {
+        Some(0) => assert_eq!(chunk.mode, ChunkMode::Empty, "key {:?}", key),
+        Some(_) => assert_eq!(chunk.mode, ChunkMode::Inner, "key {:?}", key),
+        None => full += 1,
+      }
+      chunk_manager.set_chunk(&key, &chunk);
+    }
+    assert!(full <= 4, "{} full chunks", full);
+    assert_eq!(chunk_manager.get_chunk(&[1, 10, -2]).unwrap().uniform_value(), Some(0));
+    assert_eq!(chunk_manager.get_chunk(&[1, -10, -2]).unwrap().uniform_value(), Some(1));
+
+    for y in -20 * 14..20 * 14_i64 {
+      let pos = [14 + y.rem_euclid(14), y, -28 + (y * 3).rem_euclid(14)];
+      assert_eq!(chunk_manager.get_voxel(&pos), generated_voxel(&pos, chunk_manager.noise));
+    }
+
+    // Writes of the same value keep the root, others materialize the octree
+    let pos = [16, 10 * 14 + 3, -26];
+    let key = WorldVoxelPos(pos).key(&layout).0;
+    chunk_manager.set_voxels(&[(pos, 0)]);
+    assert_eq!(chunk_manager.get_chunk(&key).unwrap().uniform_value(), Some(0));
+
+    chunk_manager.set_voxel2(&pos, 5);
+    let chunk = chunk_manager.get_chunk(&key).unwrap();
+    assert_eq!(chunk.uniform_value(), None);
+    assert_eq!(chunk.mode, ChunkMode::Surface);
+    assert_eq!(chunk_manager.get_voxel(&pos), 5);
+
+    // Back to all air, the octree shrinks again
+    chunk_manager.set_voxel2(&pos, 0);
+    let chunk = chunk_manager.get_chunk(&key).unwrap();
+    assert_eq!(chunk.uniform_value(), Some(0));
+    assert_eq!(chunk.mode, ChunkMode::Empty);
+
+    let pos = [16, -10 * 14 + 3, -26];
+    let key = WorldVoxelPos(pos).key(&layout).0;
+    chunk_manager.set_voxel2(&pos, 0);
+    let chunk = chunk_manager.get_chunk(&key).unwrap();
+    assert_eq!(chunk.mode, ChunkMode::Surface);
+    assert_eq!(chunk_manager.get_voxel(&pos), 0);
+    assert_eq!(chunk_manager.get_voxel(&[16, -10 * 14 + 4, -26]), 1);
+    Ok(())
+  }
+
+  #[test]
+  fn test_update_border_ao() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let key = [0, 10, 0];
+    let seamless_size = chunk_manager.seamless_size() as i64;
+    let start_y = key[1] * seamless_size;
+    for x in -2..20 {
+      for z in -2..20 {
+        for y in 0..5 {
+          chunk_manager.set_voxel2(&[x, start_y + y, z], 1);
+        }
+      }
+    }
+    // Wall only inside the next chunk, local x 16 of this chunk
+    for z in -2..20 {
+      for y in 5..9 {
+        chunk_manager.set_voxel2(&[16, start_y + y, z], 1);
+      }
+    }
+
+    let chunk = chunk_manager.get_chunk(&key).unwrap().clone();
+    let mut mesh = chunk.octree.compute_mesh(
+      VoxelMode::SurfaceNets,
+      &mut VoxelReuse::new(chunk_manager.depth, 3),
+      &chunk_manager.colors,
+      chunk_manager.voxel_scale,
+      key,
+      0
+    );
+    assert_eq!(mesh.ao.len(), mesh.positions.len());
+
+    let border = |mesh: &MeshData| {
+      mesh.positions.iter().position(|p| {
+        vertex_cell(p, 1.0) == [14, 4, 7]
+      }).unwrap()
+    };
+    let inner = |mesh: &MeshData| {
+      mesh.positions.iter().position(|p| {
+        vertex_cell(p, 1.0) == [7, 4, 7]
+      }).unwrap()
+    };
+    assert_eq!(mesh.ao[border(&mesh)], 1.0);
+    assert_eq!(mesh.ao[inner(&mesh)], 1.0);
+
+    chunk_manager.update_border_ao(&chunk.octree, &mut mesh);
+    assert!(mesh.ao[border(&mesh)] < 1.0);
+    assert_eq!(mesh.ao[inner(&mesh)], 1.0);
+    Ok(())
+  }
+
+  #[test]
+  fn test_border_normals_match() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let seamless_size = chunk_manager.seamless_size() as i64;
+    let key1 = [0, 10, 0];
+    let key2 = [1, 10, 0];
+    let start_y = key1[1] * seamless_size;
+    // Slope rising along x and z, crossing the chunk border at x 14
+    for x in -2..32 {
+      for z in -2..18 {
+        let h = 4 + (x + z) / 3;
+        for y in 0..h {
+          chunk_manager.set_voxel2(&[x, start_y + y, z], 1);
+        }
+      }
+    }
+
+    let mesh = |key: [i64; 3]| {
+      let chunk = chunk_manager.get_chunk(&key).unwrap().clone();
+      let mut mesh = chunk.octree.compute_mesh(
+        VoxelMode::SurfaceNets,
+        &mut VoxelReuse::new(chunk_manager.depth, 3),
+        &chunk_manager.colors,
+        chunk_manager.voxel_scale,
+        key,
+        0
+      );
+      chunk_manager.update_border_normals(&chunk.octree, &mut mesh);
+      mesh
+    };
+    let mesh1 = mesh(key1);
+    let mesh2 = mesh(key2);
+
+    let mut shared = 0;
+    for (i, p1) in mesh1.positions.iter().enumerate() {
+      for (j, p2) in mesh2.positions.iter().enumerate() {
+        let world_x = p2[0] + seamless_size as f32;
+        if (p1[0] - world_x).abs() > 1e-5 || p1[1] != p2[1] || p1[2] != p2[2] {
+          continue;
+        }
+        shared += 1;
+        for k in 0..3 {
+          let diff = (mesh1.normals[i][k] - mesh2.normals[j][k]).abs();
+          assert!(diff < 1e-5, "{:?}: {:?} {:?}", p1, mesh1.normals[i], mesh2.normals[j]);
+        }
+      }
+    }
+    assert!(shared > 0);
+    Ok(())
+  }
 }
 
 
@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use super::chunk_manager::ChunkManager;
+
+/// Decides which solid voxels hold their connected component in place
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnchorRule {
+  /// Reaching the searched region's bounds, the component may be grounded
+  /// outside of it
+  Bounds,
+  /// Voxels at or below y
+  Floor(i64),
+  /// Either Bounds or Floor(y)
+  BoundsOrFloor(i64),
+}
+
+impl AnchorRule {
+  pub(crate) fn is_anchor(&self, pos: &[i64; 3], min: &[i64; 3], max: &[i64; 3]) -> bool {
+    let on_bounds = || {
+      (0..3).any(|i| pos[i] == min[i] || pos[i] == max[i])
+    };
+    match *self {
+      AnchorRule::Bounds => on_bounds(),
+      AnchorRule::Floor(y) => pos[1] <= y,
+      AnchorRule::BoundsOrFloor(y) => pos[1] <= y || on_bounds(),
+    }
+  }
+}
+
+/// Solid voxels connected to each other but not to any anchor
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Island {
+  pub voxels: Vec<([i64; 3], u8)>,
+}
+
+impl Island {
+  pub fn bounds(&self) -> ([i64; 3], [i64; 3]) {
+    let mut min = [i64::MAX; 3];
+    let mut max = [i64::MIN; 3];
+    for (pos, _) in self.voxels.iter() {
+      for i in 0..3 {
+        min[i] = min[i].min(pos[i]);
+        max[i] = max[i].max(pos[i]);
+      }
+    }
+    (min, max)
+  }
+}
+
+const NEIGHBORS: [[i64; 3]; 6] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 1, 0], [0, -1, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+
+impl ChunkManager {
+  /**
+    Flood fills the solid voxels inside min..=max by face adjacency and
+    returns the components that have no anchor voxel. Unloaded chunks are
+    sampled from the terrain generator.
+  */
+  pub fn detached_islands(
+    &self,
+    min: [i64; 3],
+    max: [i64; 3],
+    anchor: AnchorRule
+  ) -> Vec<Island> {
+    let mut islands = Vec::new();
+    if (0..3).any(|i| min[i] > max[i]) {
+      return islands;
+    }
+
+    let size = [
+      (max[0] - min[0] + 1) as usize,
+      (max[1] - min[1] + 1) as usize,
+      (max[2] - min[2] + 1) as usize,
+    ];
+    let index = |p: &[i64; 3]| {
+      let x = (p[0] - min[0]) as usize;
+      let y = (p[1] - min[1]) as usize;
+      let z = (p[2] - min[2]) as usize;
+      (x * size[1] + y) * size[2] + z
+    };
+
+    let mut values = vec![0_u8; size[0] * size[1] * size[2]];
+    for x in min[0]..=max[0] {
+      for y in min[1]..=max[1] {
+        for z in min[2]..=max[2] {
+          let pos = [x, y, z];
+          values[index(&pos)] = self.voxel_or_generated(&pos);
+        }
+      }
+    }
+
+    let mut visited = vec![false; values.len()];
+    let mut queue = VecDeque::new();
+    for x in min[0]..=max[0] {
+      for y in min[1]..=max[1] {
+        for z in min[2]..=max[2] {
+          let start = [x, y, z];
+          let start_index = index(&start);
+          if visited[start_index] || values[start_index] == 0 {
+            continue;
+          }
+
+          visited[start_index] = true;
+          queue.push_back(start);
+
+          let mut anchored = false;
+          let mut island = Island::default();
+          while let Some(pos) = queue.pop_front() {
+            anchored |= anchor.is_anchor(&pos, &min, &max);
+            island.voxels.push((pos, values[index(&pos)]));
+
+            for n in NEIGHBORS.iter() {
+              let next = [pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]];
+              if (0..3).any(|i| next[i] < min[i] || next[i] > max[i]) {
+                continue;
+              }
+
+              let next_index = index(&next);
+              if visited[next_index] || values[next_index] == 0 {
+                continue;
+              }
+              visited[next_index] = true;
+              queue.push_back(next);
+            }
+          }
+
+          if !anchored {
+            islands.push(island);
+          }
+        }
+      }
+    }
+    islands
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use super::AnchorRule;
+
+  #[test]
+  fn test_detached_island_after_carving() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    for y in 100..110 {
+      chunk_manager.set_voxel2(&[2, y, 3], 1);
+    }
+    chunk_manager.set_voxel2(&[2, 109, 4], 5);
+
+    let min = [-5, 95, -5];
+    let max = [10, 115, 10];
+    let islands = chunk_manager.detached_islands(min, max, AnchorRule::Floor(100));
+    assert_eq!(islands.len(), 0);
+
+    chunk_manager.set_voxel2(&[2, 104, 3], 0);
+    let islands = chunk_manager.detached_islands(min, max, AnchorRule::Floor(100));
+    assert_eq!(islands.len(), 1);
+
+    let island = &islands[0];
+    assert_eq!(island.voxels.len(), 6);
+    assert!(island.voxels.contains(&([2, 109, 4], 5)));
+    assert!(island.voxels.iter().all(|(pos, _)| pos[1] > 104));
+    assert_eq!(island.bounds(), ([2, 105, 3], [2, 109, 4]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_detached_islands_bounds_anchor() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    for x in 0..10 {
+      chunk_manager.set_voxel2(&[x, 100, 0], 1);
+    }
+    chunk_manager.set_voxel2(&[3, 103, 0], 1);
+    chunk_manager.set_voxel2(&[3, 104, 0], 1);
+
+    // The beam reaches the region bounds, so it could be held from outside
+    let min = [2, 95, -5];
+    let max = [8, 110, 5];
+    let islands = chunk_manager.detached_islands(min, max, AnchorRule::Bounds);
+    assert_eq!(islands.len(), 1);
+    assert_eq!(islands[0].voxels.len(), 2);
+
+    let islands = chunk_manager.detached_islands(min, max, AnchorRule::Floor(0));
+    assert_eq!(islands.len(), 2);
+
+    let islands = chunk_manager.detached_islands(
+      min, max, AnchorRule::BoundsOrFloor(103)
+    );
+    assert_eq!(islands.len(), 0);
+    Ok(())
+  }
+
+  #[test]
+  fn test_generated_terrain_is_anchored() -> Result<(), String> {
+    let chunk_manager = ChunkManager::default();
+    let islands = chunk_manager.detached_islands(
+      [-8, -20, -8], [8, 20, 8], AnchorRule::BoundsOrFloor(-20)
+    );
+    assert_eq!(islands.len(), 0);
+
+    let islands = chunk_manager.detached_islands(
+      [8, 0, 0], [0, 0, 0], AnchorRule::Bounds
+    );
+    assert_eq!(islands.len(), 0);
+    Ok(())
+  }
+}
@@ -0,0 +1,267 @@
+use std::ops::RangeInclusive;
+use super::chunk_manager::ChunkManager;
+
+/**
+  Sizes the coordinate conversions need. Chunks are chunk_size voxels wide
+  and start seamless_size voxels apart, so the voxels on their borders are
+  held by up to 8 chunks
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkLayout {
+  pub chunk_size: u32,
+  pub seamless_size: u32,
+  /// World size of a voxel
+  pub voxel_scale: f32,
+}
+
+/// Voxel position in the world, the same in every chunk holding it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WorldVoxelPos(pub [i64; 3]);
+
+/// Chunk position in chunks, its first voxel is at key * seamless_size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkKey(pub [i64; 3]);
+
+/// Voxel position inside a chunk, 0..chunk_size on each axis
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LocalPos(pub [u32; 3]);
+
+impl ChunkLayout {
+  pub fn new(chunk_size: u32, seamless_size: u32, voxel_scale: f32) -> Self {
+    assert!(seamless_size > 0 && seamless_size <= chunk_size);
+    Self {
+      chunk_size: chunk_size,
+      seamless_size: seamless_size,
+      voxel_scale: voxel_scale,
+    }
+  }
+
+  /// Keys of the chunks holding the coordinate along an axis
+  fn key_range(&self, v: i64) -> RangeInclusive<i64> {
+    let seamless = self.seamless_size as i64;
+    let first = (v - self.chunk_size as i64 + seamless).div_euclid(seamless);
+    first..=v.div_euclid(seamless)
+  }
+}
+
+impl ChunkManager {
+  pub fn layout(&self) -> ChunkLayout {
+    ChunkLayout::new(self.chunk_size, self.seamless_size(), self.voxel_scale)
+  }
+}
+
+impl WorldVoxelPos {
+  /// Voxel nearest to the world position, the voxels sit on the corners of
+  /// the voxel_scale grid
+  pub fn from_world(pos: [f32; 3], layout: &ChunkLayout) -> Self {
+    Self(pos.map(|v| (v / layout.voxel_scale).round() as i64))
+  }
+
+  pub fn to_world(self, layout: &ChunkLayout) -> [f32; 3] {
+    self.0.map(|v| v as f32 * layout.voxel_scale)
+  }
+
+  pub fn offset(self, delta: [i64; 3]) -> Self {
+    Self([0, 1, 2].map(|i| self.0[i] + delta[i]))
+  }
+
+  /// Chunk the voxel is read from, the one where it isn't in the overlap
+  /// with the previous chunk
+  pub fn key(self, layout: &ChunkLayout) -> ChunkKey {
+    ChunkKey(self.0.map(|v| v.div_euclid(layout.seamless_size as i64)))
+  }
+
+  /// Position in the chunk, None when the chunk doesn't hold the voxel
+  pub fn local_in(self, key: ChunkKey, layout: &ChunkLayout) -> Option<LocalPos> {
+    let min = key.min_voxel(layout).0;
+    let mut local = [0; 3];
+    for i in 0..3 {
+      let v = self.0[i] - min[i];
+      if v < 0 || v >= layout.chunk_size as i64 {
+        return None;
+      }
+      local[i] = v as u32;
+    }
+    Some(LocalPos(local))
+  }
+
+  /// Local position in the chunk the voxel is read from
+  pub fn local(self, layout: &ChunkLayout) -> LocalPos {
+    self.local_in(self.key(layout), layout).unwrap()
+  }
+
+  /// Every chunk holding the voxel and the position in it, sorted by key.
+  /// Edits have to reach all of them to keep the chunk borders seamless
+  pub fn chunks(self, layout: &ChunkLayout) -> impl Iterator<Item = (ChunkKey, LocalPos)> {
+    let ranges = self.0.map(|v| layout.key_range(v));
+    let mut coords = Vec::with_capacity(8);
+    for x in ranges[0].clone() {
+      for y in ranges[1].clone() {
+        for z in ranges[2].clone() {
+          let key = ChunkKey([x, y, z]);
+          if let Some(local) = self.local_in(key, layout) {
+            coords.push((key, local));
+          }
+        }
+      }
+    }
+    coords.into_iter()
+  }
+}
+
+impl ChunkKey {
+  /// First voxel of the chunk
+  pub fn min_voxel(self, layout: &ChunkLayout) -> WorldVoxelPos {
+    WorldVoxelPos(self.0.map(|v| v * layout.seamless_size as i64))
+  }
+
+  /// Last voxel of the chunk
+  pub fn max_voxel(self, layout: &ChunkLayout) -> WorldVoxelPos {
+    self.min_voxel(layout).offset([layout.chunk_size as i64 - 1; 3])
+  }
+
+  /// World position of the chunk mesh
+  pub fn to_world(self, layout: &ChunkLayout) -> [f32; 3] {
+    self.min_voxel(layout).to_world(layout)
+  }
+
+  pub fn voxel(self, local: LocalPos, layout: &ChunkLayout) -> WorldVoxelPos {
+    local.to_world_voxel(self, layout)
+  }
+
+  pub fn contains(self, pos: WorldVoxelPos, layout: &ChunkLayout) -> bool {
+    pos.local_in(self, layout).is_some()
+  }
+}
+
+impl LocalPos {
+  pub fn to_world_voxel(self, key: ChunkKey, layout: &ChunkLayout) -> WorldVoxelPos {
+    key.min_voxel(layout).offset(self.0.map(|v| v as i64))
+  }
+}
+
+impl From<[i64; 3]> for WorldVoxelPos {
+  fn from(pos: [i64; 3]) -> Self {
+    Self(pos)
+  }
+}
+
+impl From<[i64; 3]> for ChunkKey {
+  fn from(key: [i64; 3]) -> Self {
+    Self(key)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  #[allow(deprecated)]
+  use crate::chunk::voxel_pos_to_key;
+  use crate::utils::{get_chunk_coords, key_to_world_coord_f32};
+  use crate::utils::tests::xorshift;
+  use super::*;
+
+  fn layout() -> ChunkLayout {
+    ChunkLayout::new(16, 14, 0.25)
+  }
+
+  /// Deterministic xorshift positions, near the chunk borders half the time
+  fn positions(count: usize) -> Vec<WorldVoxelPos> {
+    let mut next = xorshift(0x2545_f491_4f6c_dd1d);
+    (0..count)
+      .map(|i| {
+        WorldVoxelPos([0, 1, 2].map(|_| {
+          let v = (next() % 2_000_001) as i64 - 1_000_000;
+          match i % 2 {
+            0 => v,
+            _ => (v / 14) * 14 + (next() % 5) as i64 - 2,
+          }
+        }))
+      })
+      .collect()
+  }
+
+  #[test]
+  #[allow(deprecated)]
+  fn test_coords_round_trip() -> Result<(), String> {
+    let layout = layout();
+    let mut positions = positions(2000);
+    for x in -30..30 {
+      positions.push(WorldVoxelPos([x, -x, x * 3]));
+    }
+
+    for pos in positions {
+      let key = pos.key(&layout);
+      let local = pos.local(&layout);
+      assert_eq!(key.voxel(local, &layout), pos);
+      assert!(local.0.iter().all(|v| *v < layout.seamless_size));
+      assert_eq!(key.0, voxel_pos_to_key(&pos.0, layout.seamless_size));
+
+      let chunks: Vec<(ChunkKey, LocalPos)> = pos.chunks(&layout).collect();
+      assert!(!chunks.is_empty() && chunks.len() <= 8);
+      assert!(chunks.contains(&(key, local)));
+      for (key, local) in chunks.iter() {
+        assert_eq!(local.to_world_voxel(*key, &layout), pos);
+        assert!(key.contains(pos, &layout));
+        let (min, max) = (key.min_voxel(&layout).0, key.max_voxel(&layout).0);
+        assert!((0..3).all(|i| min[i] <= pos.0[i] && pos.0[i] <= max[i]));
+      }
+
+      // Every chunk holding the voxel is listed, the neighbors hold none
+      let near = pos.key(&layout).0;
+      for dx in -2..=2 {
+        for dy in -2..=2 {
+          for dz in -2..=2 {
+            let key = ChunkKey([near[0] + dx, near[1] + dy, near[2] + dz]);
+            let listed = chunks.iter().any(|(k, _)| *k == key);
+            assert_eq!(key.contains(pos, &layout), listed);
+          }
+        }
+      }
+
+      let world = pos.to_world(&layout);
+      assert_eq!(WorldVoxelPos::from_world(world, &layout), pos);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_coords_match_chunk_coords() -> Result<(), String> {
+    let layout = layout();
+    for pos in positions(500) {
+      let typed: Vec<([i64; 3], [u32; 3])> = pos.chunks(&layout).map(|(k, l)| (k.0, l.0)).collect();
+      let old: Vec<([i64; 3], [u32; 3])> = get_chunk_coords(&pos.0, 16, 14)
+        .iter()
+        .map(|c| (c.key, c.local))
+        .collect();
+      assert_eq!(typed, old);
+
+      let key = pos.key(&layout);
+      let world = key_to_world_coord_f32(&key.0, 14).map(|v| v * layout.voxel_scale);
+      assert_eq!(key.to_world(&layout), world);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_coords_borders() -> Result<(), String> {
+    let layout = layout();
+    let keys = |pos: [i64; 3]| -> Vec<[i64; 3]> {
+      WorldVoxelPos(pos).chunks(&layout).map(|(k, _)| k.0).collect()
+    };
+    assert_eq!(keys([5, 5, 5]), vec![[0, 0, 0]]);
+    assert_eq!(keys([14, 5, 5]), vec![[0, 0, 0], [1, 0, 0]]);
+    assert_eq!(keys([15, 5, 5]), vec![[0, 0, 0], [1, 0, 0]]);
+    assert_eq!(keys([16, 5, 5]), vec![[1, 0, 0]]);
+    assert_eq!(keys([-1, 5, 5]), vec![[-1, 0, 0]]);
+    assert_eq!(keys([0, 0, 0]).len(), 8);
+    assert_eq!(WorldVoxelPos([-1, 0, 0]).local(&layout), LocalPos([13, 0, 0]));
+    assert_eq!(WorldVoxelPos([-15, 0, 0]).key(&layout), ChunkKey([-2, 0, 0]));
+
+    // Overlaps wider than a chunk step still list every chunk
+    let wide = ChunkLayout::new(16, 4, 1.0);
+    assert_eq!(WorldVoxelPos([0, 0, 0]).chunks(&wide).count(), 4 * 4 * 4);
+    assert_eq!(WorldVoxelPos::from_world([-0.6, 0.4, 1.0], &wide), WorldVoxelPos([-1, 0, 1]));
+    Ok(())
+  }
+}
@@ -0,0 +1,320 @@
+use hashbrown::HashSet;
+use crate::utils::grid_hashmap::GridHashMap;
+use super::chunk_manager::ChunkManager;
+use super::simulation::ActiveSet;
+
+/// Level of a full liquid voxel, the levels are the amount of liquid
+pub const MAX_FLUID_LEVEL: u8 = 8;
+
+const NEIGHBORS: [[i64; 3]; 6] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 1, 0], [0, -1, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+const SIDES: [[i64; 3]; 4] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+
+/**
+  State of the liquid simulation. Liquid voxels without a level are full,
+  only the voxels in active are updated on the next tick. The cells of the
+  levels are a chunk wide, so they unload with the chunk owning the voxels
+*/
+#[derive(Clone, Debug)]
+pub struct FluidMap {
+  pub levels: GridHashMap<u8, [i64; 3]>,
+  pub active: ActiveSet,
+}
+
+impl FluidMap {
+  pub fn new(seamless_size: u32) -> Self {
+    Self {
+      levels: GridHashMap::new(seamless_size),
+      active: ActiveSet::default(),
+    }
+  }
+}
+
+impl ChunkManager {
+  /// Amount of liquid in the voxel, 0 when it isn't a liquid
+  pub fn fluid_level(&self, pos: &[i64; 3]) -> u8 {
+    if !self.materials.is_liquid(self.get_voxel(pos)) {
+      return 0;
+    }
+    *self.fluid.levels.get(pos).unwrap_or(&MAX_FLUID_LEVEL)
+  }
+
+  /// Wakes the liquids at and next to the voxels that were set
+  pub fn wake_fluids(&mut self, positions: &Vec<[i64; 3]>) {
+    for pos in positions.iter() {
+      self.wake_fluid(pos);
+    }
+  }
+
+  /**
+    Moves the active liquids one step: down first, then to the sides with
+    less liquid. The liquid is conserved and stops flowing once the levels of
+    the neighbors differ by one at most. Liquids in or above chunks that
+    aren't loaded wait for them. Returns the changed voxels, sorted
+  */
+  pub fn tick_fluids(&mut self) -> Vec<[i64; 3]> {
+    self.tick_active(|c| &mut c.fluid.active, |c, pos, changed| {
+      let voxel = c.get_voxel(pos);
+      if !c.materials.is_liquid(voxel) {
+        c.fluid.levels.remove(pos);
+        return;
+      }
+      c.flow(pos, voxel, changed);
+    })
+  }
+
+  fn flow(&mut self, pos: &[i64; 3], voxel: u8, changed: &mut HashSet<[i64; 3]>) {
+    let mut level = self.fluid_level(pos);
+    let start = level;
+
+    let below = [pos[0], pos[1] - 1, pos[2]];
+    if let Some(below_level) = self.fluid_space(&below, voxel) {
+      let amount = level.min(MAX_FLUID_LEVEL - below_level);
+      if amount > 0 {
+        level -= amount;
+        self.set_fluid(&below, voxel, below_level + amount, changed);
+      }
+    }
+
+    // One level at a time to the side with the least liquid
+    let mut sides: Vec<([i64; 3], u8)> = SIDES
+      .iter()
+      .map(|s| [pos[0] + s[0], pos[1] + s[1], pos[2] + s[2]])
+      .filter_map(|p| self.fluid_space(&p, voxel).map(|l| (p, l)))
+      .collect();
+    let start_sides = sides.clone();
+    loop {
+      let lowest = sides
+        .iter_mut()
+        .filter(|(_, l)| *l + 1 < level)
+        .min_by_key(|(_, l)| *l);
+      match lowest {
+        Some((_, side_level)) => {
+          *side_level += 1;
+          level -= 1;
+        }
+        None => break,
+      }
+    }
+    for (side, start_side) in sides.iter().zip(start_sides.iter()) {
+      if side.1 != start_side.1 {
+        self.set_fluid(&side.0, voxel, side.1, changed);
+      }
+    }
+
+    if level != start {
+      self.set_fluid(pos, voxel, level, changed);
+    }
+  }
+
+  /// Level of the voxel if the liquid can flow into it, None for solids,
+  /// other liquids and voxels outside of the loaded chunks
+  fn fluid_space(&self, pos: &[i64; 3], voxel: u8) -> Option<u8> {
+    if !self.voxel_loaded(pos) {
+      return None;
+    }
+    let current = self.get_voxel(pos);
+    if current == 0 {
+      return Some(0);
+    }
+    if current != voxel {
+      return None;
+    }
+    Some(self.fluid_level(pos))
+  }
+
+  fn set_fluid(
+    &mut self,
+    pos: &[i64; 3],
+    voxel: u8,
+    level: u8,
+    changed: &mut HashSet<[i64; 3]>
+  ) {
+    let value = if level == 0 { 0 } else { voxel };
+    if level == 0 || level == MAX_FLUID_LEVEL {
+      self.fluid.levels.remove(pos);
+    } else {
+      self.fluid.levels.insert(*pos, level);
+    }
+
+    if self.get_voxel(pos) != value {
+      self.set_loaded_voxel(pos, value);
+    }
+    changed.insert(*pos);
+    self.wake_fluid(pos);
+  }
+
+  fn wake_fluid(&mut self, pos: &[i64; 3]) {
+    self.fluid.active.insert(*pos);
+    for n in NEIGHBORS.iter() {
+      self.fluid.active.insert([pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]]);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use crate::chunk::simulation::tests::loaded;
+  use crate::chunk::coords::WorldVoxelPos;
+  use super::MAX_FLUID_LEVEL;
+
+  const STONE: u8 = 1;
+  const WATER: u8 = 2;
+
+  fn basin() -> ChunkManager {
+    let mut chunk_manager = loaded();
+    chunk_manager.materials.get_mut(WATER).unwrap().liquid = true;
+
+    // Stone floor at 110 with walls around 1..11
+    for x in 0..12 {
+      for z in 0..12 {
+        chunk_manager.set_voxel2(&[x, 110, z], STONE);
+        if x == 0 || x == 11 || z == 0 || z == 11 {
+          for y in 111..114 {
+            chunk_manager.set_voxel2(&[x, y, z], STONE);
+          }
+        }
+      }
+    }
+    chunk_manager
+  }
+
+  fn pour(chunk_manager: &mut ChunkManager) {
+    let mut edits = Vec::new();
+    for y in 111..115 {
+      chunk_manager.set_voxel2(&[5, y, 5], WATER);
+      edits.push([5, y, 5]);
+    }
+    chunk_manager.wake_fluids(&edits);
+  }
+
+  fn volume(chunk_manager: &ChunkManager) -> u32 {
+    let mut total = 0;
+    for x in 1..11 {
+      for y in 111..116 {
+        for z in 1..11 {
+          total += chunk_manager.fluid_level(&[x, y, z]) as u32;
+        }
+      }
+    }
+    total
+  }
+
+  #[test]
+  fn test_fluid_flows_and_settles() -> Result<(), String> {
+    let mut chunk_manager = basin();
+    pour(&mut chunk_manager);
+    let start = volume(&chunk_manager);
+    assert_eq!(start, 4 * MAX_FLUID_LEVEL as u32);
+
+    let mut ticks = 0;
+    while !chunk_manager.fluid.active.is_empty() {
+      chunk_manager.tick_fluids();
+      assert_eq!(volume(&chunk_manager), start);
+      ticks += 1;
+      assert!(ticks < 500, "not settled");
+    }
+    assert!(chunk_manager.tick_fluids().is_empty());
+
+    // Spread over the floor, the walls keep it in
+    for x in 0..12 {
+      for z in 0..12 {
+        assert_eq!(chunk_manager.get_voxel(&[x, 110, z]), STONE);
+        for y in 112..116 {
+          assert_eq!(chunk_manager.fluid_level(&[x, y, z]), 0);
+        }
+        if x == 0 || x == 11 || z == 0 || z == 11 {
+          assert_eq!(chunk_manager.get_voxel(&[x, 111, z]), STONE);
+          continue;
+        }
+        let level = chunk_manager.fluid_level(&[x, 111, z]) as i32;
+        for [dx, dz] in [[1, 0], [0, 1]] {
+          let next = [x + dx, 111, z + dz];
+          if chunk_manager.get_voxel(&next) == STONE {
+            continue;
+          }
+          let next_level = chunk_manager.fluid_level(&next) as i32;
+          assert!((level - next_level).abs() <= 1, "at {} {}", x, z);
+        }
+      }
+    }
+    assert!(chunk_manager.fluid_level(&[4, 111, 5]) > 0);
+    Ok(())
+  }
+
+  #[test]
+  fn test_fluid_unload() -> Result<(), String> {
+    let mut chunk_manager = basin();
+    pour(&mut chunk_manager);
+    for _ in 0..3 {
+      chunk_manager.tick_fluids();
+    }
+    let layout = chunk_manager.layout();
+    let owner = |pos: &[i64; 3]| WorldVoxelPos(*pos).key(&layout).0;
+    let key = [0, 8, 0];
+    assert!(chunk_manager.fluid.levels.keys().any(|p| owner(p) == key));
+    assert!(chunk_manager.fluid.active.active.iter().any(|p| owner(p) == key));
+
+    // Parked under chunks that aren't loaded
+    let pending = &mut chunk_manager.fluid.active.pending;
+    pending.entry([0, 20, 0]).or_default().insert([5, 113, 5]);
+    pending.entry([0, 21, 0]).or_default().insert([5, 113, 5]);
+    pending.entry([0, 21, 0]).or_default().insert([5, 111, 5]);
+
+    // The state of the removed chunk goes with it, the rest is kept
+    let levels = chunk_manager.fluid.levels.len();
+    chunk_manager.remove_chunk(&key);
+    assert!(chunk_manager.get_chunk(&key).is_none());
+    assert!(chunk_manager.fluid.levels.len() < levels);
+    assert!(chunk_manager.fluid.levels.keys().all(|p| owner(p) != key));
+    assert!(chunk_manager.fluid.active.active.iter().all(|p| owner(p) != key));
+    assert!(chunk_manager.fluid.active.active.iter().any(|p| owner(p) != key));
+
+    let pending = &chunk_manager.fluid.active.pending;
+    assert!(!pending.contains_key(&[0, 20, 0]));
+    assert_eq!(pending[&[0, 21, 0]].iter().collect::<Vec<_>>(), vec![&[5, 111, 5]]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_fluid_deterministic() -> Result<(), String> {
+    let mut first = basin();
+    let mut second = basin();
+    pour(&mut first);
+    pour(&mut second);
+    for _ in 0..20 {
+      assert_eq!(first.tick_fluids(), second.tick_fluids());
+    }
+    assert_eq!(first.fluid.levels, second.fluid.levels);
+
+    // Waits above the chunk that isn't loaded
+    let pos = [5, 100, 5];
+    first.set_voxel2(&pos, WATER);
+    first.wake_fluids(&vec![pos]);
+    first.tick_fluids();
+    assert_eq!(first.fluid_level(&pos), MAX_FLUID_LEVEL);
+    assert!(first.fluid.active.is_empty());
+    let keys: Vec<[i64; 3]> = first.fluid.active.pending.keys().cloned().collect();
+    assert_eq!(keys.len(), 1);
+    let key = keys[0];
+    assert!(first.get_chunk(&key).is_none());
+    assert!(first.fluid.active.pending[&key].contains(&pos));
+
+    // Flows once the chunk loads
+    let chunk = ChunkManager::new_chunk(&key, first.depth as u8, 0, first.noise);
+    first.set_chunk(&key, &chunk);
+    assert!(first.fluid.active.pending.is_empty());
+    first.tick_fluids();
+    assert_eq!(first.fluid_level(&pos), 0);
+    assert_eq!(first.fluid_level(&[5, 99, 5]), MAX_FLUID_LEVEL);
+    Ok(())
+  }
+}
@@ -0,0 +1,225 @@
+use super::chunk_manager::ChunkManager;
+
+/// Farthest a grain slides sideways at once, for the shallow piles
+pub const MAX_SLIDE: i64 = 4;
+/// Air voxels counted below a slide target
+const MAX_DROP: i64 = 16;
+
+const SIDES: [[i64; 3]; 8] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 0, 1], [0, 0, -1],
+  [1, 0, 1], [-1, 0, -1],
+  [1, 0, -1], [-1, 0, 1],
+];
+
+impl ChunkManager {
+  /// Wakes the grains around the voxels that were set
+  pub fn wake_grains(&mut self, positions: &Vec<[i64; 3]>) {
+    for pos in positions.iter() {
+      self.wake_grain(pos);
+    }
+  }
+
+  /**
+    Moves the active granular voxels one step: down into air, or to the side
+    when the pile is steeper than the angle of repose of the material. Grains
+    in or above chunks that aren't loaded wait for them. Returns the changed
+    voxels, sorted
+  */
+  pub fn tick_grains(&mut self) -> Vec<[i64; 3]> {
+    self.tick_active(|c| &mut c.granular, |c, pos, changed| {
+      let voxel = c.get_voxel(pos);
+      if !c.materials.is_granular(voxel) {
+        return;
+      }
+
+      let below = [pos[0], pos[1] - 1, pos[2]];
+      let target = if c.get_voxel(&below) == 0 {
+        Some(below)
+      } else {
+        c.slide_target(pos, voxel)
+      };
+      if let Some(target) = target {
+        c.set_loaded_voxel(pos, 0);
+        c.set_loaded_voxel(&target, voxel);
+        changed.insert(*pos);
+        changed.insert(target);
+        c.granular.insert(target);
+        c.wake_grain(pos);
+      }
+    })
+  }
+
+  /**
+    Air voxel next to the top grain it slides into, the one above the deepest
+    drop steeper than the slope. A drop of one is always stable, the grain
+    would slide back
+  */
+  fn slide_target(&self, pos: &[i64; 3], voxel: u8) -> Option<[i64; 3]> {
+    let above = [pos[0], pos[1] + 1, pos[2]];
+    if self.get_voxel(&above) != 0 {
+      return None;
+    }
+
+    // Ties go to a side turning with the ticks, so piles spread evenly
+    let slope = self.materials.repose_slope(voxel);
+    let mut target: Option<(i64, [i64; 3])> = None;
+    for i in 0..SIDES.len() {
+      let side = SIDES[(i + self.granular.ticks as usize) % SIDES.len()];
+      let length = ((side[0] * side[0] + side[2] * side[2]) as f32).sqrt();
+      for dist in 1..=MAX_SLIDE {
+        let next = [pos[0] + side[0] * dist, pos[1], pos[2] + side[2] * dist];
+        if !self.voxel_loaded(&next) || self.get_voxel(&next) != 0 {
+          break;
+        }
+
+        let drop = self.air_below(&next);
+        if drop >= 2 && drop as f32 > slope * dist as f32 * length {
+          if target.map(|(d, _)| drop > d).unwrap_or(true) {
+            target = Some((drop, next));
+          }
+          break;
+        }
+      }
+    }
+    target.map(|(_, p)| p)
+  }
+
+  /// Air voxels from the voxel down, up to MAX_DROP
+  fn air_below(&self, pos: &[i64; 3]) -> i64 {
+    let mut drop = 0;
+    while drop < MAX_DROP {
+      let p = [pos[0], pos[1] - drop, pos[2]];
+      if !self.voxel_loaded(&p) || self.get_voxel(&p) != 0 {
+        break;
+      }
+      drop += 1;
+    }
+    drop
+  }
+
+  /// Wakes the voxel and the grains that can move into it once it is air,
+  /// the ones above and beside it, diagonals too
+  fn wake_grain(&mut self, pos: &[i64; 3]) {
+    for x in -1..2 {
+      for y in 0..2 {
+        for z in -1..2 {
+          self.granular.insert([pos[0] + x, pos[1] + y, pos[2] + z]);
+        }
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use crate::chunk::simulation::tests::loaded;
+
+  const STONE: u8 = 1;
+  const SAND: u8 = 3;
+
+  fn floor(repose: f32) -> ChunkManager {
+    let mut chunk_manager = loaded();
+    let sand = chunk_manager.materials.get_mut(SAND).unwrap();
+    sand.granular = true;
+    sand.repose = repose;
+    for x in -10..24 {
+      for z in -10..24 {
+        chunk_manager.set_voxel2(&[x, 110, z], STONE);
+      }
+    }
+    chunk_manager
+  }
+
+  fn settle(chunk_manager: &mut ChunkManager) {
+    let mut ticks = 0;
+    while !chunk_manager.granular.active.is_empty() {
+      chunk_manager.tick_grains();
+      ticks += 1;
+      assert!(ticks < 500, "not settled");
+    }
+  }
+
+  /// Sand column heights on the floor from -10, 0 without sand
+  fn heights(chunk_manager: &ChunkManager) -> Vec<Vec<i64>> {
+    let mut heights = vec![vec![0; 34]; 34];
+    for x in 0..34 {
+      for z in 0..34 {
+        for y in 111..130 {
+          if chunk_manager.get_voxel(&[x - 10, y, z - 10]) == SAND {
+            heights[x as usize][z as usize] = y - 110;
+          }
+        }
+      }
+    }
+    heights
+  }
+
+  fn pile(repose: f32) -> Vec<Vec<i64>> {
+    let mut chunk_manager = floor(repose);
+    let mut edits = Vec::new();
+    for y in 111..123 {
+      chunk_manager.set_voxel2(&[7, y, 7], SAND);
+      edits.push([7, y, 7]);
+    }
+    chunk_manager.wake_grains(&edits);
+    settle(&mut chunk_manager);
+    assert!(chunk_manager.tick_grains().is_empty());
+
+    let heights = heights(&chunk_manager);
+    let count: i64 = heights.iter().flatten().sum();
+    assert_eq!(count, 12, "grains lost or floating");
+    heights
+  }
+
+  #[test]
+  fn test_grains_fall() -> Result<(), String> {
+    let mut chunk_manager = floor(45.0);
+    chunk_manager.set_voxel2(&[5, 115, 5], STONE);
+    chunk_manager.set_voxel2(&[5, 116, 5], SAND);
+    chunk_manager.set_voxel2(&[5, 117, 5], SAND);
+
+    // Removing the support
+    chunk_manager.set_voxel2(&[5, 115, 5], 0);
+    chunk_manager.wake_grains(&vec![[5, 115, 5]]);
+    settle(&mut chunk_manager);
+    assert_eq!(chunk_manager.get_voxel(&[5, 111, 5]), SAND);
+    assert_eq!(chunk_manager.get_voxel(&[5, 116, 5]), 0);
+
+    // Two grains stacked on the floor are steeper than 45 degrees
+    let heights = heights(&chunk_manager);
+    assert_eq!(heights[15][15], 1);
+    assert_eq!(heights.iter().flatten().sum::<i64>(), 2);
+    Ok(())
+  }
+
+  /// Largest height difference of adjacent columns
+  fn max_step(heights: &Vec<Vec<i64>>) -> i64 {
+    let mut step = 0;
+    for x in 0..33 {
+      for z in 0..33 {
+        step = step.max((heights[x][z] - heights[x + 1][z]).abs());
+        step = step.max((heights[x][z] - heights[x][z + 1]).abs());
+      }
+    }
+    step
+  }
+
+  #[test]
+  fn test_angle_of_repose() -> Result<(), String> {
+    // tan(75) is 3.7, steps of 3 voxels hold
+    let steep = pile(75.0);
+    assert!(max_step(&steep) <= 3);
+
+    // Shallower piles spread wider and lower
+    let shallow = pile(20.0);
+    assert!(max_step(&shallow) <= 1);
+    let top = |h: &Vec<Vec<i64>>| *h.iter().flatten().max().unwrap();
+    let width = |h: &Vec<Vec<i64>>| h.iter().flatten().filter(|v| **v > 0).count();
+    assert!(top(&shallow) < top(&steep), "{} {}", top(&shallow), top(&steep));
+    assert!(width(&shallow) > width(&steep));
+    Ok(())
+  }
+}
@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use super::chunk_manager::ChunkManager;
+use super::connectivity::{AnchorRule, Island};
+
+/// Stress of the solid voxels not connected to any anchor
+pub const UNSUPPORTED: u32 = u32::MAX;
+
+const NEIGHBORS: [[i64; 3]; 6] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 1, 0], [0, -1, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+
+/**
+  Stress of the solid voxels inside min..=max. The stress is the shortest
+  solid path from an anchor, where going up is free and going sideways or
+  down costs 1, so pillars carry no stress and spans carry more the farther
+  they reach. Liquids are not structural and count as air
+*/
+#[derive(Clone, Debug)]
+pub struct StressMap {
+  pub min: [i64; 3],
+  pub max: [i64; 3],
+  pub anchor: AnchorRule,
+  size: [usize; 3],
+  values: Vec<u8>,
+  strength: Vec<f32>,
+  stress: Vec<u32>,
+}
+
+impl ChunkManager {
+  /// Unloaded chunks are sampled from the terrain generator
+  pub fn stress_map(&self, min: [i64; 3], max: [i64; 3], anchor: AnchorRule) -> StressMap {
+    let size = if (0..3).any(|i| min[i] > max[i]) {
+      [0; 3]
+    } else {
+      [
+        (max[0] - min[0] + 1) as usize,
+        (max[1] - min[1] + 1) as usize,
+        (max[2] - min[2] + 1) as usize,
+      ]
+    };
+    let len = size[0] * size[1] * size[2];
+
+    let mut map = StressMap {
+      min: min,
+      max: max,
+      anchor: anchor,
+      size: size,
+      values: vec![0; len],
+      strength: vec![0.0; len],
+      stress: Vec::new(),
+    };
+    for i in 0..len {
+      let pos = map.pos(i);
+      let voxel = self.voxel_or_generated(&pos);
+      if voxel == 0 || self.materials.is_liquid(voxel) {
+        continue;
+      }
+      map.values[i] = voxel;
+      map.strength[i] = self.materials.strength(voxel);
+    }
+    map.stress = map.spread(&vec![false; len]);
+    map
+  }
+}
+
+impl StressMap {
+  /// None for air and voxels outside of the map
+  pub fn stress(&self, pos: &[i64; 3]) -> Option<u32> {
+    let i = self.index(pos)?;
+    if self.values[i] == 0 {
+      return None;
+    }
+    Some(self.stress[i])
+  }
+
+  /**
+    Stress over the strength of the voxel, above 1.0 breaks it. Infinite for
+    unsupported voxels, None for air and voxels outside of the map
+  */
+  pub fn load(&self, pos: &[i64; 3]) -> Option<f32> {
+    let i = self.index(pos)?;
+    if self.values[i] == 0 {
+      return None;
+    }
+    Some(self.load_at(i))
+  }
+
+  /// Load of every solid voxel, for the debug heatmaps
+  pub fn loads(&self) -> Vec<([i64; 3], f32)> {
+    (0..self.values.len())
+      .filter(|i| self.values[*i] > 0)
+      .map(|i| (self.pos(i), self.load_at(i)))
+      .collect()
+  }
+
+  /**
+    Supported voxels that collapse: the overloaded ones and the ones they
+    held up, repeated until the rest carries its stress. Grouped by face
+    adjacency, the voxels that were unsupported already are left to
+    ChunkManager::detached_islands
+  */
+  pub fn collapsing_islands(&self) -> Vec<Island> {
+    let len = self.values.len();
+    let mut broken = vec![false; len];
+    let mut stress = self.stress.clone();
+    loop {
+      let mut breaking = false;
+      for i in 0..len {
+        let supported = self.stress[i] != UNSUPPORTED && stress[i] != UNSUPPORTED;
+        if self.values[i] > 0 && !broken[i] && supported
+          && stress[i] as f32 > self.strength[i]
+        {
+          broken[i] = true;
+          breaking = true;
+        }
+      }
+      if !breaking {
+        break;
+      }
+      stress = self.spread(&broken);
+    }
+
+    let collapsing: Vec<bool> = (0..len)
+      .map(|i| {
+        self.values[i] > 0 && self.stress[i] != UNSUPPORTED
+          && (broken[i] || stress[i] == UNSUPPORTED)
+      })
+      .collect();
+
+    let mut islands = Vec::new();
+    let mut visited = vec![false; len];
+    let mut queue = VecDeque::new();
+    for start in 0..len {
+      if visited[start] || !collapsing[start] {
+        continue;
+      }
+      visited[start] = true;
+      queue.push_back(start);
+
+      let mut island = Island::default();
+      while let Some(i) = queue.pop_front() {
+        let pos = self.pos(i);
+        island.voxels.push((pos, self.values[i]));
+        for n in NEIGHBORS.iter() {
+          let next = [pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]];
+          if let Some(next_index) = self.index(&next) {
+            if !visited[next_index] && collapsing[next_index] {
+              visited[next_index] = true;
+              queue.push_back(next_index);
+            }
+          }
+        }
+      }
+      islands.push(island);
+    }
+    islands
+  }
+
+  /// Stress from the anchors through the solid voxels that aren't broken,
+  /// a 0-1 breadth first search as the steps cost 0 or 1
+  fn spread(&self, broken: &Vec<bool>) -> Vec<u32> {
+    let len = self.values.len();
+    let solid = |i: usize| self.values[i] > 0 && !broken[i];
+    let mut stress = vec![UNSUPPORTED; len];
+    let mut queue = VecDeque::new();
+    for i in 0..len {
+      if solid(i) && self.anchor.is_anchor(&self.pos(i), &self.min, &self.max) {
+        stress[i] = 0;
+        queue.push_back(i);
+      }
+    }
+
+    while let Some(i) = queue.pop_front() {
+      let pos = self.pos(i);
+      for n in NEIGHBORS.iter() {
+        let next = [pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]];
+        let next_index = match self.index(&next) {
+          Some(index) => index,
+          None => continue,
+        };
+        if !solid(next_index) {
+          continue;
+        }
+
+        let cost = if n[1] == 1 { 0 } else { 1 };
+        let next_stress = stress[i] + cost;
+        if next_stress < stress[next_index] {
+          stress[next_index] = next_stress;
+          if cost == 0 {
+            queue.push_front(next_index);
+          } else {
+            queue.push_back(next_index);
+          }
+        }
+      }
+    }
+    stress
+  }
+
+  fn load_at(&self, i: usize) -> f32 {
+    if self.stress[i] == UNSUPPORTED {
+      return f32::INFINITY;
+    }
+    self.stress[i] as f32 / self.strength[i].max(f32::EPSILON)
+  }
+
+  fn index(&self, pos: &[i64; 3]) -> Option<usize> {
+    if (0..3).any(|i| pos[i] < self.min[i] || pos[i] >= self.min[i] + self.size[i] as i64) {
+      return None;
+    }
+    let x = (pos[0] - self.min[0]) as usize;
+    let y = (pos[1] - self.min[1]) as usize;
+    let z = (pos[2] - self.min[2]) as usize;
+    Some((x * self.size[1] + y) * self.size[2] + z)
+  }
+
+  fn pos(&self, i: usize) -> [i64; 3] {
+    let z = i % self.size[2];
+    let y = (i / self.size[2]) % self.size[1];
+    let x = i / (self.size[1] * self.size[2]);
+    [self.min[0] + x as i64, self.min[1] + y as i64, self.min[2] + z as i64]
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use crate::chunk::connectivity::AnchorRule;
+
+  const STONE: u8 = 1;
+  const WOOD: u8 = 2;
+
+  /// Pillar from the floor at 100 up to 105, with a beam of the length
+  fn cantilever(length: i64) -> ChunkManager {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.materials.get_mut(WOOD).unwrap().strength = 4.0;
+    for y in 100..106 {
+      chunk_manager.set_voxel2(&[0, y, 0], STONE);
+    }
+    for x in 1..=length {
+      chunk_manager.set_voxel2(&[x, 105, 0], WOOD);
+    }
+    chunk_manager
+  }
+
+  #[test]
+  fn test_beam_stress() -> Result<(), String> {
+    let chunk_manager = cantilever(3);
+    let map = chunk_manager.stress_map([-5, 95, -5], [10, 110, 5], AnchorRule::Floor(100));
+    assert_eq!(map.stress(&[0, 100, 0]), Some(0));
+    assert_eq!(map.stress(&[0, 105, 0]), Some(0));
+    assert_eq!(map.stress(&[3, 105, 0]), Some(3));
+    assert_eq!(map.stress(&[3, 106, 0]), None);
+    assert_eq!(map.load(&[2, 105, 0]), Some(0.5));
+    assert_eq!(map.loads().len(), 9);
+    assert!(map.collapsing_islands().is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_overloaded_span_collapses() -> Result<(), String> {
+    let mut chunk_manager = cantilever(7);
+
+    // Hanging down from the end of the beam
+    chunk_manager.set_voxel2(&[7, 104, 0], WOOD);
+    let map = chunk_manager.stress_map([-5, 95, -5], [10, 110, 5], AnchorRule::Floor(100));
+    assert!(map.load(&[5, 105, 0]).unwrap() > 1.0);
+
+    let islands = map.collapsing_islands();
+    assert_eq!(islands.len(), 1);
+    let island = &islands[0];
+    assert_eq!(island.voxels.len(), 4);
+    assert_eq!(island.bounds(), ([5, 104, 0], [7, 105, 0]));
+
+    // A second pillar under the end carries it
+    for y in 100..105 {
+      chunk_manager.set_voxel2(&[7, y, 0], STONE);
+    }
+    let map = chunk_manager.stress_map([-5, 95, -5], [10, 110, 5], AnchorRule::Floor(100));
+    assert_eq!(map.stress(&[4, 105, 0]), Some(3));
+    assert!(map.collapsing_islands().is_empty());
+
+    // Unsupported voxels are left to the detached islands
+    chunk_manager.set_voxel2(&[3, 108, 0], STONE);
+    let map = chunk_manager.stress_map([-5, 95, -5], [10, 110, 5], AnchorRule::Floor(100));
+    assert_eq!(map.load(&[3, 108, 0]), Some(f32::INFINITY));
+    assert!(map.collapsing_islands().is_empty());
+    Ok(())
+  }
+}
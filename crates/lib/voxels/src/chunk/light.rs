@@ -0,0 +1,568 @@
+use std::collections::VecDeque;
+use hashbrown::{HashMap, HashSet};
+use crate::data::voxel_octree::MeshData;
+use crate::data::ambient_occlusion::vertex_cell;
+use super::coords::{ChunkLayout, WorldVoxelPos};
+use super::chunk_manager::{ChunkManager, ChunkMode};
+use super::MAX_ELEVATION;
+
+pub const MAX_LIGHT: u8 = 15;
+
+const NEIGHBORS: [[i64; 3]; 6] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 1, 0], [0, -1, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+const DOWN: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Channel {
+  Sky,
+  Block,
+}
+
+/// Light of the voxels a chunk owns, key * seamless_size up to the next key
+#[derive(Clone, Debug, Default)]
+pub struct ChunkLight {
+  pub sky: Vec<u8>,
+  pub block: Vec<u8>,
+}
+
+/// Per chunk light levels, only chunks that were lit have an entry
+#[derive(Clone, Debug, Default)]
+pub struct LightMap {
+  pub chunks: HashMap<[i64; 3], ChunkLight>,
+}
+
+impl LightMap {
+  fn index(pos: &[i64; 3], layout: &ChunkLayout) -> ([i64; 3], usize) {
+    let seamless_size = layout.seamless_size as i64;
+    let key = WorldVoxelPos(*pos).key(layout).0;
+    let x = pos[0] - key[0] * seamless_size;
+    let y = pos[1] - key[1] * seamless_size;
+    let z = pos[2] - key[2] * seamless_size;
+    (key, ((x * seamless_size + y) * seamless_size + z) as usize)
+  }
+
+  fn get(&self, channel: Channel, pos: &[i64; 3], layout: &ChunkLayout) -> Option<u8> {
+    let (key, index) = LightMap::index(pos, layout);
+    let light = self.chunks.get(&key)?;
+    match channel {
+      Channel::Sky => Some(light.sky[index]),
+      Channel::Block => Some(light.block[index]),
+    }
+  }
+
+  fn set(&mut self, channel: Channel, pos: &[i64; 3], value: u8, layout: &ChunkLayout) {
+    let (key, index) = LightMap::index(pos, layout);
+    if let Some(light) = self.chunks.get_mut(&key) {
+      match channel {
+        Channel::Sky => light.sky[index] = value,
+        Channel::Block => light.block[index] = value,
+      }
+    }
+  }
+}
+
+impl ChunkManager {
+  /// Block light the voxel value emits, from its material
+  pub fn emission(&self, voxel: u8) -> u8 {
+    self.materials.emissive(voxel)
+  }
+
+  /// [sky, block] light, None if the chunk owning the voxel isn't lit
+  pub fn get_light(&self, pos: &[i64; 3]) -> Option<[u8; 2]> {
+    let layout = self.layout();
+    Some([
+      self.light.get(Channel::Sky, pos, &layout)?,
+      self.light.get(Channel::Block, pos, &layout)?,
+    ])
+  }
+
+  pub fn remove_light(&mut self, key: &[i64; 3]) {
+    self.light.chunks.remove(key);
+  }
+
+  /**
+    Lights the loaded chunks that aren't lit yet: skylight columns, emissive
+    voxels and the light coming from lit adjacent chunks. Returns the keys of
+    the chunks with changed light, their meshes need to be recomputed
+  */
+  pub fn light_chunks(&mut self, keys: &Vec<[i64; 3]>) -> Vec<[i64; 3]> {
+    let s = self.seamless_size() as i64;
+    let layout = self.layout();
+    let mut light = std::mem::take(&mut self.light);
+
+    // Upper chunks first, so the columns below can continue their skylight
+    let mut new_keys: Vec<[i64; 3]> = keys
+      .iter()
+      .filter(|k| self.chunks.contains_key(*k) && !light.chunks.contains_key(*k))
+      .cloned()
+      .collect();
+    new_keys.sort_by(|a, b| b[1].cmp(&a[1]));
+    new_keys.dedup();
+    let new_set: HashSet<[i64; 3]> = new_keys.iter().cloned().collect();
+
+    let len = (s * s * s) as usize;
+    for key in new_keys.iter() {
+      light.chunks.insert(*key, ChunkLight {
+        sky: vec![0; len],
+        block: vec![0; len],
+      });
+    }
+
+    let top = self.sky_top();
+    let mut changed = HashSet::new();
+    let mut sky_queue = VecDeque::new();
+    let mut block_queue = VecDeque::new();
+    let mut sky_removal = VecDeque::new();
+    for key in new_keys.iter() {
+      let start = [key[0] * s, key[1] * s, key[2] * s];
+      for x in start[0]..start[0] + s {
+        for z in start[2]..start[2] + s {
+          let top_y = start[1] + s - 1;
+          let above = [x, top_y + 1, z];
+          let mut exposed = match light.get(Channel::Sky, &above, &layout) {
+            Some(v) => v == MAX_LIGHT,
+            None => !self.sky_blocked_above(&[x, top_y, z], top),
+          };
+
+          for y in (start[1]..=top_y).rev() {
+            let pos = [x, y, z];
+            let voxel = self.get_voxel(&pos);
+            exposed = exposed && voxel == 0;
+            if exposed {
+              light.set(Channel::Sky, &pos, MAX_LIGHT, &layout);
+              sky_queue.push_back(pos);
+              changed.insert(pos);
+            }
+
+            let emission = self.emission(voxel);
+            if emission > 0 {
+              light.set(Channel::Block, &pos, emission, &layout);
+              block_queue.push_back(pos);
+              changed.insert(pos);
+            }
+          }
+
+          // Lit column below assumed the sky wasn't blocked here
+          let below = [x, start[1] - 1, z];
+          let below_key = WorldVoxelPos(below).key(&layout).0;
+          if !exposed && !new_set.contains(&below_key)
+            && light.get(Channel::Sky, &below, &layout) == Some(MAX_LIGHT) {
+            light.set(Channel::Sky, &below, 0, &layout);
+            changed.insert(below);
+            sky_removal.push_back((below, MAX_LIGHT));
+          }
+        }
+      }
+
+      // Light flowing in from the lit adjacent chunks
+      let min = [start[0] - 1, start[1] - 1, start[2] - 1];
+      let max = [start[0] + s, start[1] + s, start[2] + s];
+      for x in min[0]..=max[0] {
+        for y in min[1]..=max[1] {
+          for z in min[2]..=max[2] {
+            let pos = [x, y, z];
+            let on_shell = (0..3).any(|i| pos[i] == min[i] || pos[i] == max[i]);
+            if !on_shell || new_set.contains(&WorldVoxelPos(pos).key(&layout).0) {
+              continue;
+            }
+            if light.get(Channel::Sky, &pos, &layout).unwrap_or(0) > 0 {
+              sky_queue.push_back(pos);
+            }
+            if light.get(Channel::Block, &pos, &layout).unwrap_or(0) > 0 {
+              block_queue.push_back(pos);
+            }
+          }
+        }
+      }
+    }
+
+    self.unpropagate(&mut light, Channel::Sky, sky_removal, &mut sky_queue, &mut changed);
+    self.propagate(&mut light, Channel::Sky, &mut sky_queue, &mut changed);
+    self.propagate(&mut light, Channel::Block, &mut block_queue, &mut changed);
+
+    self.light = light;
+    self.changed_light_keys(&changed)
+  }
+
+  /**
+    Updates the light around the voxels that were set, after setting them.
+    Returns the keys of the chunks with changed light
+  */
+  pub fn update_light(&mut self, positions: &Vec<[i64; 3]>) -> Vec<[i64; 3]> {
+    let layout = self.layout();
+    let mut light = std::mem::take(&mut self.light);
+    let top = self.sky_top();
+
+    let mut changed = HashSet::new();
+    let mut sky_removal = VecDeque::new();
+    let mut block_removal = VecDeque::new();
+    let mut sky_queue = VecDeque::new();
+    let mut block_queue = VecDeque::new();
+    for pos in positions.iter() {
+      let (sky, block) = match (
+        light.get(Channel::Sky, pos, &layout), light.get(Channel::Block, pos, &layout)
+      ) {
+        (Some(sky), Some(block)) => (sky, block),
+        _ => continue,
+      };
+      light.set(Channel::Sky, pos, 0, &layout);
+      light.set(Channel::Block, pos, 0, &layout);
+      sky_removal.push_back((*pos, sky));
+      block_removal.push_back((*pos, block));
+      changed.insert(*pos);
+
+      let voxel = self.get_voxel(pos);
+      let emission = self.emission(voxel);
+      if emission > 0 {
+        light.set(Channel::Block, pos, emission, &layout);
+        block_queue.push_back(*pos);
+      }
+      if voxel > 0 {
+        continue;
+      }
+
+      for n in NEIGHBORS.iter() {
+        let next = [pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]];
+        sky_queue.push_back(next);
+        block_queue.push_back(next);
+      }
+
+      let above = [pos[0], pos[1] + 1, pos[2]];
+      if light.get(Channel::Sky, &above, &layout).is_none()
+        && !self.sky_blocked_above(pos, top) {
+        light.set(Channel::Sky, pos, MAX_LIGHT, &layout);
+        sky_queue.push_back(*pos);
+      }
+    }
+
+    self.unpropagate(&mut light, Channel::Sky, sky_removal, &mut sky_queue, &mut changed);
+    self.unpropagate(&mut light, Channel::Block, block_removal, &mut block_queue, &mut changed);
+    self.propagate(&mut light, Channel::Sky, &mut sky_queue, &mut changed);
+    self.propagate(&mut light, Channel::Block, &mut block_queue, &mut changed);
+
+    self.light = light;
+    self.changed_light_keys(&changed)
+  }
+
+  /**
+    Stores [sky, block] light from 0.0 to 1.0 per mesh position, the brightest
+    of the voxels around the vertex. Left empty when the chunk isn't lit
+  */
+  pub fn apply_light(&self, mesh: &mut MeshData) {
+    for transparent in mesh.transparent.iter_mut() {
+      self.apply_light(transparent);
+    }
+    mesh.light.clear();
+    if !self.light.chunks.contains_key(&mesh.key) {
+      return;
+    }
+
+    let s = self.seamless_size() as i64;
+    let start = [mesh.key[0] * s, mesh.key[1] * s, mesh.key[2] * s];
+    let mut cache = HashMap::new();
+    for pos in mesh.positions.iter() {
+      let cell = vertex_cell(pos, self.voxel_scale);
+      let value = *cache.entry(cell).or_insert_with(|| {
+        let mut sky = 0;
+        let mut block = 0;
+        let mut lit = false;
+        for x in 0..2 {
+          for y in 0..2 {
+            for z in 0..2 {
+              let p = [
+                start[0] + cell[0] + x,
+                start[1] + cell[1] + y,
+                start[2] + cell[2] + z,
+              ];
+              if let Some([sky_light, block_light]) = self.get_light(&p) {
+                sky = sky.max(sky_light);
+                block = block.max(block_light);
+                lit = true;
+              }
+            }
+          }
+        }
+        if !lit {
+          sky = MAX_LIGHT;
+        }
+        [sky as f32 / MAX_LIGHT as f32, block as f32 / MAX_LIGHT as f32]
+      });
+      mesh.light.push(value);
+    }
+  }
+
+  fn propagate(
+    &self,
+    light: &mut LightMap,
+    channel: Channel,
+    queue: &mut VecDeque<[i64; 3]>,
+    changed: &mut HashSet<[i64; 3]>,
+  ) {
+    let layout = self.layout();
+    while let Some(pos) = queue.pop_front() {
+      let value = light.get(channel, &pos, &layout).unwrap_or(0);
+      if value <= 1 {
+        continue;
+      }
+
+      for (i, n) in NEIGHBORS.iter().enumerate() {
+        let next = [pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]];
+        let current = match light.get(channel, &next, &layout) {
+          Some(v) => v,
+          None => continue,
+        };
+        if self.get_voxel(&next) != 0 {
+          continue;
+        }
+
+        // Skylight goes straight down without getting dimmer
+        let new = if channel == Channel::Sky && i == DOWN && value == MAX_LIGHT {
+          MAX_LIGHT
+        } else {
+          value - 1
+        };
+        if current < new {
+          light.set(channel, &next, new, &layout);
+          changed.insert(next);
+          queue.push_back(next);
+        }
+      }
+    }
+  }
+
+  /// Removes the light that came from the removed positions, the brighter
+  /// voxels around them are queued to fill the light back in
+  fn unpropagate(
+    &self,
+    light: &mut LightMap,
+    channel: Channel,
+    mut removal: VecDeque<([i64; 3], u8)>,
+    refill: &mut VecDeque<[i64; 3]>,
+    changed: &mut HashSet<[i64; 3]>,
+  ) {
+    let layout = self.layout();
+    while let Some((pos, value)) = removal.pop_front() {
+      for (i, n) in NEIGHBORS.iter().enumerate() {
+        let next = [pos[0] + n[0], pos[1] + n[1], pos[2] + n[2]];
+        let current = match light.get(channel, &next, &layout) {
+          Some(v) => v,
+          None => continue,
+        };
+        if current == 0 {
+          continue;
+        }
+
+        let sky_column = channel == Channel::Sky
+          && i == DOWN
+          && value == MAX_LIGHT
+          && current == MAX_LIGHT;
+        if current >= value && !sky_column {
+          refill.push_back(next);
+          continue;
+        }
+
+        light.set(channel, &next, 0, &layout);
+        changed.insert(next);
+        removal.push_back((next, current));
+
+        if channel == Channel::Block {
+          let emission = self.emission(self.get_voxel(&next));
+          if emission > 0 {
+            light.set(channel, &next, emission, &layout);
+            refill.push_back(next);
+          }
+        }
+      }
+    }
+  }
+
+  /// Highest y that can block the sky, above it is open
+  fn sky_top(&self) -> i64 {
+    let s = self.seamless_size() as i64;
+    let max_key = self.chunks.keys().map(|k| k[1]).max().unwrap_or(0);
+    ((max_key + 1) * s).max(MAX_ELEVATION)
+  }
+
+  /// Whether a solid voxel is above pos, loaded or generated
+  fn sky_blocked_above(&self, pos: &[i64; 3], top: i64) -> bool {
+    let s = self.seamless_size() as i64;
+    let layout = self.layout();
+    let mut y = pos[1] + 1;
+    while y <= top {
+      let p = [pos[0], y, pos[2]];
+      let key = WorldVoxelPos(p).key(&layout).0;
+      let next_chunk_y = (key[1] + 1) * s;
+      match self.get_chunk(&key) {
+        Some(chunk) => {
+          match chunk.mode {
+            ChunkMode::Inner => return true,
+            ChunkMode::Empty => {
+              y = next_chunk_y;
+              continue;
+            }
+            _ => {}
+          }
+          if self.get_voxel(&p) > 0 {
+            return true;
+          }
+        }
+        None => {
+          if y >= MAX_ELEVATION {
+            y = next_chunk_y;
+            continue;
+          }
+          if self.voxel_or_generated(&p) > 0 {
+            return true;
+          }
+        }
+      }
+      y += 1;
+    }
+    false
+  }
+
+  fn changed_light_keys(&self, changed: &HashSet<[i64; 3]>) -> Vec<[i64; 3]> {
+    let mut keys = HashSet::new();
+    for pos in changed.iter() {
+      for (key, _) in WorldVoxelPos(*pos).chunks(&self.layout()) {
+        if self.chunks.contains_key(&key.0) {
+          keys.insert(key.0);
+        }
+      }
+    }
+    keys.into_iter().collect()
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use super::MAX_LIGHT;
+
+  fn load(chunk_manager: &mut ChunkManager, keys: &Vec<[i64; 3]>) {
+    for key in keys.iter() {
+      let chunk = ChunkManager::new_chunk(
+        key, chunk_manager.depth as u8, 0, chunk_manager.noise
+      );
+      chunk_manager.set_chunk(key, &chunk);
+    }
+  }
+
+  fn keys_around(key: [i64; 3]) -> Vec<[i64; 3]> {
+    let mut keys = Vec::new();
+    for x in -1..2 {
+      for y in -1..2 {
+        for z in -1..2 {
+          keys.push([key[0] + x, key[1] + y, key[2] + z]);
+        }
+      }
+    }
+    keys
+  }
+
+  #[test]
+  fn test_skylight_and_roof() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let keys = keys_around([0, 8, 0]);
+    load(&mut chunk_manager, &keys);
+    chunk_manager.light_chunks(&keys);
+
+    let pos = [5, 110, 5];
+    assert_eq!(chunk_manager.get_light(&pos), Some([MAX_LIGHT, 0]));
+
+    // Roof above, the column below is now lit from the sides
+    let mut edits = Vec::new();
+    for x in 0..11 {
+      for z in 0..11 {
+        chunk_manager.set_voxel2(&[x, 115, z], 1);
+        edits.push([x, 115, z]);
+      }
+    }
+    let changed = chunk_manager.update_light(&edits);
+    assert!(changed.contains(&[0, 8, 0]));
+
+    let sky = chunk_manager.get_light(&pos).unwrap()[0];
+    assert!(sky < MAX_LIGHT && sky > 0, "sky {}", sky);
+    assert_eq!(chunk_manager.get_light(&[5, 100, 5]).unwrap()[0], sky);
+    assert_eq!(chunk_manager.get_light(&[20, 110, 5]), Some([MAX_LIGHT, 0]));
+
+    // Removing the roof brings the skylight back
+    for pos in edits.iter() {
+      chunk_manager.set_voxel2(pos, 0);
+    }
+    chunk_manager.update_light(&edits);
+    assert_eq!(chunk_manager.get_light(&pos), Some([MAX_LIGHT, 0]));
+    assert_eq!(chunk_manager.get_light(&[5, 100, 5]), Some([MAX_LIGHT, 0]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_block_light_across_chunks() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.materials.get_mut(5).unwrap().emissive = 12;
+    let keys = keys_around([0, -8, 0]);
+    load(&mut chunk_manager, &keys);
+    chunk_manager.light_chunks(&keys);
+
+    // Carve a tunnel through the chunk border underground
+    let y = -8 * 14 + 5;
+    let mut edits = Vec::new();
+    for x in 5..25 {
+      chunk_manager.set_voxel2(&[x, y, 5], 0);
+      edits.push([x, y, 5]);
+    }
+    chunk_manager.update_light(&edits);
+    assert_eq!(chunk_manager.get_light(&[10, y, 5]), Some([0, 0]));
+
+    chunk_manager.set_voxel2(&[10, y, 5], 5);
+    chunk_manager.update_light(&vec![[10, y, 5]]);
+    assert_eq!(chunk_manager.get_light(&[10, y, 5]), Some([0, 12]));
+    assert_eq!(chunk_manager.get_light(&[15, y, 5]), Some([0, 7]));
+    assert_eq!(chunk_manager.get_light(&[21, y, 5]), Some([0, 1]));
+    assert_eq!(chunk_manager.get_light(&[10, y + 1, 5]), Some([0, 0]));
+
+    // Newly lit chunks get the light from their lit neighbors
+    let mut relit = ChunkManager::default();
+    relit.materials.get_mut(5).unwrap().emissive = 12;
+    relit.chunks = chunk_manager.chunks.clone();
+    relit.light_chunks(&vec![[0, -8, 0]]);
+    relit.light_chunks(&keys);
+    for x in 5..25 {
+      assert_eq!(relit.get_light(&[x, y, 5]), chunk_manager.get_light(&[x, y, 5]));
+    }
+
+    chunk_manager.set_voxel2(&[10, y, 5], 0);
+    chunk_manager.update_light(&vec![[10, y, 5]]);
+    assert_eq!(chunk_manager.get_light(&[15, y, 5]), Some([0, 0]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_apply_light() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let keys = keys_around([0, 0, 0]);
+    load(&mut chunk_manager, &keys);
+
+    let chunk = chunk_manager.get_chunk(&[0, 0, 0]).unwrap().clone();
+    let mut mesh = chunk.octree.compute_mesh(
+      crate::data::voxel_octree::VoxelMode::SurfaceNets,
+      &mut crate::data::surface_nets::VoxelReuse::new(chunk_manager.depth, 3),
+      &chunk_manager.colors,
+      chunk_manager.voxel_scale,
+      chunk.key,
+      0
+    );
+    chunk_manager.apply_light(&mut mesh);
+    assert!(mesh.light.is_empty());
+
+    chunk_manager.light_chunks(&keys);
+    chunk_manager.apply_light(&mut mesh);
+    assert_eq!(mesh.light.len(), mesh.positions.len());
+    assert!(mesh.light.iter().any(|l| l[0] == 1.0));
+    Ok(())
+  }
+}
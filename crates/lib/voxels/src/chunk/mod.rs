@@ -3,9 +3,19 @@ use noise::NoiseFn;
 use noise::OpenSimplex;
 use num_traits::Pow;
 use crate::data::voxel_octree::VoxelOctree;
+use crate::data::materials::MaterialRegistry;
 use self::chunk_manager::*;
 
 pub mod chunk_manager;
+pub mod raycast;
+pub mod apron;
+pub mod connectivity;
+pub mod light;
+pub mod fluid;
+pub mod granular;
+pub mod simulation;
+pub mod integrity;
+pub mod coords;
 
 
 pub fn is_adjacent(key1: &[i64; 3], key2: &[i64; 3]) -> bool {
@@ -529,8 +539,9 @@ pub fn world_key_to_pos(key: &[i64; 3], seamless_size: u32) -> [i64; 3] {
   ]
 }
 
-/* TODO: Might be convert what voxel_pos_to_key() implementation */
-pub fn world_pos_to_key(pos: &[i64; 3], seamless_size: u32) -> [i64; 3] {
+/// Off by one on the negative multiples of seamless_size, only kept for the
+/// region keys. Voxel positions use coords::WorldVoxelPos::key
+fn world_pos_to_key(pos: &[i64; 3], seamless_size: u32) -> [i64; 3] {
   let mut x = pos[0];
   let mut y = pos[1];
   let mut z = pos[2];
@@ -575,6 +586,8 @@ pub fn region_pos_to_world_key(pos: &[u32; 3], seamless_size: u32) -> [i64; 3] {
   world_pos_to_key(&world_pos, seamless_size)
 }
 
+/// Same as coords::WorldVoxelPos::key
+#[deprecated(note = "use coords::WorldVoxelPos::key")]
 pub fn voxel_pos_to_key(pos: &[i64; 3], seamless_size: u32) -> [i64; 3] {
   let seamless_size_i64 = seamless_size as i64;
 
@@ -644,57 +657,43 @@ pub fn same_coord2(pos1: &[i64; 4], pos2: &[i64; 4]) -> bool {
   true
 }
 
-pub fn chunk_mode(octree: &VoxelOctree) -> ChunkMode {
-  let mut mode = ChunkMode::None;
-
-  let size = octree.get_size();
-  // let start = 1;
-  // let end = size - 1;
-
-  let start = 1;
-  let end = size - 2;
-
-  let mut is_air = false;
-  let mut has_value = false;
-  for x in start..end {
-    for y in start..end {
-      for z in start..end {
-        let voxel = octree.get_voxel(x, y, z);
-
-        if voxel == 1 {
-          has_value = true;
-        }
-        
-        if voxel == 0 {
-          is_air = true;
-        }
-        
-      }
-    }
-  }
-  if (!is_air && has_value) || (is_air && !has_value) {
-    mode = ChunkMode::Air;  // Should be renamed as empty
+/// Classifies the octree by whether it has both air and opaque voxels. The
+/// transparent and liquid materials are meshed apart, chunks holding them
+/// are never Inner, and only chunks of air are Empty
+pub fn chunk_mode(octree: &VoxelOctree, materials: &MaterialRegistry) -> ChunkMode {
+  match octree.air_and_solid(materials) {
+    (true, true) => ChunkMode::Surface,
+    (false, true) => ChunkMode::Inner,
+    // Without materials every non-zero voxel counts as solid
+    _ if octree.air_and_solid(&MaterialRegistry::default()).1 => ChunkMode::Surface,
+    _ => ChunkMode::Empty,
   }
-  // if has_value && !is_air {
-  //   mode = ChunkMode::Inner;
-  // }
-  if is_air && has_value {
-    mode = ChunkMode::Loaded;
-  }
-  // println!("{} {}", is_air, has_value);
-  mode
 }
 
 fn noise_elevation(x: &u32, z: &u32, middle: &i64, noise: OpenSimplex) -> i64 {
+  world_elevation(*x as i64 - middle, *z as i64 - middle, noise)
+}
+
+/// Generated terrain is air at and above this y
+pub const MAX_ELEVATION: i64 = 16;
+
+fn world_elevation(x: i64, z: i64, noise: OpenSimplex) -> i64 {
   let frequency = 0.0125;
-  let height_scale = 16.0;
-  let fx = (*x as i64 - middle) as f64 * frequency;
-  let fz = (*z as i64 - middle) as f64 * frequency;
+  let height_scale = MAX_ELEVATION as f64;
+  let fx = x as f64 * frequency;
+  let fz = z as f64 * frequency;
   let noise = noise.get([fx, fz]);
   let elevation = (noise * height_scale) as i64;
   elevation
 }
 
+/// Voxel value ChunkManager::new_chunk() generates at the world voxel position,
+/// without building the chunk
+pub fn generated_voxel(pos: &[i64; 3], noise: OpenSimplex) -> u8 {
+  let elevation = world_elevation(pos[0], pos[2], noise);
+  if pos[1] < elevation { 1 } else { 0 }
+}
+
 pub fn get_dist(pos1: &[i64; 3], pos2: &[i64; 3]) -> f32 {
   let mut dist_sqr = 0;
   for (index, val) in pos1.iter().enumerate() {
@@ -750,6 +749,7 @@ mod tests {
   use super::*;
 
   #[test]
+  #[allow(deprecated)]
   fn test_voxel_pos_to_key() -> Result<(), String> {
     let chunk_size = 14;
 
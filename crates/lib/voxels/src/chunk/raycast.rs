@@ -0,0 +1,243 @@
+use super::chunk_manager::ChunkManager;
+use super::generated_voxel;
+
+/// Longest ray in voxels, longer or infinite max_dist values are clamped
+pub const MAX_RAYCAST_VOXELS: f32 = 4096.0;
+
+/// Result of a voxel traversal, positions are in voxel coordinates.
+/// Multiply by voxel_scale to get the world position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+  pub voxel: [i64; 3],
+  pub value: u8,
+  /// Normal of the face the ray entered the voxel through
+  pub normal: [i64; 3],
+  /// Air voxel in front of the hit face, voxel + normal
+  pub air: [i64; 3],
+  /// World distance from the origin to the hit face
+  pub dist: f32,
+}
+
+impl ChunkManager {
+  /**
+    Amanatides-Woo voxel traversal from a world position.
+    Voxel coordinate p covers the cell centered at p * voxel_scale, the same
+    cell the nearest coord rounding picks. The voxel containing the origin is
+    skipped. Unloaded chunks are sampled from the terrain generator, as that is
+    what they contain once loaded.
+  */
+  pub fn raycast(
+    &self,
+    origin: [f32; 3],
+    dir: [f32; 3],
+    max_dist: f32
+  ) -> Option<RaycastHit> {
+    let len = (dir[0] * dir[0] + dir[1] * dir[1] + dir[2] * dir[2]).sqrt();
+    if len == 0.0 || !len.is_finite() || origin.iter().any(|v| !v.is_finite()) {
+      return None;
+    }
+
+    let scale = self.voxel_scale;
+    if max_dist.is_nan() {
+      return None;
+    }
+    let max_dist = max_dist.min(MAX_RAYCAST_VOXELS * scale);
+    let mut voxel = [0_i64; 3];
+    let mut step = [0_i64; 3];
+    let mut t_max = [f32::MAX; 3];
+    let mut t_delta = [f32::MAX; 3];
+    for i in 0..3 {
+      let d = dir[i] / len;
+      let cell_pos = origin[i] / scale + 0.5;
+      voxel[i] = cell_pos.floor() as i64;
+
+      if d > 0.0 {
+        step[i] = 1;
+        t_max[i] = ((voxel[i] + 1) as f32 - cell_pos) * scale / d;
+        t_delta[i] = scale / d;
+      }
+      if d < 0.0 {
+        step[i] = -1;
+        t_max[i] = (voxel[i] as f32 - cell_pos) * scale / d;
+        t_delta[i] = -scale / d;
+      }
+    }
+
+    loop {
+      let mut axis = 0;
+      if t_max[1] < t_max[axis] {
+        axis = 1;
+      }
+      if t_max[2] < t_max[axis] {
+        axis = 2;
+      }
+
+      let dist = t_max[axis];
+      if dist > max_dist {
+        return None;
+      }
+
+      voxel[axis] += step[axis];
+      t_max[axis] += t_delta[axis];
+
+      let value = self.voxel_or_generated(&voxel);
+      if value == 0 {
+        continue;
+      }
+
+      let mut normal = [0; 3];
+      normal[axis] = -step[axis];
+      return Some(RaycastHit {
+        voxel: voxel,
+        value: value,
+        normal: normal,
+        air: [
+          voxel[0] + normal[0],
+          voxel[1] + normal[1],
+          voxel[2] + normal[2],
+        ],
+        dist: dist,
+      });
+    }
+  }
+
+  /// Loaded voxel, or the generated terrain voxel when the chunk isn't loaded
+  pub(crate) fn voxel_or_generated(&self, pos: &[i64; 3]) -> u8 {
+    match self.get_voxel_safe(pos) {
+      Some(v) => v,
+      None => generated_voxel(pos, self.noise),
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+  use crate::chunk::generated_voxel;
+
+  #[test]
+  fn test_raycast_down() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.set_voxel2(&[3, 100, -5], 4);
+
+    let hit = chunk_manager.raycast([3.0, 110.0, -5.0], [0.0, -1.0, 0.0], 20.0);
+    let hit = hit.unwrap();
+    assert_eq!(hit.voxel, [3, 100, -5]);
+    assert_eq!(hit.value, 4);
+    assert_eq!(hit.normal, [0, 1, 0]);
+    assert_eq!(hit.air, [3, 101, -5]);
+    assert!((hit.dist - 9.5).abs() < 0.0001, "dist {}", hit.dist);
+
+    let hit = chunk_manager.raycast([3.0, 110.0, -5.0], [0.0, -1.0, 0.0], 9.0);
+    assert!(hit.is_none());
+    Ok(())
+  }
+
+  #[test]
+  fn test_raycast_sides_and_negative_coords() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let voxel = [-20, 90, -31];
+    chunk_manager.set_voxel2(&voxel, 2);
+
+    let cases = [
+      ([-10.0, 90.0, -31.0], [-1.0, 0.0, 0.0], [1, 0, 0]),
+      ([-30.0, 90.0, -31.0], [1.0, 0.0, 0.0], [-1, 0, 0]),
+      ([-20.0, 90.0, -20.0], [0.0, 0.0, -1.0], [0, 0, 1]),
+      ([-20.0, 90.0, -40.0], [0.0, 0.0, 1.0], [0, 0, -1]),
+      ([-20.0, 80.0, -31.0], [0.0, 1.0, 0.0], [0, -1, 0]),
+    ];
+    for (origin, dir, normal) in cases.iter() {
+      let hit = chunk_manager.raycast(*origin, *dir, 100.0).unwrap();
+      assert_eq!(hit.voxel, voxel, "origin {:?}", origin);
+      assert_eq!(hit.normal, *normal, "origin {:?}", origin);
+      assert_eq!(chunk_manager.get_voxel(&hit.air), 0, "origin {:?}", origin);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_raycast_diagonal() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.set_voxel2(&[5, 105, 5], 1);
+
+    let hit = chunk_manager.raycast([0.0, 100.0, 0.0], [1.0, 1.0, 1.0], 100.0);
+    let hit = hit.unwrap();
+    assert_eq!(hit.voxel, [5, 105, 5]);
+    assert_eq!(hit.normal.iter().map(|n| n.abs()).sum::<i64>(), 1);
+    Ok(())
+  }
+
+  #[test]
+  fn test_raycast_voxel_scale() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.voxel_scale = 0.5;
+    chunk_manager.set_voxel2(&[4, 200, 4], 3);
+
+    // Voxel [4, 200, 4] is centered at world [2.0, 100.0, 2.0]
+    let hit = chunk_manager.raycast([2.1, 110.0, 1.9], [0.0, -1.0, 0.0], 20.0);
+    let hit = hit.unwrap();
+    assert_eq!(hit.voxel, [4, 200, 4]);
+    assert_eq!(hit.air, [4, 201, 4]);
+    assert!((hit.dist - 9.75).abs() < 0.0001, "dist {}", hit.dist);
+
+    let hit = chunk_manager.raycast([2.3, 110.0, 2.0], [0.0, -1.0, 0.0], 20.0);
+    assert!(hit.is_none() || hit.unwrap().voxel != [4, 200, 4]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_raycast_unloaded_terrain() -> Result<(), String> {
+    let chunk_manager = ChunkManager::default();
+    assert_eq!(chunk_manager.len(), 0);
+
+    let hit = chunk_manager.raycast([7.0, 60.0, -3.0], [0.0, -1.0, 0.0], 200.0);
+    let hit = hit.unwrap();
+    assert_eq!(hit.voxel[0], 7);
+    assert_eq!(hit.voxel[2], -3);
+    assert_eq!(generated_voxel(&hit.voxel, chunk_manager.noise), 1);
+    assert_eq!(generated_voxel(&hit.air, chunk_manager.noise), 0);
+
+    let key = [0, -1, -1];
+    let chunk = ChunkManager::new_chunk(
+      &key, chunk_manager.depth as u8, 0, chunk_manager.noise
+    );
+    let seamless_size = chunk_manager.seamless_size() as i64;
+    let size = chunk_manager.chunk_size;
+    for x in 0..size {
+      for y in 0..size {
+        for z in 0..size {
+          let pos = [
+            key[0] * seamless_size + x as i64,
+            key[1] * seamless_size + y as i64,
+            key[2] * seamless_size + z as i64,
+          ];
+          assert_eq!(
+            chunk.octree.get_voxel(x, y, z),
+            generated_voxel(&pos, chunk_manager.noise),
+            "at pos {:?}",
+            pos
+          );
+        }
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_raycast_zero_direction() -> Result<(), String> {
+    let chunk_manager = ChunkManager::default();
+    assert!(chunk_manager.raycast([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], 10.0).is_none());
+    Ok(())
+  }
+
+  #[test]
+  fn test_raycast_unbounded_dist() -> Result<(), String> {
+    let chunk_manager = ChunkManager::default();
+    let up = [0.0, 1.0, 0.0];
+    assert!(chunk_manager.raycast([0.0, 100.0, 0.0], up, f32::INFINITY).is_none());
+    assert!(chunk_manager.raycast([0.0, 100.0, 0.0], up, f32::NAN).is_none());
+    assert!(chunk_manager.raycast([f32::NAN, 100.0, 0.0], up, 10.0).is_none());
+    Ok(())
+  }
+}
@@ -0,0 +1,125 @@
+use hashbrown::{HashMap, HashSet};
+use super::chunk_manager::ChunkManager;
+use super::coords::WorldVoxelPos;
+
+/**
+  Voxels a simulation updates on its next tick. The ones waiting for a chunk
+  that isn't loaded are parked in pending under its key
+*/
+#[derive(Clone, Debug, Default)]
+pub struct ActiveSet {
+  pub active: HashSet<[i64; 3]>,
+  pub pending: HashMap<[i64; 3], HashSet<[i64; 3]>>,
+  pub ticks: u64,
+}
+
+impl ActiveSet {
+  pub fn insert(&mut self, pos: [i64; 3]) {
+    self.active.insert(pos);
+  }
+
+  /// True when nothing is updated on the next tick, parked voxels aside
+  pub fn is_empty(&self) -> bool {
+    self.active.is_empty()
+  }
+
+  /// Moves the voxels parked for the chunk back to active
+  pub fn wake_chunk(&mut self, key: &[i64; 3]) {
+    if let Some(parked) = self.pending.remove(key) {
+      self.active.extend(parked);
+    }
+  }
+
+  /// Drops the active and parked voxels of an unloaded chunk
+  pub fn unload<F: Fn(&[i64; 3]) -> bool>(&mut self, owned: F) {
+    self.active.retain(|pos| !owned(pos));
+    for parked in self.pending.values_mut() {
+      parked.retain(|pos| !owned(pos));
+    }
+    self.pending.retain(|_, parked| !parked.is_empty());
+  }
+}
+
+impl ChunkManager {
+  /**
+    Runs step on the active voxels of the set, bottom up in a fixed order so
+    the same state gives the same result. Voxels in or above chunks that
+    aren't loaded are parked until they are. Returns the changed voxels,
+    sorted
+  */
+  pub(crate) fn tick_active<F>(
+    &mut self,
+    set: fn(&mut ChunkManager) -> &mut ActiveSet,
+    mut step: F,
+  ) -> Vec<[i64; 3]>
+  where
+    F: FnMut(&mut ChunkManager, &[i64; 3], &mut HashSet<[i64; 3]>),
+  {
+    let mut active: Vec<[i64; 3]> = set(self).active.drain().collect();
+    active.sort_by_key(|p| (p[1], p[0], p[2]));
+
+    let layout = self.layout();
+    let mut changed = HashSet::new();
+    for pos in active.iter() {
+      let below = [pos[0], pos[1] - 1, pos[2]];
+      let unloaded = [*pos, below]
+        .iter()
+        .flat_map(|p| WorldVoxelPos(*p).chunks(&layout))
+        .find(|(key, _)| !self.chunks.contains_key(&key.0));
+      if let Some((key, _)) = unloaded {
+        set(self).pending.entry(key.0).or_default().insert(*pos);
+        continue;
+      }
+      step(self, pos, &mut changed);
+    }
+
+    let mut changed: Vec<[i64; 3]> = changed.into_iter().collect();
+    changed.sort();
+    for key in self.voxel_keys(&changed) {
+      if let Some(chunk) = self.chunks.get_mut(&key) {
+        chunk.update_mode(&self.materials);
+      }
+    }
+
+    set(self).ticks += 1;
+    changed
+  }
+
+  /// Wakes the simulated voxels waiting for the chunk
+  pub(crate) fn wake_simulations(&mut self, key: &[i64; 3]) {
+    self.fluid.active.wake_chunk(key);
+    self.granular.wake_chunk(key);
+  }
+
+  /// Drops the simulation state of the voxels the removed chunk owns
+  pub(crate) fn unload_simulations(&mut self, key: &[i64; 3]) {
+    let layout = self.layout();
+    let owned = |pos: &[i64; 3]| WorldVoxelPos(*pos).key(&layout).0 == *key;
+    self.fluid.levels.remove_cell(key);
+    self.fluid.active.unload(owned);
+    self.granular.unload(owned);
+  }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use crate::chunk::chunk_manager::ChunkManager;
+
+  /// The 3x3x3 chunks around chunk 0, 8, 0 loaded and empty
+  pub(crate) fn loaded() -> ChunkManager {
+    let mut chunk_manager = ChunkManager::default();
+    for x in -1..2 {
+      for y in -1..2 {
+        for z in -1..2 {
+          let key = [x, 8 + y, z];
+          let chunk = ChunkManager::new_chunk(
+            &key, chunk_manager.depth as u8, 0, chunk_manager.noise
+          );
+          chunk_manager.set_chunk(&key, &chunk);
+        }
+      }
+    }
+    chunk_manager
+  }
+}
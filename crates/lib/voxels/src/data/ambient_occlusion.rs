@@ -0,0 +1,96 @@
+use hashbrown::HashMap;
+use super::voxel_octree::MeshData;
+
+/// Voxels sampled around a vertex cell on each side
+pub const AO_RADIUS: i64 = 1;
+
+/// Cell a surface nets vertex was placed in, the cell spans voxels
+/// cell..=cell + 1. The vertex is always strictly inside its cell
+pub fn vertex_cell(pos: &[f32; 3], scale: f32) -> [i64; 3] {
+  [
+    (pos[0] / scale).floor() as i64,
+    (pos[1] / scale).floor() as i64,
+    (pos[2] / scale).floor() as i64,
+  ]
+}
+
+/// Whether sampling around the cell needs voxels outside 0..size
+pub fn cell_ao_in_bounds(cell: &[i64; 3], size: i64) -> bool {
+  (0..3).all(|i| cell[i] - AO_RADIUS >= 0 && cell[i] + 1 + AO_RADIUS < size)
+}
+
+/**
+  Occlusion from the solid ratio around the cell, a flat surface is half solid
+  and stays unoccluded(1.0), a crevice surrounded by solid goes to 0.0
+*/
+pub fn cell_ao<F: Fn(i64, i64, i64) -> bool>(cell: &[i64; 3], is_solid: &F) -> f32 {
+  let mut solid = 0;
+  let mut total = 0;
+  for x in cell[0] - AO_RADIUS..=cell[0] + 1 + AO_RADIUS {
+    for y in cell[1] - AO_RADIUS..=cell[1] + 1 + AO_RADIUS {
+      for z in cell[2] - AO_RADIUS..=cell[2] + 1 + AO_RADIUS {
+        if is_solid(x, y, z) {
+          solid += 1;
+        }
+        total += 1;
+      }
+    }
+  }
+
+  let ratio = solid as f32 / total as f32;
+  (1.0 - (ratio - 0.5).max(0.0) * 2.0).clamp(0.0, 1.0)
+}
+
+/// Ambient occlusion for each of the mesh positions, is_solid takes local
+/// voxel coords of the mesh
+pub fn compute_ao<F: Fn(i64, i64, i64) -> bool>(
+  mesh: &MeshData,
+  scale: f32,
+  is_solid: &F,
+) -> Vec<f32> {
+  let mut cache = HashMap::new();
+  let mut ao = Vec::with_capacity(mesh.positions.len());
+  for pos in mesh.positions.iter() {
+    let cell = vertex_cell(pos, scale);
+    let value = *cache
+      .entry(cell)
+      .or_insert_with(|| cell_ao(&cell, is_solid));
+    ao.push(value);
+  }
+  ao
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cell_ao() -> Result<(), String> {
+    let floor = |_x: i64, y: i64, _z: i64| y <= 0;
+    assert_eq!(cell_ao(&[3, 0, 3], &floor), 1.0);
+
+    let corner = |x: i64, y: i64, _z: i64| y <= 0 || x <= 0;
+    let ao = cell_ao(&[0, 0, 3], &corner);
+    assert!(ao < 1.0 && ao > 0.0, "ao {}", ao);
+
+    let pit = |x: i64, y: i64, z: i64| y <= 0 || x <= 0 || z <= 0;
+    assert!(cell_ao(&[0, 0, 0], &pit) < ao);
+
+    let edge = |x: i64, y: i64, _z: i64| y <= 0 && x <= 0;
+    assert_eq!(cell_ao(&[0, 0, 3], &edge), 1.0);
+    Ok(())
+  }
+
+  #[test]
+  fn test_vertex_cell() -> Result<(), String> {
+    assert_eq!(vertex_cell(&[1.5, 0.2, 3.9], 1.0), [1, 0, 3]);
+    assert_eq!(vertex_cell(&[0.75, 0.2, 1.9], 0.5), [1, 0, 3]);
+
+    assert!(cell_ao_in_bounds(&[1, 1, 1], 16));
+    assert!(!cell_ao_in_bounds(&[0, 1, 1], 16));
+    assert!(cell_ao_in_bounds(&[13, 1, 1], 16));
+    assert!(!cell_ao_in_bounds(&[14, 1, 1], 16));
+    Ok(())
+  }
+}
@@ -0,0 +1,192 @@
+use hashbrown::HashMap;
+use super::voxel_octree::MeshData;
+use super::ambient_occlusion::vertex_cell;
+
+/// Chunk sides in the order of the neighbor lods: +x, -x, +y, -y, +z, -z
+pub const SIDES: [[i64; 3]; 6] = [
+  [1, 0, 0], [-1, 0, 0],
+  [0, 1, 0], [0, -1, 0],
+  [0, 0, 1], [0, 0, -1],
+];
+
+/// Skirt depth in voxels at lod 0, doubled for each lod
+pub const SKIRT_VOXELS: f32 = 2.0;
+
+/// Whether the cell is in the outer cell layer of the side
+fn on_side(cell: &[i64; 3], side: usize, last_cell: i64) -> bool {
+  let axis = side / 2;
+  if side % 2 == 0 {
+    cell[axis] >= last_cell
+  } else {
+    cell[axis] <= 0
+  }
+}
+
+/**
+  Hangs skirts below the open mesh edges on the chunk sides facing a neighbor
+  of a different lod, so the cracks and T-junctions where the two surfaces
+  don't meet are covered. Both chunks of a transition get skirts, whichever
+  surface is lower hides the gap. mesh.lod is the lod of the chunk, size is
+  the octree size the mesh was computed from
+*/
+pub fn add_transition_skirts(
+  mesh: &mut MeshData,
+  size: u32,
+  scale: f32,
+  neighbor_lods: &[usize; 6],
+) {
+  let sides: Vec<usize> = (0..SIDES.len())
+    .filter(|side| neighbor_lods[*side] != mesh.lod)
+    .collect();
+  if sides.is_empty() || mesh.indices.len() < 3 {
+    return;
+  }
+
+  // Edges used by only one triangle, kept in the winding of that triangle
+  let mut edges = HashMap::new();
+  for tri in mesh.indices.chunks(3) {
+    for i in 0..3 {
+      let a = tri[i];
+      let b = tri[(i + 1) % 3];
+      edges
+        .entry((a.min(b), a.max(b)))
+        .and_modify(|e: &mut (u32, u32, u32)| e.2 += 1)
+        .or_insert((a, b, 1));
+    }
+  }
+  let mut open_edges: Vec<(u32, u32)> = edges
+    .values()
+    .filter(|e| e.2 == 1)
+    .map(|e| (e.0, e.1))
+    .collect();
+  open_edges.sort();
+
+  let last_cell = size as i64 - 2;
+  let mut lowered = HashMap::new();
+  for (a, b) in open_edges.iter() {
+    let cell_a = vertex_cell(&mesh.positions[*a as usize], scale);
+    let cell_b = vertex_cell(&mesh.positions[*b as usize], scale);
+    let side = sides.iter().find(|side| {
+      on_side(&cell_a, **side, last_cell) && on_side(&cell_b, **side, last_cell)
+    });
+    let side = match side {
+      Some(s) => *s,
+      None => continue,
+    };
+
+    let lod = mesh.lod.max(neighbor_lods[side]);
+    let depth = SKIRT_VOXELS * scale * 2_i32.pow(lod as u32) as f32;
+    let a2 = *lowered
+      .entry((*a, side))
+      .or_insert_with(|| add_lowered_vertex(mesh, *a, depth));
+    let b2 = *lowered
+      .entry((*b, side))
+      .or_insert_with(|| add_lowered_vertex(mesh, *b, depth));
+
+    // Both windings, the skirt can be seen from either chunk
+    mesh.indices.extend([*a, *b, b2, *a, b2, a2]);
+    mesh.indices.extend([*b, *a, a2, *b, a2, b2]);
+  }
+}
+
+/// Copy of the vertex moved into the surface, against its normal
+fn add_lowered_vertex(mesh: &mut MeshData, index: u32, depth: f32) -> u32 {
+  let i = index as usize;
+  let len = mesh.positions.len();
+  let n = mesh.normals.get(i).copied().unwrap_or([0.0, 1.0, 0.0]);
+  let n_len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+  let dir = if n_len > f32::EPSILON {
+    [n[0] / n_len, n[1] / n_len, n[2] / n_len]
+  } else {
+    [0.0, 1.0, 0.0]
+  };
+
+  let p = mesh.positions[i];
+  if mesh.normals.len() == len {
+    mesh.normals.push(n);
+  }
+  if mesh.colors.len() == len {
+    mesh.colors.push(mesh.colors[i]);
+  }
+  if mesh.uvs.len() == len {
+    mesh.uvs.push(mesh.uvs[i]);
+  }
+  if mesh.weights.len() == len {
+    mesh.weights.push(mesh.weights[i]);
+  }
+  if mesh.types.len() == len {
+    mesh.types.push(mesh.types[i]);
+  }
+  if mesh.ao.len() == len {
+    mesh.ao.push(mesh.ao[i]);
+  }
+  if mesh.light.len() == len {
+    mesh.light.push(mesh.light[i]);
+  }
+  mesh.positions.push([
+    p[0] - dir[0] * depth,
+    p[1] - dir[1] * depth,
+    p[2] - dir[2] * depth,
+  ]);
+  len as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+  use crate::data::voxel_octree::MeshData;
+  use super::*;
+
+  /// Flat quad strip along x at y = 4.5, from cell 0 to the last cell
+  fn strip(size: u32) -> MeshData {
+    let mut mesh = MeshData::default();
+    let last = (size - 2) as f32;
+    for x in [0.5, last + 0.5] {
+      for z in [3.5, 4.5] {
+        mesh.positions.push([x, 4.5, z]);
+        mesh.normals.push([0.0, 4.0, 0.0]);
+        mesh.colors.push([1.0, 0.0, 0.0]);
+        mesh.ao.push(0.5);
+      }
+    }
+    mesh.indices = vec![0, 1, 3, 0, 3, 2];
+    mesh
+  }
+
+  #[test]
+  fn test_no_skirts_between_same_lod() -> Result<(), String> {
+    let mut mesh = strip(16);
+    mesh.lod = 1;
+    add_transition_skirts(&mut mesh, 16, 1.0, &[1; 6]);
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.indices.len(), 6);
+    Ok(())
+  }
+
+  #[test]
+  fn test_skirts_on_lod_sides() -> Result<(), String> {
+    let mut mesh = strip(16);
+    // Only the +x neighbor differs
+    add_transition_skirts(&mut mesh, 16, 1.0, &[2, 0, 0, 0, 0, 0]);
+
+    assert_eq!(mesh.positions.len(), 6);
+    assert_eq!(mesh.indices.len(), 6 + 12);
+    assert_eq!(mesh.normals.len(), 6);
+    assert_eq!(mesh.colors.len(), 6);
+    assert_eq!(mesh.ao.len(), 6);
+
+    let depth = SKIRT_VOXELS * 4.0;
+    for p in mesh.positions[4..].iter() {
+      assert_eq!(p[0], 14.5);
+      assert_eq!(p[1], 4.5 - depth);
+    }
+    assert_eq!(mesh.ao[5], 0.5);
+
+    // Both x sides face another lod
+    let mut mesh = strip(16);
+    add_transition_skirts(&mut mesh, 16, 1.0, &[1, 1, 0, 0, 0, 0]);
+    assert_eq!(mesh.positions.len(), 8);
+    assert_eq!(mesh.indices.len(), 6 + 24);
+    Ok(())
+  }
+}
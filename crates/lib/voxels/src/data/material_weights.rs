@@ -0,0 +1,97 @@
+use hashbrown::HashMap;
+use super::voxel_octree::MeshData;
+use super::ambient_occlusion::vertex_cell;
+
+/// Materials blended per vertex, the rest of the cell voxels are dropped
+pub const MATERIALS_PER_VERTEX: usize = 4;
+
+/**
+  Most common voxel values on the solid corners of the cell with their share
+  of the corners. Unused slots are 0 with a weight of 0.0, the weights sum to
+  1.0 when the cell has a solid corner
+*/
+pub fn cell_materials<F: Fn(i64, i64, i64) -> u8>(
+  cell: &[i64; 3],
+  voxel: &F
+) -> ([u32; 4], [f32; 4]) {
+  let mut counts: Vec<(u32, u32)> = Vec::new();
+  for x in cell[0]..cell[0] + 2 {
+    for y in cell[1]..cell[1] + 2 {
+      for z in cell[2]..cell[2] + 2 {
+        let value = voxel(x, y, z) as u32;
+        if value == 0 {
+          continue;
+        }
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+          Some((_, count)) => *count += 1,
+          None => counts.push((value, 1)),
+        }
+      }
+    }
+  }
+  counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+  counts.truncate(MATERIALS_PER_VERTEX);
+
+  let total: u32 = counts.iter().map(|(_, count)| count).sum();
+  let mut types = [0; 4];
+  let mut weights = [0.0; 4];
+  for (i, (value, count)) in counts.iter().enumerate() {
+    types[i] = *value;
+    weights[i] = *count as f32 / total as f32;
+  }
+  (types, weights)
+}
+
+/// Voxel types and their blend weights for each of the mesh positions, voxel
+/// takes local voxel coords of the mesh
+pub fn compute_material_weights<F: Fn(i64, i64, i64) -> u8>(
+  mesh: &MeshData,
+  scale: f32,
+  voxel: &F,
+) -> (Vec<[u32; 4]>, Vec<[f32; 4]>) {
+  let mut cache = HashMap::new();
+  let mut types = Vec::with_capacity(mesh.positions.len());
+  let mut weights = Vec::with_capacity(mesh.positions.len());
+  for pos in mesh.positions.iter() {
+    let cell = vertex_cell(pos, scale);
+    let (t, w) = *cache
+      .entry(cell)
+      .or_insert_with(|| cell_materials(&cell, voxel));
+    types.push(t);
+    weights.push(w);
+  }
+  (types, weights)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cell_materials() -> Result<(), String> {
+    // Grass on top of dirt, one stone corner
+    let voxel = |x: i64, y: i64, z: i64| {
+      if x == 0 && y == 0 && z == 0 {
+        3
+      } else if y == 0 {
+        1
+      } else {
+        2
+      }
+    };
+    let (types, weights) = cell_materials(&[0, 0, 0], &voxel);
+    assert_eq!(types, [2, 1, 3, 0]);
+    assert_eq!(weights, [0.5, 0.375, 0.125, 0.0]);
+
+    let air = |_x: i64, _y: i64, _z: i64| 0;
+    assert_eq!(cell_materials(&[0, 0, 0], &air), ([0; 4], [0.0; 4]));
+
+    // Only the 4 most common values are kept
+    let mixed = |x: i64, y: i64, z: i64| (1 + x + y * 2 + z * 4) as u8;
+    let (types, weights) = cell_materials(&[0, 0, 0], &mixed);
+    assert_eq!(types, [1, 2, 3, 4]);
+    assert_eq!(weights.iter().sum::<f32>(), 1.0);
+    Ok(())
+  }
+}
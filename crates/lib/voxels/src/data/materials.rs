@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use super::voxel_octree::MeshData;
+
+/// Rapier defaults, for meshes without material weights
+pub const DEFAULT_FRICTION: f32 = 0.5;
+pub const DEFAULT_RESTITUTION: f32 = 0.0;
+
+/// Properties of a voxel value
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VoxelMaterial {
+  pub name: String,
+  pub color: [f32; 3],
+  /// Layer of the chunk array texture, None keeps the flat color
+  #[serde(default)]
+  pub texture: Option<u32>,
+  /// Relative time to dig the voxel, 1.0 is the default
+  #[serde(default = "default_hardness")]
+  pub hardness: f32,
+  #[serde(default = "default_friction")]
+  pub friction: f32,
+  #[serde(default)]
+  pub restitution: f32,
+  #[serde(default)]
+  pub transparent: bool,
+  /// Liquids are meshed apart like the transparent materials and their
+  /// colliders are sensors
+  #[serde(default)]
+  pub liquid: bool,
+  /// Alpha of the transparent and liquid materials
+  #[serde(default = "default_opacity")]
+  pub opacity: f32,
+  /// Granular voxels fall when unsupported and pile up
+  #[serde(default)]
+  pub granular: bool,
+  /// Steepest stable slope of the granular piles, in degrees
+  #[serde(default = "default_repose")]
+  pub repose: f32,
+  /// Stress the voxel carries before breaking, a voxel's stress is how far
+  /// it is from the supported ground along solid paths
+  #[serde(default = "default_strength")]
+  pub strength: f32,
+  /// Block light the voxel emits, 0 doesn't emit
+  #[serde(default)]
+  pub emissive: u8,
+}
+
+fn default_hardness() -> f32 { 1.0 }
+fn default_friction() -> f32 { DEFAULT_FRICTION }
+fn default_opacity() -> f32 { 0.5 }
+fn default_repose() -> f32 { 45.0 }
+fn default_strength() -> f32 { 16.0 }
+
+impl VoxelMaterial {
+  pub fn new(name: &str, color: [f32; 3]) -> Self {
+    Self {
+      name: name.to_string(),
+      color: color,
+      texture: None,
+      hardness: default_hardness(),
+      friction: DEFAULT_FRICTION,
+      restitution: DEFAULT_RESTITUTION,
+      transparent: false,
+      liquid: false,
+      opacity: default_opacity(),
+      granular: false,
+      repose: default_repose(),
+      strength: default_strength(),
+      emissive: 0,
+    }
+  }
+}
+
+/**
+  Material of each voxel value, voxel 1 is the first material as the voxel 0
+  is air. The colors are the palette passed to the meshers
+*/
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct MaterialRegistry {
+  pub materials: Vec<VoxelMaterial>,
+}
+
+impl MaterialRegistry {
+  /// Default materials named after their voxel value
+  pub fn from_colors(colors: &[[f32; 3]]) -> Self {
+    let materials = colors
+      .iter()
+      .enumerate()
+      .map(|(i, color)| VoxelMaterial::new(&format!("Voxel {}", i + 1), *color))
+      .collect();
+    Self { materials: materials }
+  }
+
+  /// None for air and values without a material
+  pub fn get(&self, voxel: u8) -> Option<&VoxelMaterial> {
+    if voxel == 0 {
+      return None;
+    }
+    self.materials.get(voxel as usize - 1)
+  }
+
+  pub fn get_mut(&mut self, voxel: u8) -> Option<&mut VoxelMaterial> {
+    if voxel == 0 {
+      return None;
+    }
+    self.materials.get_mut(voxel as usize - 1)
+  }
+
+  /// Voxel value of the material with the name
+  pub fn find(&self, name: &str) -> Option<u8> {
+    self.materials
+      .iter()
+      .position(|m| m.name == name)
+      .map(|i| (i + 1) as u8)
+  }
+
+  pub fn colors(&self) -> Vec<[f32; 3]> {
+    self.materials.iter().map(|m| m.color).collect()
+  }
+
+  /// Voxel value of the closest color, 1 without materials
+  pub fn nearest(&self, color: &[f32; 3]) -> u8 {
+    let dist = |c: &[f32; 3]| (0..3).map(|i| (c[i] - color[i]).powi(2)).sum::<f32>();
+    let mut nearest = 0;
+    for i in 1..self.materials.len().min(255) {
+      if dist(&self.materials[i].color) < dist(&self.materials[nearest].color) {
+        nearest = i;
+      }
+    }
+    nearest as u8 + 1
+  }
+
+  pub fn emissive(&self, voxel: u8) -> u8 {
+    self.get(voxel).map(|m| m.emissive).unwrap_or(0)
+  }
+
+  pub fn is_transparent(&self, voxel: u8) -> bool {
+    self.get(voxel).map(|m| m.transparent).unwrap_or(false)
+  }
+
+  pub fn is_liquid(&self, voxel: u8) -> bool {
+    self.get(voxel).map(|m| m.liquid).unwrap_or(false)
+  }
+
+  pub fn is_granular(&self, voxel: u8) -> bool {
+    self.get(voxel).map(|m| m.granular).unwrap_or(false)
+  }
+
+  /// Height a granular pile of the voxel can rise per voxel of distance
+  pub fn repose_slope(&self, voxel: u8) -> f32 {
+    let repose = self.get(voxel).map(|m| m.repose).unwrap_or(default_repose());
+    repose.clamp(1.0, 89.0).to_radians().tan()
+  }
+
+  pub fn strength(&self, voxel: u8) -> f32 {
+    self.get(voxel).map(|m| m.strength).unwrap_or(default_strength())
+  }
+
+  /// Whether the voxel is meshed apart from the opaque voxels
+  pub fn separate_pass(&self, voxel: u8) -> bool {
+    self.get(voxel).map(|m| m.transparent || m.liquid).unwrap_or(false)
+  }
+
+  /// [friction, restitution] of the mesh surface, averaged by the material
+  /// weights of its vertices
+  pub fn surface_properties(&self, mesh: &MeshData) -> [f32; 2] {
+    let mut sum = [0.0; 2];
+    let mut total = 0.0;
+    for (types, weights) in mesh.types.iter().zip(mesh.weights.iter()) {
+      for i in 0..types.len() {
+        let material = match self.get(types[i] as u8) {
+          Some(m) => m,
+          None => continue,
+        };
+        sum[0] += material.friction * weights[i];
+        sum[1] += material.restitution * weights[i];
+        total += weights[i];
+      }
+    }
+
+    if total <= 0.0 {
+      return [DEFAULT_FRICTION, DEFAULT_RESTITUTION];
+    }
+    [sum[0] / total, sum[1] / total]
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_material_registry() -> Result<(), String> {
+    let mut registry = MaterialRegistry::from_colors(&[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+    assert!(registry.get(0).is_none());
+    assert!(registry.get(3).is_none());
+    assert_eq!(registry.get(2).unwrap().color, [0.0, 1.0, 0.0]);
+    assert_eq!(registry.colors(), vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+
+    let ice = registry.get_mut(2).unwrap();
+    ice.name = "Ice".to_string();
+    ice.friction = 0.1;
+    ice.restitution = 0.2;
+    ice.emissive = 4;
+    assert_eq!(registry.find("Ice"), Some(2));
+    assert_eq!(registry.emissive(2), 4);
+    assert_eq!(registry.emissive(0), 0);
+
+    let str = ron::to_string(&registry).unwrap();
+    let loaded: MaterialRegistry = ron::from_str(&str).unwrap();
+    assert_eq!(loaded, registry);
+    Ok(())
+  }
+
+  #[test]
+  fn test_surface_properties() -> Result<(), String> {
+    let mut registry = MaterialRegistry::from_colors(&[[1.0; 3], [1.0; 3]]);
+    registry.get_mut(2).unwrap().friction = 0.1;
+
+    let mut mesh = MeshData::default();
+    assert_eq!(registry.surface_properties(&mesh), [DEFAULT_FRICTION, DEFAULT_RESTITUTION]);
+
+    mesh.types = vec![[1, 2, 0, 0], [2, 0, 0, 0]];
+    mesh.weights = vec![[0.5, 0.5, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]];
+    let [friction, restitution] = registry.surface_properties(&mesh);
+    assert!((friction - 0.2).abs() < 1e-6);
+    assert_eq!(restitution, 0.0);
+    Ok(())
+  }
+}
@@ -0,0 +1,143 @@
+use super::voxel_octree::{VoxelOctree, MeshData};
+use super::surface_nets::{VoxelReuse, get_surface_nets_of};
+use super::materials::MaterialRegistry;
+
+/**
+  Surface nets with the transparent and liquid materials meshed apart. The
+  opaque mesh treats them as air so the faces behind them stay, each of them
+  gets its own mesh in MeshData::transparent without the faces it shares
+  with the opaque voxels
+*/
+pub fn get_surface_nets_passes(
+  octree: &VoxelOctree,
+  voxel_reuse: &mut VoxelReuse,
+  materials: &MaterialRegistry,
+  scale: f32,
+  key: [i64; 3],
+  lod: usize,
+) -> MeshData {
+  let colors = materials.colors();
+  let separate = separate_values(octree, materials);
+  let opaque = |v: u8| if materials.separate_pass(v) { 0 } else { v };
+  let mut data = get_surface_nets_of(
+    octree, voxel_reuse, &colors, scale, key, lod, &opaque, 0
+  );
+
+  for value in separate {
+    let mask = |v: u8| if v == value || !materials.separate_pass(v) { v } else { 0 };
+    let mut mesh = get_surface_nets_of(
+      octree, voxel_reuse, &colors, scale, key, lod, &mask, value
+    );
+    if mesh.indices.is_empty() {
+      continue;
+    }
+
+    let color = materials.get(value).map(|m| m.color).unwrap_or([1.0; 3]);
+    mesh.colors = vec![color; mesh.positions.len()];
+    mesh.types = vec![[value as u32, 0, 0, 0]; mesh.positions.len()];
+    mesh.weights = vec![[1.0, 0.0, 0.0, 0.0]; mesh.positions.len()];
+    data.transparent.push(mesh);
+  }
+  data
+}
+
+/// Voxel values of the octree meshed in a separate pass, sorted
+fn separate_values(octree: &VoxelOctree, materials: &MaterialRegistry) -> Vec<u8> {
+  let mut found = [false; 256];
+  let size = octree.get_size();
+  for x in 0..size {
+    for y in 0..size {
+      for z in 0..size {
+        let voxel = octree.get_voxel(x, y, z);
+        if !found[voxel as usize] && materials.separate_pass(voxel) {
+          found[voxel as usize] = true;
+        }
+      }
+    }
+  }
+  (1..=255).filter(|v| found[*v as usize]).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::data::surface_nets::get_surface_nets;
+
+  const WATER: u8 = 2;
+
+  fn registry() -> MaterialRegistry {
+    let mut materials = MaterialRegistry::from_colors(&[[0.5; 3], [0.0, 0.0, 1.0]]);
+    let water = materials.get_mut(WATER).unwrap();
+    water.transparent = true;
+    water.liquid = true;
+    materials
+  }
+
+  fn octree<F: Fn(u32, u32, u32) -> u8>(voxel: F) -> VoxelOctree {
+    let mut octree = VoxelOctree::new(0, 4);
+    for x in 0..16 {
+      for y in 0..16 {
+        for z in 0..16 {
+          let v = voxel(x, y, z);
+          if v > 0 {
+            octree.set_voxel(x, y, z, v);
+          }
+        }
+      }
+    }
+    octree
+  }
+
+  #[test]
+  fn test_liquid_pass() -> Result<(), String> {
+    let materials = registry();
+    let inside = |x: u32, y: u32, z: u32| {
+      (4..10).contains(&x) && (4..10).contains(&y) && (4..10).contains(&z)
+    };
+    let water = octree(|x, y, z| if inside(x, y, z) { WATER } else { 0 });
+    let stone = octree(|x, y, z| if inside(x, y, z) { 1 } else { 0 });
+
+    let mut reuse = VoxelReuse::new(4, 3);
+    let data = get_surface_nets_passes(&water, &mut reuse, &materials, 1.0, [0; 3], 0);
+    assert!(data.indices.is_empty());
+    assert!(!data.is_empty());
+    assert_eq!(data.transparent.len(), 1);
+
+    let expected = get_surface_nets(&stone, &mut reuse, &materials.colors(), 1.0, [0; 3], 0);
+    let liquid = &data.transparent[0];
+    assert_eq!(liquid.positions.len(), expected.positions.len());
+    assert_eq!(liquid.indices.len(), expected.indices.len());
+    assert!(liquid.types.iter().all(|t| *t == [WATER as u32, 0, 0, 0]));
+    assert!(liquid.colors.iter().all(|c| *c == [0.0, 0.0, 1.0]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_liquid_on_stone() -> Result<(), String> {
+    let materials = registry();
+    let stone_only = |_x: u32, y: u32, _z: u32| if y < 6 { 1 } else { 0 };
+    let mixed = octree(|x, y, z| if y >= 6 && y < 10 { WATER } else { stone_only(x, y, z) });
+    let stone = octree(stone_only);
+
+    let mut reuse = VoxelReuse::new(4, 3);
+    let data = get_surface_nets_passes(&mixed, &mut reuse, &materials, 1.0, [0; 3], 0);
+    let expected = get_surface_nets(&stone, &mut reuse, &materials.colors(), 1.0, [0; 3], 0);
+    assert_eq!(data.positions, expected.positions);
+    assert_eq!(data.indices, expected.indices);
+
+    // Only the water surface, no faces between the water and the stone
+    assert_eq!(data.transparent.len(), 1);
+    let liquid = &data.transparent[0];
+    assert!(!liquid.indices.is_empty());
+    for tri in liquid.indices.chunks(3) {
+      let p = [0, 1, 2].map(|i| liquid.positions[tri[i] as usize]);
+      let u = [p[1][0] - p[0][0], p[1][1] - p[0][1], p[1][2] - p[0][2]];
+      let v = [p[2][0] - p[0][0], p[2][1] - p[0][1], p[2][2] - p[0][2]];
+      let normal_y = u[2] * v[0] - u[0] * v[2];
+      assert!(normal_y.abs() > 1e-6, "vertical water face {:?}", p);
+      assert!(p.iter().all(|p| p[1] > 8.0), "water face below the surface {:?}", p);
+    }
+    Ok(())
+  }
+}
@@ -1,5 +1,11 @@
 pub mod surface_nets;
 pub mod voxel_octree;
+pub mod ambient_occlusion;
+pub mod lod_transition;
+pub mod normals;
+pub mod material_weights;
+pub mod materials;
+pub mod mesh_passes;
 
 
 pub const CUBE_EDGES: [(usize, usize); 12] = [
@@ -0,0 +1,85 @@
+use hashbrown::HashMap;
+use super::voxel_octree::MeshData;
+use super::ambient_occlusion::vertex_cell;
+
+/// Whether the gradient of the cell only needs voxels inside 0..size,
+/// the central differences reach one voxel past each side of the cell
+pub fn cell_normal_in_bounds(cell: &[i64; 3], size: i64) -> bool {
+  (0..3).all(|i| cell[i] - 1 >= 0 && cell[i] + 2 < size)
+}
+
+/**
+  Sum of the central difference gradients at the 8 corners of the cell,
+  pointing from solid to air. Neighbor cells share the corner gradients so
+  the normals stay smooth across cells and, with the same samples, across
+  chunks
+*/
+pub fn cell_normal<F: Fn(i64, i64, i64) -> bool>(cell: &[i64; 3], is_solid: &F) -> [f32; 3] {
+  let dist = |x: i64, y: i64, z: i64| if is_solid(x, y, z) { -1.0 } else { 1.0 };
+
+  let mut normal = [0.0f32; 3];
+  for x in cell[0]..cell[0] + 2 {
+    for y in cell[1]..cell[1] + 2 {
+      for z in cell[2]..cell[2] + 2 {
+        normal[0] += dist(x + 1, y, z) - dist(x - 1, y, z);
+        normal[1] += dist(x, y + 1, z) - dist(x, y - 1, z);
+        normal[2] += dist(x, y, z + 1) - dist(x, y, z - 1);
+      }
+    }
+  }
+
+  let len = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+  if len <= f32::EPSILON {
+    return normal;
+  }
+  [normal[0] / len, normal[1] / len, normal[2] / len]
+}
+
+/// Normal for each of the mesh positions, is_solid takes local voxel coords
+/// of the mesh. Keeps the current normal where the gradient cancels out
+pub fn compute_normals<F: Fn(i64, i64, i64) -> bool>(
+  mesh: &MeshData,
+  scale: f32,
+  is_solid: &F,
+) -> Vec<[f32; 3]> {
+  let mut cache = HashMap::new();
+  let mut normals = Vec::with_capacity(mesh.positions.len());
+  for (i, pos) in mesh.positions.iter().enumerate() {
+    let cell = vertex_cell(pos, scale);
+    let normal = *cache
+      .entry(cell)
+      .or_insert_with(|| cell_normal(&cell, is_solid));
+    if normal == [0.0; 3] && i < mesh.normals.len() {
+      normals.push(mesh.normals[i]);
+    } else {
+      normals.push(normal);
+    }
+  }
+  normals
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cell_normal() -> Result<(), String> {
+    let floor = |_x: i64, y: i64, _z: i64| y <= 0;
+    assert_eq!(cell_normal(&[3, 0, 3], &floor), [0.0, 1.0, 0.0]);
+
+    let wall = |x: i64, _y: i64, _z: i64| x >= 5;
+    assert_eq!(cell_normal(&[4, 2, 3], &wall), [-1.0, 0.0, 0.0]);
+
+    let slope = |x: i64, y: i64, _z: i64| y <= x;
+    let n = cell_normal(&[3, 3, 3], &slope);
+    assert!(n[0] < 0.0 && n[1] > 0.0 && n[2] == 0.0, "normal {:?}", n);
+    assert!((n[0] + n[1]).abs() < 1e-6);
+
+    assert!(cell_normal_in_bounds(&[1, 1, 1], 16));
+    assert!(!cell_normal_in_bounds(&[0, 1, 1], 16));
+    assert!(cell_normal_in_bounds(&[13, 1, 1], 16));
+    assert!(!cell_normal_in_bounds(&[14, 1, 1], 16));
+    Ok(())
+  }
+}
@@ -0,0 +1,720 @@
+use serde_json::{Value, json};
+use crate::data::voxel_octree::MeshData;
+use crate::data::materials::MaterialRegistry;
+use super::{MeshNode, voxelize::{TriangleMesh, IDENTITY, mul_matrix, transform_point}};
+
+const GLB_MAGIC: u32 = 0x46546C67;
+const CHUNK_JSON: u32 = 0x4E4F534A;
+const CHUNK_BIN: u32 = 0x004E4942;
+
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+const OPAQUE_MATERIAL: usize = 0;
+const TRANSPARENT_MATERIAL: usize = 1;
+
+/// Deepest node hierarchy read, guards against cycles
+const MAX_NODE_DEPTH: usize = 64;
+
+/**
+  Binary glTF 2.0 of the meshes with normals and vertex colors, a node per
+  mesh at its translation. Merging bakes the translations into a single
+  mesh. The transparent meshes are blended primitives, their alpha is the
+  opacity of their material
+*/
+pub fn write_glb(nodes: &[MeshNode], materials: &MaterialRegistry, merge: bool) -> Vec<u8> {
+  let mut gltf = Builder::default();
+  if merge {
+    let mut opaque = Primitive::default();
+    let mut transparent = Primitive::default();
+    for node in nodes.iter() {
+      opaque.append(node.mesh, node.translation, None);
+      for mesh in node.mesh.transparent.iter() {
+        transparent.append(mesh, node.translation, Some(materials));
+      }
+    }
+    gltf.node("voxels", None, &opaque, &transparent);
+  } else {
+    for node in nodes.iter() {
+      let mut opaque = Primitive::default();
+      let mut transparent = Primitive::default();
+      opaque.append(node.mesh, [0.0; 3], None);
+      for mesh in node.mesh.transparent.iter() {
+        transparent.append(mesh, [0.0; 3], Some(materials));
+      }
+      gltf.node(&node.name, Some(node.translation), &opaque, &transparent);
+    }
+  }
+  gltf.glb()
+}
+
+/// Vertices of a glTF primitive, the colors are RGBA
+#[derive(Default)]
+struct Primitive {
+  positions: Vec<[f32; 3]>,
+  normals: Vec<[f32; 3]>,
+  colors: Vec<[f32; 4]>,
+  indices: Vec<u32>,
+}
+
+impl Primitive {
+  fn append(&mut self, mesh: &MeshData, offset: [f32; 3], materials: Option<&MaterialRegistry>) {
+    let start = self.positions.len() as u32;
+    for (i, p) in mesh.positions.iter().enumerate() {
+      self.positions.push([p[0] + offset[0], p[1] + offset[1], p[2] + offset[2]]);
+      self.normals.push(*mesh.normals.get(i).unwrap_or(&[0.0, 1.0, 0.0]));
+
+      let c = mesh.colors.get(i).unwrap_or(&[1.0; 3]);
+      let alpha = match (materials, mesh.types.get(i)) {
+        (Some(materials), Some(types)) => materials
+          .get(types[0] as u8)
+          .map(|m| m.opacity)
+          .unwrap_or(1.0),
+        _ => 1.0,
+      };
+      self.colors.push([c[0], c[1], c[2], alpha]);
+    }
+    self.indices.extend(mesh.indices.iter().map(|i| start + i));
+  }
+}
+
+#[derive(Default)]
+struct Builder {
+  nodes: Vec<Value>,
+  meshes: Vec<Value>,
+  accessors: Vec<Value>,
+  views: Vec<Value>,
+  bin: Vec<u8>,
+}
+
+impl Builder {
+  /// Skips the nodes without triangles
+  fn node(
+    &mut self,
+    name: &str,
+    translation: Option<[f32; 3]>,
+    opaque: &Primitive,
+    transparent: &Primitive
+  ) {
+    let primitives: Vec<Value> = [(opaque, OPAQUE_MATERIAL), (transparent, TRANSPARENT_MATERIAL)]
+      .iter()
+      .filter(|(p, _)| !p.indices.is_empty())
+      .map(|(p, material)| self.primitive(p, *material))
+      .collect();
+    if primitives.is_empty() {
+      return;
+    }
+
+    self.meshes.push(json!({ "name": name, "primitives": primitives }));
+    let mut node = json!({ "name": name, "mesh": self.meshes.len() - 1 });
+    if let Some(t) = translation {
+      node["translation"] = json!(t);
+    }
+    self.nodes.push(node);
+  }
+
+  fn primitive(&mut self, p: &Primitive, material: usize) -> Value {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for pos in p.positions.iter() {
+      for i in 0..3 {
+        min[i] = min[i].min(pos[i]);
+        max[i] = max[i].max(pos[i]);
+      }
+    }
+
+    let count = p.positions.len();
+    let positions = self.accessor(floats(&p.positions), ARRAY_BUFFER, FLOAT, count, "VEC3");
+    self.accessors[positions]["min"] = json!(min);
+    self.accessors[positions]["max"] = json!(max);
+    let normals = self.accessor(floats(&p.normals), ARRAY_BUFFER, FLOAT, count, "VEC3");
+    let colors = self.accessor(floats(&p.colors), ARRAY_BUFFER, FLOAT, count, "VEC4");
+    let indices = self.accessor(
+      p.indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+      ELEMENT_ARRAY_BUFFER, UNSIGNED_INT, p.indices.len(), "SCALAR"
+    );
+    json!({
+      "attributes": { "POSITION": positions, "NORMAL": normals, "COLOR_0": colors },
+      "indices": indices,
+      "material": material,
+      "mode": 4,
+    })
+  }
+
+  /// Index of the accessor, its data gets a buffer view of its own
+  fn accessor(
+    &mut self,
+    bytes: Vec<u8>,
+    target: u32,
+    component: u32,
+    count: usize,
+    kind: &str
+  ) -> usize {
+    self.views.push(json!({
+      "buffer": 0,
+      "byteOffset": self.bin.len(),
+      "byteLength": bytes.len(),
+      "target": target,
+    }));
+    self.bin.extend(bytes);
+    self.accessors.push(json!({
+      "bufferView": self.views.len() - 1,
+      "componentType": component,
+      "count": count,
+      "type": kind,
+    }));
+    self.accessors.len() - 1
+  }
+
+  fn glb(self) -> Vec<u8> {
+    let mut json = json!({
+      "asset": { "version": "2.0", "generator": "voxels" },
+      "scene": 0,
+      "scenes": [{}],
+    });
+    // Empty arrays aren't valid glTF, they are left out
+    if !self.nodes.is_empty() {
+      let material = |name: &str| json!({
+        "name": name,
+        "pbrMetallicRoughness": { "metallicFactor": 0, "roughnessFactor": 1 },
+      });
+      let mut transparent = material("transparent");
+      transparent["alphaMode"] = json!("BLEND");
+      transparent["doubleSided"] = json!(true);
+
+      json["scenes"] = json!([{ "nodes": (0..self.nodes.len()).collect::<Vec<usize>>() }]);
+      json["nodes"] = Value::Array(self.nodes);
+      json["meshes"] = Value::Array(self.meshes);
+      json["materials"] = json!([material("opaque"), transparent]);
+      json["accessors"] = Value::Array(self.accessors);
+      json["bufferViews"] = Value::Array(self.views);
+      json["buffers"] = json!([{ "byteLength": self.bin.len() }]);
+    }
+
+    let mut json = json.to_string().into_bytes();
+    while json.len() % 4 != 0 {
+      json.push(b' ');
+    }
+    let mut bin = self.bin;
+    while bin.len() % 4 != 0 {
+      bin.push(0);
+    }
+
+    let mut length = 12 + 8 + json.len();
+    if !bin.is_empty() {
+      length += 8 + bin.len();
+    }
+    let mut bytes = Vec::with_capacity(length);
+    for v in [GLB_MAGIC, 2, length as u32, json.len() as u32, CHUNK_JSON] {
+      bytes.extend(v.to_le_bytes());
+    }
+    bytes.extend(json);
+    if !bin.is_empty() {
+      for v in [bin.len() as u32, CHUNK_BIN] {
+        bytes.extend(v.to_le_bytes());
+      }
+      bytes.extend(bin);
+    }
+    bytes
+  }
+}
+
+fn floats<const N: usize>(values: &[[f32; N]]) -> Vec<u8> {
+  values.iter().flatten().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/**
+  Triangles of a glTF 2.0 file, binary or JSON with embedded buffers, in the
+  scene space. A triangle takes the base color of its material times the
+  average of its vertex colors
+*/
+pub fn read_gltf(bytes: &[u8]) -> Result<TriangleMesh, String> {
+  let (json, bin) = if bytes.len() >= 12 && u32_le(bytes, 0) == GLB_MAGIC {
+    glb_chunks(bytes)?
+  } else {
+    (bytes, None)
+  };
+  let json: Value = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+
+  let mut buffers = Vec::new();
+  for (i, buffer) in array(&json, "buffers").iter().enumerate() {
+    let data = match buffer["uri"].as_str() {
+      Some(uri) => match uri.split_once(";base64,") {
+        Some((prefix, data)) if prefix.starts_with("data:") => decode_base64(data)?,
+        _ => return Err(format!("External buffer {} isn't supported", uri)),
+      },
+      None if i == 0 => bin.ok_or("Missing binary chunk")?.to_vec(),
+      None => return Err(format!("Buffer {} has no data", i)),
+    };
+    buffers.push(data);
+  }
+  let reader = Reader { json: &json, buffers: buffers };
+
+  let mut mesh = TriangleMesh::default();
+  let scene = json["scene"].as_u64().unwrap_or(0) as usize;
+  let roots: Vec<usize> = match json["scenes"].get(scene) {
+    Some(scene) => array(scene, "nodes").iter().filter_map(|n| n.as_u64()).map(|n| n as usize).collect(),
+    None => (0..array(&json, "nodes").len()).collect(),
+  };
+  for root in roots {
+    reader.node(root, &IDENTITY, &mut mesh, 0)?;
+  }
+  Ok(mesh)
+}
+
+struct Reader<'a> {
+  json: &'a Value,
+  buffers: Vec<Vec<u8>>,
+}
+
+impl<'a> Reader<'a> {
+  fn node(
+    &self,
+    index: usize,
+    parent: &[[f32; 4]; 4],
+    mesh: &mut TriangleMesh,
+    depth: usize
+  ) -> Result<(), String> {
+    let node = self.json["nodes"].get(index).ok_or(format!("Missing node {}", index))?;
+    if depth > MAX_NODE_DEPTH {
+      return Err("Node hierarchy too deep".to_string());
+    }
+    let transform = mul_matrix(parent, &node_matrix(node));
+
+    if let Some(index) = node["mesh"].as_u64() {
+      let primitives = array(&self.json["meshes"][index as usize], "primitives");
+      for primitive in primitives.iter() {
+        self.primitive(primitive, &transform, mesh)?;
+      }
+    }
+    for child in array(node, "children").iter().filter_map(|c| c.as_u64()) {
+      self.node(child as usize, &transform, mesh, depth + 1)?;
+    }
+    Ok(())
+  }
+
+  fn primitive(
+    &self,
+    primitive: &Value,
+    transform: &[[f32; 4]; 4],
+    mesh: &mut TriangleMesh
+  ) -> Result<(), String> {
+    if primitive["mode"].as_u64().unwrap_or(4) != 4 {
+      return Ok(());
+    }
+    let attributes = &primitive["attributes"];
+    let positions = self.floats(
+      attributes["POSITION"].as_u64().ok_or("Missing positions")?, &["VEC3"], &[FLOAT]
+    )?;
+    let colors = match attributes["COLOR_0"].as_u64() {
+      Some(index) => self.floats(index, &["VEC3", "VEC4"], &[FLOAT, UNSIGNED_BYTE, UNSIGNED_SHORT])?,
+      None => Vec::new(),
+    };
+    if !colors.is_empty() && colors.len() < positions.len() {
+      return Err("Fewer colors than positions".to_string());
+    }
+    let indices: Vec<u32> = match primitive["indices"].as_u64() {
+      Some(index) => self.indices(index)?,
+      None => (0..positions.len() as u32).collect(),
+    };
+
+    let mut base = [1.0; 3];
+    if let Some(material) = primitive["material"].as_u64() {
+      let factor = &self.json["materials"][material as usize]["pbrMetallicRoughness"]["baseColorFactor"];
+      for (i, c) in base.iter_mut().enumerate() {
+        *c = factor[i].as_f64().unwrap_or(1.0) as f32;
+      }
+    }
+
+    let start = mesh.positions.len() as u32;
+    for p in positions.iter() {
+      mesh.positions.push(transform_point(transform, &[p[0], p[1], p[2]]));
+    }
+    for tri in indices.chunks_exact(3) {
+      if tri.iter().any(|i| *i as usize >= positions.len()) {
+        return Err("Index out of bounds".to_string());
+      }
+      let mut color = base;
+      if !colors.is_empty() {
+        for (c, value) in color.iter_mut().enumerate() {
+          *value *= tri.iter().map(|i| colors[*i as usize][c]).sum::<f32>() / 3.0;
+        }
+      }
+      mesh.indices.extend(tri.iter().map(|i| start + i));
+      mesh.colors.push(color);
+    }
+    Ok(())
+  }
+
+  /// Elements of the accessor, the normalized integers mapped to 0..1
+  fn floats(&self, index: u64, types: &[&str], components: &[u32]) -> Result<Vec<Vec<f32>>, String> {
+    let accessor = self.accessor(index, types, components)?;
+    Ok((0..accessor.count).map(|i| {
+      (0..accessor.components).map(|c| {
+        let b = accessor.get(i, c);
+        match (accessor.component, accessor.normalized) {
+          (FLOAT, _) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+          (UNSIGNED_BYTE, true) => b[0] as f32 / 255.0,
+          (UNSIGNED_BYTE, false) => b[0] as f32,
+          (UNSIGNED_SHORT, true) => u16::from_le_bytes([b[0], b[1]]) as f32 / 65535.0,
+          (UNSIGNED_SHORT, false) => u16::from_le_bytes([b[0], b[1]]) as f32,
+          _ => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+        }
+      }).collect()
+    }).collect())
+  }
+
+  /// Elements of a scalar accessor of unsigned integers
+  fn indices(&self, index: u64) -> Result<Vec<u32>, String> {
+    let accessor = self.accessor(index, &["SCALAR"], &[UNSIGNED_BYTE, UNSIGNED_SHORT, UNSIGNED_INT])?;
+    Ok((0..accessor.count).map(|i| {
+      let b = accessor.get(i, 0);
+      match accessor.component {
+        UNSIGNED_BYTE => b[0] as u32,
+        UNSIGNED_SHORT => u16::from_le_bytes([b[0], b[1]]) as u32,
+        _ => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+      }
+    }).collect())
+  }
+
+  /// Layout of the accessor, checking its type, component type and bounds
+  fn accessor(&self, index: u64, types: &[&str], components: &[u32]) -> Result<Accessor<'_>, String> {
+    let accessor = &self.json["accessors"][index as usize];
+    let count = accessor["count"].as_u64().ok_or("Invalid accessor")? as usize;
+    let kind = accessor["type"].as_str().unwrap_or("");
+    if !types.contains(&kind) {
+      return Err(format!("Accessor {} of type {:?}, expected {:?}", index, kind, types));
+    }
+    let component = accessor["componentType"].as_u64().unwrap_or(0) as u32;
+    if !components.contains(&component) {
+      return Err(format!("Accessor {} of component type {}, expected {:?}", index, component, components));
+    }
+    let size = match component {
+      UNSIGNED_BYTE => 1,
+      UNSIGNED_SHORT => 2,
+      _ => 4,
+    };
+    let components = match kind {
+      "SCALAR" => 1,
+      "VEC2" => 2,
+      "VEC3" => 3,
+      _ => 4,
+    };
+
+    let view = &self.json["bufferViews"][accessor["bufferView"].as_u64().ok_or("Sparse accessor")? as usize];
+    let buffer = self.buffers
+      .get(view["buffer"].as_u64().unwrap_or(0) as usize)
+      .ok_or("Missing buffer")?;
+    let offset = (view["byteOffset"].as_u64().unwrap_or(0) + accessor["byteOffset"].as_u64().unwrap_or(0)) as usize;
+    let stride = view["byteStride"].as_u64().unwrap_or(0) as usize;
+    let stride = if stride == 0 { size * components } else { stride };
+    let end = stride
+      .checked_mul(count.saturating_sub(1))
+      .and_then(|e| e.checked_add(offset + size * components));
+    if count > 0 && end.map(|e| e > buffer.len()).unwrap_or(true) {
+      return Err(format!("Accessor {} out of its buffer", index));
+    }
+
+    Ok(Accessor {
+      bytes: buffer,
+      count: count,
+      components: components,
+      component: component,
+      size: size,
+      normalized: accessor["normalized"].as_bool().unwrap_or(false),
+      offset: offset,
+      stride: stride,
+    })
+  }
+}
+
+struct Accessor<'a> {
+  bytes: &'a [u8],
+  count: usize,
+  components: usize,
+  component: u32,
+  size: usize,
+  normalized: bool,
+  offset: usize,
+  stride: usize,
+}
+
+impl<'a> Accessor<'a> {
+  /// Bytes of the component c of the element i
+  fn get(&self, i: usize, c: usize) -> &'a [u8] {
+    let at = self.offset + i * self.stride + c * self.size;
+    &self.bytes[at..at + self.size]
+  }
+}
+
+/// JSON and binary chunk of a glb
+fn glb_chunks(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+  let mut json = None;
+  let mut bin = None;
+  let mut at = 12;
+  while at + 8 <= bytes.len() {
+    let length = u32_le(bytes, at) as usize;
+    let data = bytes.get(at + 8..at + 8 + length).ok_or("Truncated glb chunk")?;
+    match u32_le(bytes, at + 4) {
+      CHUNK_JSON => json = Some(data),
+      CHUNK_BIN => bin = Some(data),
+      _ => {}
+    }
+    at += 8 + length;
+  }
+  Ok((json.ok_or("Missing JSON chunk")?, bin))
+}
+
+/// Column major matrix of the node, from its matrix or its TRS
+fn node_matrix(node: &Value) -> [[f32; 4]; 4] {
+  let float = |v: &Value, i: usize, default: f32| v[i].as_f64().map(|v| v as f32).unwrap_or(default);
+  let mut m = IDENTITY;
+  if node["matrix"].is_array() {
+    for (i, value) in m.iter_mut().flatten().enumerate() {
+      *value = float(&node["matrix"], i, *value);
+    }
+    return m;
+  }
+
+  let t = [0, 1, 2].map(|i| float(&node["translation"], i, 0.0));
+  let s = [0, 1, 2].map(|i| float(&node["scale"], i, 1.0));
+  let [x, y, z, w] = [0, 1, 2, 3].map(|i| float(&node["rotation"], i, if i == 3 { 1.0 } else { 0.0 }));
+  let rotation = [
+    [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + z * w), 2.0 * (x * z - y * w)],
+    [2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + x * w)],
+    [2.0 * (x * z + y * w), 2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y)],
+  ];
+  for c in 0..3 {
+    for r in 0..3 {
+      m[c][r] = rotation[c][r] * s[c];
+    }
+  }
+  m[3] = [t[0], t[1], t[2], 1.0];
+  m
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+  value[key].as_array().map(|a| a.as_slice()).unwrap_or(&[])
+}
+
+fn u32_le(bytes: &[u8], i: usize) -> u32 {
+  u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, String> {
+  let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+  let mut bits = 0u32;
+  let mut count = 0;
+  for c in data.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+    let value = match c {
+      b'A'..=b'Z' => c - b'A',
+      b'a'..=b'z' => c - b'a' + 26,
+      b'0'..=b'9' => c - b'0' + 52,
+      b'+' | b'-' => 62,
+      b'/' | b'_' => 63,
+      _ => return Err("Invalid base64 data".to_string()),
+    };
+    bits = bits << 6 | value as u32;
+    count += 6;
+    if count >= 8 {
+      count -= 8;
+      bytes.push((bits >> count) as u8);
+    }
+  }
+  Ok(bytes)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn triangle(color: [f32; 3]) -> MeshData {
+    MeshData {
+      positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+      normals: vec![[0.0, 0.0, 1.0]; 3],
+      colors: vec![color; 3],
+      types: vec![[2, 0, 0, 0]; 3],
+      indices: vec![0, 1, 2],
+      ..Default::default()
+    }
+  }
+
+  fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+  }
+
+  /// JSON and binary chunks of the glb, checking the layout
+  fn chunks(bytes: &[u8]) -> (String, Vec<u8>) {
+    assert_eq!(u32_at(bytes, 0), GLB_MAGIC);
+    assert_eq!(u32_at(bytes, 4), 2);
+    assert_eq!(u32_at(bytes, 8) as usize, bytes.len());
+
+    let json_len = u32_at(bytes, 12) as usize;
+    assert_eq!(json_len % 4, 0);
+    assert_eq!(u32_at(bytes, 16), CHUNK_JSON);
+    let json = String::from_utf8(bytes[20..20 + json_len].to_vec()).unwrap();
+
+    let bin_start = 20 + json_len;
+    if bin_start == bytes.len() {
+      return (json, Vec::new());
+    }
+    let bin_len = u32_at(bytes, bin_start) as usize;
+    assert_eq!(u32_at(bytes, bin_start + 4), CHUNK_BIN);
+    assert_eq!(bin_start + 8 + bin_len, bytes.len());
+    (json, bytes[bin_start + 8..].to_vec())
+  }
+
+  #[test]
+  fn test_glb_nodes() -> Result<(), String> {
+    let mut materials = MaterialRegistry::from_colors(&[[1.0; 3], [0.0, 0.0, 1.0]]);
+    materials.get_mut(2).unwrap().opacity = 0.25;
+
+    let first = triangle([1.0, 0.0, 0.0]);
+    let mut second = triangle([0.0, 1.0, 0.0]);
+    second.transparent.push(triangle([0.0, 0.0, 1.0]));
+    let nodes = vec![
+      MeshNode { name: "chunk 0 0 0".to_string(), translation: [0.0; 3], mesh: &first },
+      MeshNode { name: "chunk 1 0 0".to_string(), translation: [14.0, 0.0, 0.0], mesh: &second },
+    ];
+
+    let (json, bin) = chunks(&write_glb(&nodes, &materials, false));
+    let gltf: Value = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    assert_eq!(array(&gltf, "nodes").len(), 2);
+    assert_eq!(gltf["nodes"][1]["translation"], json!([14.0, 0.0, 0.0]));
+    assert_eq!(gltf["accessors"][0]["min"], json!([0.0, 0.0, 0.0]));
+    assert_eq!(gltf["accessors"][0]["max"], json!([1.0, 1.0, 0.0]));
+    assert_eq!(gltf["meshes"][1]["primitives"][1]["material"], 1);
+    // 3 primitives of 3 vertices: positions, normals, RGBA and indices
+    let primitive = 3 * (12 + 12 + 16 + 4);
+    assert_eq!(bin.len(), 3 * primitive);
+    assert!(json.contains(&format!(r#""buffers":[{{"byteLength":{}}}]"#, bin.len())));
+
+    // The transparent alpha follows the opacity of the material
+    let alpha_offset = 2 * primitive + 36 + 36 + 12;
+    let alpha = f32::from_le_bytes(bin[alpha_offset..alpha_offset + 4].try_into().unwrap());
+    assert_eq!(alpha, 0.25);
+    Ok(())
+  }
+
+  #[test]
+  fn test_glb_merged() -> Result<(), String> {
+    let materials = MaterialRegistry::default();
+    let first = triangle([1.0, 0.0, 0.0]);
+    let second = triangle([0.0, 1.0, 0.0]);
+    let empty = MeshData::default();
+    let nodes = vec![
+      MeshNode { name: "a".to_string(), translation: [0.0; 3], mesh: &first },
+      MeshNode { name: "b".to_string(), translation: [0.0, 14.0, 0.0], mesh: &second },
+      MeshNode { name: "c".to_string(), translation: [0.0; 3], mesh: &empty },
+    ];
+
+    let (json, bin) = chunks(&write_glb(&nodes, &materials, true));
+    let gltf: Value = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    assert_eq!(array(&gltf, "nodes").len(), 1);
+    assert!(!json.contains("translation"));
+    assert_eq!(gltf["accessors"][0]["min"], json!([0.0, 0.0, 0.0]));
+    assert_eq!(gltf["accessors"][0]["max"], json!([1.0, 15.0, 0.0]));
+    assert_eq!(bin.len(), 6 * (12 + 12 + 16) + 6 * 4);
+
+    // Indices of the second mesh follow its vertices
+    let indices: Vec<u32> = bin[bin.len() - 24..]
+      .chunks(4)
+      .map(|b| u32_at(b, 0))
+      .collect();
+    assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+
+    let (json, bin) = chunks(&write_glb(&[], &materials, true));
+    assert!(!json.contains("meshes"));
+    assert!(bin.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_read_glb() -> Result<(), String> {
+    let materials = MaterialRegistry::default();
+    let first = triangle([1.0, 0.0, 0.0]);
+    let mut second = triangle([0.0, 1.0, 0.0]);
+    second.transparent.push(triangle([0.0, 0.0, 1.0]));
+    let nodes = vec![
+      MeshNode { name: "a".to_string(), translation: [0.0; 3], mesh: &first },
+      MeshNode { name: "b".to_string(), translation: [14.0, 0.0, 0.0], mesh: &second },
+    ];
+
+    let mesh = read_gltf(&write_glb(&nodes, &materials, false))?;
+    assert_eq!(mesh.positions.len(), 9);
+    assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(mesh.positions[4], [15.0, 0.0, 0.0]);
+    assert_eq!(mesh.colors, vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+    assert_eq!(read_gltf(&write_glb(&[], &materials, true))?, TriangleMesh::default());
+    assert!(read_gltf(b"not gltf").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_glb_escaped_names() -> Result<(), String> {
+    let materials = MaterialRegistry::default();
+    let mesh = triangle([1.0, 0.0, 0.0]);
+    let name = "a \"quoted\" \\ name\n";
+    let nodes = vec![MeshNode { name: name.to_string(), translation: [0.0; 3], mesh: &mesh }];
+
+    let (json, _) = chunks(&write_glb(&nodes, &materials, false));
+    let gltf: Value = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    assert_eq!(gltf["nodes"][0]["name"], name);
+    assert_eq!(gltf["meshes"][0]["name"], name);
+    Ok(())
+  }
+
+  #[test]
+  fn test_read_gltf_embedded() -> Result<(), String> {
+    // Positions, u16 indices and normalized u8 colors in a data uri
+    let json = r#"{
+      "asset": {"version": "2.0"},
+      "scene": 0,
+      "scenes": [{"nodes": [0]}],
+      "nodes": [
+        {"scale": [2, 2, 2], "children": [1]},
+        {"translation": [1, 0, 0], "rotation": [0, 0, 0.70710678, 0.70710678], "mesh": 0}
+      ],
+      "meshes": [{"primitives": [{
+        "attributes": {"POSITION": 0, "COLOR_0": 2}, "indices": 1, "material": 0
+      }]}],
+      "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [0.5, 1, 1, 1]}}],
+      "accessors": [
+        {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"},
+        {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"},
+        {"bufferView": 2, "componentType": 5121, "normalized": true, "count": 3, "type": "VEC4"}
+      ],
+      "bufferViews": [
+        {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+        {"buffer": 0, "byteOffset": 36, "byteLength": 6},
+        {"buffer": 0, "byteOffset": 44, "byteLength": 12}
+      ],
+      "buffers": [{"byteLength": 56, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAIAAAD/AAD/AAD//wAA//8="}]
+    }"#;
+    let mesh = read_gltf(json.as_bytes())?;
+    assert_eq!(mesh.indices, vec![0, 1, 2]);
+    let near = |a: [f32; 3], b: [f32; 3]| (0..3).all(|i| (a[i] - b[i]).abs() < 1e-5);
+    assert!(near(mesh.positions[0], [2.0, 0.0, 0.0]));
+    assert!(near(mesh.positions[1], [2.0, 2.0, 0.0]));
+    assert!(near(mesh.positions[2], [0.0, 0.0, 0.0]));
+    assert!(near(mesh.colors[0], [1.0 / 6.0, 0.0, 2.0 / 3.0]));
+
+    let external = json.replace("data:application/octet-stream;base64,", "mesh.bin#");
+    assert!(read_gltf(external.as_bytes()).is_err());
+
+    // Positions must be float VEC3, indices unsigned integers
+    let vec2 = json.replace(r#""count": 3, "type": "VEC3""#, r#""count": 3, "type": "VEC2""#);
+    assert!(read_gltf(vec2.as_bytes()).is_err());
+    let shorts = json.replace(r#""componentType": 5126"#, r#""componentType": 5123"#);
+    assert!(read_gltf(shorts.as_bytes()).is_err());
+    let float_indices = json.replace(r#""componentType": 5123"#, r#""componentType": 5126"#);
+    assert!(read_gltf(float_indices.as_bytes()).is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,326 @@
+use crate::chunk::chunk_manager::ChunkManager;
+
+/// Widest heightmap side imported or exported
+pub const MAX_HEIGHTMAP_SIZE: u32 = 4096;
+/// Side of the squares of columns written at once, large heightmaps aren't
+/// held in memory as a whole
+pub const HEIGHTMAP_TILE: u32 = 16;
+/// Most voxels placed in a column, its depth and scale together
+pub const MAX_HEIGHTMAP_COLUMN: i64 = 4096;
+
+/// Grayscale heights, row major from the min x, min z corner. The image
+/// columns go along x and its rows along z, so north is up in top down maps
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Heightmap {
+  pub width: u32,
+  pub height: u32,
+  /// 0 is black and u16::MAX white
+  pub values: Vec<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightmapOptions {
+  /// World x and z of the first pixel
+  pub origin: [i64; 2],
+  /// Voxels between the heights of black and white
+  pub scale: f32,
+  /// Voxel height of black
+  pub offset: i64,
+  /// Voxels filled below the height of black
+  pub depth: i64,
+  pub voxel: u8,
+  /// Clears the terrain above the surface up to the height of white
+  pub clear_above: bool,
+}
+
+impl HeightmapOptions {
+  /// Errors for the columns over MAX_HEIGHTMAP_COLUMN voxels
+  pub fn check(&self) -> Result<(), String> {
+    if !self.scale.is_finite() || self.depth < 0 {
+      return Err("Heightmap scale must be finite and depth positive".to_string());
+    }
+    let column = self.depth.saturating_add(self.scale.abs().ceil() as i64 + 1);
+    if column > MAX_HEIGHTMAP_COLUMN {
+      return Err(format!("Columns of {} voxels over {}", column, MAX_HEIGHTMAP_COLUMN));
+    }
+    Ok(())
+  }
+}
+
+impl Default for HeightmapOptions {
+  fn default() -> Self {
+    Self {
+      origin: [0, 0],
+      scale: 64.0,
+      offset: 0,
+      depth: 4,
+      voxel: 1,
+      clear_above: true,
+    }
+  }
+}
+
+impl Heightmap {
+  /// 8 or 16 bit PNG, the colored ones use the average of their channels
+  pub fn read_png(bytes: &[u8]) -> Result<Self, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (_, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+    let (color, depth) = reader.output_color_type();
+    let info = reader.info();
+    let (width, height) = (info.width, info.height);
+    check_size(width, height)?;
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+
+    let wide = depth == png::BitDepth::Sixteen;
+    let sample = |i: usize| match wide {
+      true => u16::from_be_bytes([data[i * 2], data[i * 2 + 1]]) as u32,
+      false => data[i] as u32 * 257,
+    };
+    let samples = color.samples();
+    let channels = match color {
+      png::ColorType::RGB | png::ColorType::RGBA => 3,
+      _ => 1,
+    };
+    let values = (0..(width * height) as usize)
+      .map(|p| ((0..channels).map(|c| sample(p * samples + c)).sum::<u32>() / channels as u32) as u16)
+      .collect();
+    Ok(Self { width: width, height: height, values: values })
+  }
+
+  /// 16 bit grayscale PNG
+  pub fn write_png(&self) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let data: Vec<u8> = self.values.iter().flat_map(|v| v.to_be_bytes()).collect();
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&data).unwrap();
+    drop(writer);
+    bytes
+  }
+
+  /// Headerless little endian 16 bit heights, like the .r16 and .raw files
+  /// of terrain tools
+  pub fn read_raw16(bytes: &[u8], width: u32) -> Result<Self, String> {
+    let row = width as usize * 2;
+    if width == 0 || bytes.len() % row != 0 {
+      return Err(format!("{} bytes aren't rows of width {}", bytes.len(), width));
+    }
+    let height = (bytes.len() / row) as u32;
+    check_size(width, height)?;
+    let values = bytes
+      .chunks_exact(2)
+      .map(|b| u16::from_le_bytes([b[0], b[1]]))
+      .collect();
+    Ok(Self { width: width, height: height, values: values })
+  }
+
+  pub fn write_raw16(&self) -> Vec<u8> {
+    self.values.iter().flat_map(|v| v.to_le_bytes()).collect()
+  }
+
+  /// Min and max pixels, exclusive, of the HEIGHTMAP_TILE squares covering
+  /// the map
+  pub fn tiles(&self) -> Vec<([u32; 2], [u32; 2])> {
+    let mut tiles = Vec::new();
+    for z in (0..self.height).step_by(HEIGHTMAP_TILE as usize) {
+      for x in (0..self.width).step_by(HEIGHTMAP_TILE as usize) {
+        let max = [(x + HEIGHTMAP_TILE).min(self.width), (z + HEIGHTMAP_TILE).min(self.height)];
+        tiles.push(([x, z], max));
+      }
+    }
+    tiles
+  }
+
+  pub fn get(&self, x: u32, z: u32) -> u16 {
+    self.values[(z * self.width + x) as usize]
+  }
+}
+
+impl ChunkManager {
+  /// Voxel changes of the terrain columns the heightmap sets
+  pub fn place_heightmap(
+    &self,
+    map: &Heightmap,
+    options: &HeightmapOptions,
+    min: [u32; 2],
+    max: [u32; 2]
+  ) -> Vec<([i64; 3], u8)> {
+    let top = options.offset + options.scale.ceil() as i64;
+    let mut voxels = Vec::new();
+    for z in min[1]..max[1].min(map.height) {
+      for x in min[0]..max[0].min(map.width) {
+        let world = [options.origin[0] + x as i64, options.origin[1] + z as i64];
+        let height = surface_height(map.get(x, z), options);
+        for y in (options.offset - options.depth)..=height {
+          voxels.push(([world[0], y, world[1]], options.voxel));
+        }
+        if options.clear_above {
+          for y in (height + 1)..=top {
+            voxels.push(([world[0], y, world[1]], 0));
+          }
+        }
+      }
+    }
+    voxels
+  }
+
+  /// Writes the heightmap into the terrain a tile at a time, returns the keys
+  /// of the changed chunks, sorted
+  pub fn import_heightmap(
+    &mut self,
+    map: &Heightmap,
+    options: &HeightmapOptions
+  ) -> Result<Vec<[i64; 3]>, String> {
+    options.check()?;
+    let mut keys = Vec::new();
+    for (min, max) in map.tiles() {
+      let voxels = self.place_heightmap(map, options, min, max);
+      keys.extend(self.set_voxels(&voxels));
+    }
+    keys.sort();
+    keys.dedup();
+    Ok(keys)
+  }
+
+  /**
+    Top down map of the highest solid voxel of the columns in min..=max,
+    black at min y and white at max y. Liquids and columns without solid
+    voxels are skipped, they stay black. Importing it with min y as the
+    offset and the region height as the scale rebuilds the surface
+  */
+  pub fn export_heightmap(&self, min: [i64; 3], max: [i64; 3]) -> Result<Heightmap, String> {
+    let size = [0, 1, 2].map(|i| max[i] - min[i] + 1);
+    if size[1] < 1 {
+      return Err(format!("Region height {} below 1", size[1]));
+    }
+    check_size(size[0].max(0) as u32, size[2].max(0) as u32)?;
+
+    let range = (max[1] - min[1]).max(1) as f32;
+    let mut map = Heightmap {
+      width: size[0] as u32,
+      height: size[2] as u32,
+      values: Vec::with_capacity((size[0] * size[2]) as usize),
+    };
+    for z in min[2]..=max[2] {
+      for x in min[0]..=max[0] {
+        let surface = (min[1]..=max[1]).rev().find(|y| {
+          let voxel = self.voxel_or_generated(&[x, *y, z]);
+          voxel != 0 && !self.materials.is_liquid(voxel)
+        });
+        let value = match surface {
+          Some(y) => ((y - min[1]) as f32 / range * u16::MAX as f32).round() as u16,
+          None => 0,
+        };
+        map.values.push(value);
+      }
+    }
+    Ok(map)
+  }
+}
+
+fn surface_height(value: u16, options: &HeightmapOptions) -> i64 {
+  options.offset + (value as f32 / u16::MAX as f32 * options.scale).round() as i64
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), String> {
+  let valid = 1..=MAX_HEIGHTMAP_SIZE;
+  if !valid.contains(&width) || !valid.contains(&height) {
+    return Err(format!("Size {}x{} outside of 1..={}", width, height, MAX_HEIGHTMAP_SIZE));
+  }
+  Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ramp() -> Heightmap {
+    Heightmap {
+      width: 3,
+      height: 2,
+      values: vec![0, u16::MAX / 2, u16::MAX, 0, 0, u16::MAX],
+    }
+  }
+
+  #[test]
+  fn test_heightmap_png() -> Result<(), String> {
+    let map = ramp();
+    assert_eq!(Heightmap::read_png(&map.write_png())?, map);
+    assert_eq!(Heightmap::read_raw16(&map.write_raw16(), 3)?, map);
+    assert!(Heightmap::read_raw16(&map.write_raw16(), 4).is_err());
+
+    // 8 bit RGB scales up to 16 bits
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(&[255, 255, 255, 30, 60, 90]).unwrap();
+    let rgb = Heightmap::read_png(&bytes)?;
+    assert_eq!(rgb.values, vec![u16::MAX, 60 * 257]);
+
+    assert!(Heightmap::read_png(b"not a png").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_heightmap_import_export() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let options = HeightmapOptions {
+      origin: [13, -1],
+      scale: 20.0,
+      offset: 100,
+      depth: 2,
+      voxel: 3,
+      ..Default::default()
+    };
+    let keys = chunk_manager.import_heightmap(&ramp(), &options)?;
+    assert!(keys.len() > 1);
+    assert_eq!(chunk_manager.get_voxel(&[13, 100, -1]), 3);
+    assert_eq!(chunk_manager.get_voxel(&[13, 101, -1]), 0);
+    assert_eq!(chunk_manager.get_voxel(&[13, 98, -1]), 3);
+    assert_eq!(chunk_manager.get_voxel(&[14, 110, -1]), 3);
+    assert_eq!(chunk_manager.get_voxel(&[14, 111, -1]), 0);
+    assert_eq!(chunk_manager.get_voxel(&[15, 120, 0]), 3);
+
+    let map = chunk_manager.export_heightmap([13, 100, -1], [15, 120, 0])?;
+    assert_eq!(map.width, 3);
+    assert_eq!(map.height, 2);
+    let expected: Vec<u16> = [0, 10, 20, 0, 0, 20]
+      .iter()
+      .map(|h| (*h as f32 / 20.0 * u16::MAX as f32).round() as u16)
+      .collect();
+    assert_eq!(map.values, expected);
+
+    assert!(chunk_manager.export_heightmap([0, 5, 0], [10, 4, 10]).is_err());
+    assert!(chunk_manager.export_heightmap([0, 0, 0], [5000, 4, 0]).is_err());
+
+    let tall = HeightmapOptions { scale: 1e9, ..options.clone() };
+    assert!(chunk_manager.import_heightmap(&ramp(), &tall).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_heightmap_tiles() -> Result<(), String> {
+    let map = Heightmap { width: 40, height: 17, values: vec![u16::MAX; 40 * 17] };
+    let tiles = map.tiles();
+    assert_eq!(tiles.len(), 3 * 2);
+    assert_eq!(tiles[0], ([0, 0], [16, 16]));
+    assert_eq!(tiles[5], ([32, 16], [40, 17]));
+
+    // The tiles place every column once
+    let chunk_manager = ChunkManager::default();
+    let options = HeightmapOptions { scale: 2.0, depth: 0, ..Default::default() };
+    let count: usize = tiles
+      .iter()
+      .map(|(min, max)| chunk_manager.place_heightmap(&map, &options, *min, *max).len())
+      .sum();
+    assert_eq!(count, 40 * 17 * 3);
+    Ok(())
+  }
+}
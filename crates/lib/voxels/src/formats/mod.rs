@@ -0,0 +1,17 @@
+use crate::data::voxel_octree::MeshData;
+
+pub mod vox;
+pub mod gltf;
+pub mod weld;
+pub mod obj;
+pub mod stl;
+pub mod ply;
+pub mod voxelize;
+pub mod heightmap;
+
+/// Chunk mesh placed in an exported scene
+pub struct MeshNode<'a> {
+  pub name: String,
+  pub translation: [f32; 3],
+  pub mesh: &'a MeshData,
+}
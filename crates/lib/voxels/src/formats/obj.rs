@@ -0,0 +1,234 @@
+use std::fmt::Write;
+use hashbrown::HashMap;
+use crate::data::materials::MaterialRegistry;
+use super::{weld::WorldMesh, voxelize::TriangleMesh};
+
+/// Where the OBJ keeps the palette colors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjColors {
+  /// A material per voxel value in the MTL file
+  Materials,
+  /// Colors after the vertex positions, read by most tools
+  Vertex,
+}
+
+/**
+  Wavefront OBJ of the mesh and its MTL file, empty with vertex colors. The
+  OBJ refers to the MTL as mtl_name
+*/
+pub fn write_obj(
+  mesh: &WorldMesh,
+  materials: &MaterialRegistry,
+  colors: ObjColors,
+  mtl_name: &str
+) -> (String, String) {
+  let mut obj = String::new();
+  let mut mtl = String::new();
+  if colors == ObjColors::Materials {
+    writeln!(obj, "mtllib {}", mtl_name).unwrap();
+  }
+  writeln!(obj, "o voxels").unwrap();
+
+  for (i, p) in mesh.positions.iter().enumerate() {
+    match colors {
+      ObjColors::Materials => writeln!(obj, "v {} {} {}", p[0], p[1], p[2]).unwrap(),
+      ObjColors::Vertex => {
+        let c = mesh.colors[i];
+        writeln!(obj, "v {} {} {} {} {} {}", p[0], p[1], p[2], c[0], c[1], c[2]).unwrap()
+      }
+    }
+  }
+  for n in mesh.normals.iter() {
+    writeln!(obj, "vn {} {} {}", n[0], n[1], n[2]).unwrap();
+  }
+
+  // Faces grouped by material, in the order of the voxel values
+  let mut order: Vec<usize> = (0..mesh.triangle_count()).collect();
+  if colors == ObjColors::Materials {
+    order.sort_by_key(|t| mesh.voxels[*t]);
+  }
+  let mut current = None;
+  for t in order {
+    let voxel = mesh.voxels[t];
+    if colors == ObjColors::Materials && current != Some(voxel) {
+      current = Some(voxel);
+      let name = material_name(materials, voxel);
+      writeln!(obj, "usemtl {}", name).unwrap();
+      write_material(&mut mtl, materials, voxel, &name);
+    }
+
+    let f = [0, 1, 2].map(|i| mesh.indices[t * 3 + i] + 1);
+    writeln!(obj, "f {}//{} {}//{} {}//{}", f[0], f[0], f[1], f[1], f[2], f[2]).unwrap();
+  }
+  (obj, mtl)
+}
+
+/// Value and name of the material, without the characters MTL names can't hold
+fn material_name(materials: &MaterialRegistry, voxel: u8) -> String {
+  let name: String = match materials.get(voxel) {
+    Some(m) => m.name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect(),
+    None => "none".to_string(),
+  };
+  format!("voxel{}_{}", voxel, name)
+}
+
+fn write_material(mtl: &mut String, materials: &MaterialRegistry, voxel: u8, name: &str) {
+  let material = materials.get(voxel);
+  let color = material.map(|m| m.color).unwrap_or([1.0; 3]);
+  writeln!(mtl, "newmtl {}", name).unwrap();
+  writeln!(mtl, "Kd {} {} {}", color[0], color[1], color[2]).unwrap();
+  if materials.separate_pass(voxel) {
+    writeln!(mtl, "d {}", material.map(|m| m.opacity).unwrap_or(1.0)).unwrap();
+  }
+  writeln!(mtl, "illum 1").unwrap();
+  writeln!(mtl).unwrap();
+}
+
+/**
+  Triangles of a Wavefront OBJ, the polygons are split in fans. A triangle
+  takes the Kd color of its material in the MTL file, else the average of
+  its vertex colors
+*/
+pub fn read_obj(obj: &str, mtl: Option<&str>) -> Result<TriangleMesh, String> {
+  let mut kd = HashMap::new();
+  let mut name = String::new();
+  for line in mtl.unwrap_or("").lines() {
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("newmtl") => name = words.collect::<Vec<_>>().join(" "),
+      Some("Kd") => { kd.insert(name.clone(), parse_floats::<3>(words, line)?); }
+      _ => {}
+    }
+  }
+
+  let mut mesh = TriangleMesh::default();
+  let mut vertex_colors = Vec::new();
+  let mut material = None;
+  for (number, line) in obj.lines().enumerate() {
+    let mut words = line.split_whitespace();
+    match words.next() {
+      Some("v") => {
+        let values: Vec<&str> = words.collect();
+        let position = parse_floats::<3>(values.iter().copied(), line)?;
+        let color = match values.len() >= 6 {
+          true => parse_floats::<3>(values[3..].iter().copied(), line)?,
+          false => [1.0; 3],
+        };
+        mesh.positions.push(position);
+        vertex_colors.push(color);
+      }
+      Some("usemtl") => {
+        let name = words.collect::<Vec<_>>().join(" ");
+        material = kd.get(&name).copied();
+      }
+      Some("f") => {
+        let mut face = Vec::new();
+        for word in words {
+          let index: i64 = word.split('/').next().unwrap().parse()
+            .map_err(|_| format!("Invalid face on line {}", number + 1))?;
+          let count = mesh.positions.len() as i64;
+          let index = if index < 0 { count + index } else { index - 1 };
+          if index < 0 || index >= count {
+            return Err(format!("Face index out of bounds on line {}", number + 1));
+          }
+          face.push(index as u32);
+        }
+        for i in 1..face.len().saturating_sub(1) {
+          let tri = [face[0], face[i], face[i + 1]];
+          mesh.indices.extend(tri);
+          mesh.colors.push(material.unwrap_or_else(|| {
+            let colors = tri.map(|v| vertex_colors[v as usize]);
+            [0, 1, 2].map(|c| (colors[0][c] + colors[1][c] + colors[2][c]) / 3.0)
+          }));
+        }
+      }
+      _ => {}
+    }
+  }
+  Ok(mesh)
+}
+
+fn parse_floats<'a, const N: usize>(
+  mut words: impl Iterator<Item = &'a str>,
+  line: &str
+) -> Result<[f32; N], String> {
+  let mut values = [0.0; N];
+  for value in values.iter_mut() {
+    *value = words.next()
+      .and_then(|w| w.parse().ok())
+      .ok_or_else(|| format!("Invalid line \"{}\"", line))?;
+  }
+  Ok(values)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn mesh() -> WorldMesh {
+    WorldMesh {
+      positions: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+      normals: vec![[0.0, 0.0, 1.0]; 4],
+      colors: vec![[1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.5; 3]],
+      indices: vec![0, 1, 2, 0, 3, 1, 0, 2, 3],
+      voxels: vec![2, 1, 2],
+    }
+  }
+
+  #[test]
+  fn test_obj_materials() -> Result<(), String> {
+    let mut materials = MaterialRegistry::from_colors(&[[0.5; 3], [0.0, 0.0, 1.0]]);
+    let water = materials.get_mut(2).unwrap();
+    water.name = "Deep water".to_string();
+    water.liquid = true;
+    water.opacity = 0.25;
+
+    let (obj, mtl) = write_obj(&mesh(), &materials, ObjColors::Materials, "world.mtl");
+    let lines: Vec<&str> = obj.lines().collect();
+    assert_eq!(lines[0], "mtllib world.mtl");
+    assert_eq!(lines.iter().filter(|l| l.starts_with("v ")).count(), 4);
+    assert_eq!(lines.iter().filter(|l| l.starts_with("vn ")).count(), 4);
+    assert!(lines.contains(&"v 1 0 0"));
+
+    let faces: Vec<&&str> = lines.iter().filter(|l| l.starts_with("usemtl") || l.starts_with("f ")).collect();
+    assert_eq!(faces, vec![
+      &"usemtl voxel1_Voxel_1", &"f 1//1 4//4 2//2",
+      &"usemtl voxel2_Deep_water", &"f 1//1 2//2 3//3", &"f 1//1 3//3 4//4",
+    ]);
+
+    assert!(mtl.contains("newmtl voxel1_Voxel_1\nKd 0.5 0.5 0.5\nillum 1"));
+    assert!(mtl.contains("newmtl voxel2_Deep_water\nKd 0 0 1\nd 0.25\n"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_obj_vertex_colors() -> Result<(), String> {
+    let materials = MaterialRegistry::default();
+    let (obj, mtl) = write_obj(&mesh(), &materials, ObjColors::Vertex, "world.mtl");
+    assert!(mtl.is_empty());
+    assert!(!obj.contains("mtllib") && !obj.contains("usemtl"));
+    assert!(obj.contains("v 0 1 0 0 1 0\n"));
+    assert!(obj.contains("f 1//1 2//2 3//3\nf 1//1 4//4 2//2\n"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_read_obj() -> Result<(), String> {
+    let mtl = "newmtl red\nKd 1 0 0\n";
+    let obj = "mtllib a.mtl\n\
+      v 0 0 0 0 1 0\nv 1 0 0 0 1 0\nv 1 1 0 0 0 1\nv 0 1 0\n\
+      f 1//1 2//2 3//3\n\
+      usemtl red\n\
+      f -4/1 -3/2 -2/3 -1/4\n";
+    let mesh = read_obj(obj, Some(mtl))?;
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.indices, vec![0, 1, 2, 0, 1, 2, 0, 2, 3]);
+    assert_eq!(mesh.colors[0], [0.0, 2.0 / 3.0, 1.0 / 3.0]);
+    assert_eq!(mesh.colors[1..], [[1.0, 0.0, 0.0], [1.0, 0.0, 0.0]]);
+
+    assert!(read_obj("v 0 0 0\nf 1 2 3\n", None).is_err());
+    assert!(read_obj("v 0 x 0\n", None).is_err());
+    Ok(())
+  }
+}
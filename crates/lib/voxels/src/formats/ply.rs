@@ -0,0 +1,73 @@
+use super::weld::WorldMesh;
+
+/// Binary little endian PLY of the mesh with normals and vertex colors
+pub fn write_ply(mesh: &WorldMesh) -> Vec<u8> {
+  let header = format!(
+    concat!(
+      "ply\n",
+      "format binary_little_endian 1.0\n",
+      "comment voxels\n",
+      "element vertex {}\n",
+      "property float x\n",
+      "property float y\n",
+      "property float z\n",
+      "property float nx\n",
+      "property float ny\n",
+      "property float nz\n",
+      "property uchar red\n",
+      "property uchar green\n",
+      "property uchar blue\n",
+      "element face {}\n",
+      "property list uchar uint vertex_indices\n",
+      "end_header\n",
+    ),
+    mesh.positions.len(),
+    mesh.triangle_count()
+  );
+
+  let mut bytes = header.into_bytes();
+  for i in 0..mesh.positions.len() {
+    for v in mesh.positions[i].iter().chain(mesh.normals[i].iter()) {
+      bytes.extend(v.to_le_bytes());
+    }
+    bytes.extend(mesh.colors[i].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+  }
+  for tri in mesh.indices.chunks_exact(3) {
+    bytes.push(3);
+    for i in tri.iter() {
+      bytes.extend(i.to_le_bytes());
+    }
+  }
+  bytes
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_ply() -> Result<(), String> {
+    let mesh = WorldMesh {
+      positions: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+      normals: vec![[0.0, 0.0, 1.0]; 3],
+      colors: vec![[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+      indices: vec![0, 1, 2],
+      voxels: vec![1],
+    };
+    let bytes = write_ply(&mesh);
+    let end = b"end_header\n";
+    let start = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+    let header = String::from_utf8(bytes[..start].to_vec()).unwrap();
+    assert!(header.contains("element vertex 3\n"));
+    assert!(header.contains("element face 1\n"));
+
+    let vertex = 6 * 4 + 3;
+    assert_eq!(bytes.len(), start + 3 * vertex + 1 + 3 * 4);
+    assert_eq!(&bytes[start + 24..start + 27], &[255, 0, 0]);
+    assert_eq!(&bytes[start + vertex + 24..start + vertex + 27], &[0, 255, 0]);
+    assert_eq!(bytes[start + 3 * vertex], 3);
+    assert_eq!(&bytes[bytes.len() - 4..], &2_u32.to_le_bytes());
+    Ok(())
+  }
+}
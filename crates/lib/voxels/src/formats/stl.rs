@@ -0,0 +1,50 @@
+use super::weld::WorldMesh;
+
+/// Binary STL of the mesh, the facet normals follow the winding
+pub fn write_stl(mesh: &WorldMesh) -> Vec<u8> {
+  let count = mesh.triangle_count();
+  let mut bytes = Vec::with_capacity(84 + count * 50);
+  let mut header = b"voxels".to_vec();
+  header.resize(80, 0);
+  bytes.extend(header);
+  bytes.extend((count as u32).to_le_bytes());
+
+  for t in 0..count {
+    let mut values = vec![mesh.face_normal(t)];
+    for i in 0..3 {
+      values.push(mesh.positions[mesh.indices[t * 3 + i] as usize]);
+    }
+    for v in values.iter().flatten() {
+      bytes.extend(v.to_le_bytes());
+    }
+    bytes.extend(0_u16.to_le_bytes());
+  }
+  bytes
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_stl() -> Result<(), String> {
+    let mesh = WorldMesh {
+      positions: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+      normals: vec![[0.0, 0.0, 1.0]; 3],
+      colors: vec![[1.0; 3]; 3],
+      indices: vec![0, 1, 2, 0, 2, 1],
+      voxels: vec![1, 1],
+    };
+    let bytes = write_stl(&mesh);
+    assert_eq!(bytes.len(), 84 + 2 * 50);
+    assert_eq!(&bytes[80..84], &2_u32.to_le_bytes());
+
+    let float = |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    assert_eq!([float(84), float(88), float(92)], [0.0, 0.0, 1.0]);
+    assert_eq!([float(84 + 50), float(88 + 50), float(92 + 50)], [0.0, 0.0, -1.0]);
+    // Second vertex of the first facet
+    assert_eq!([float(108), float(112), float(116)], [1.0, 0.0, 0.0]);
+    Ok(())
+  }
+}
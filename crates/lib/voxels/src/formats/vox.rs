@@ -0,0 +1,610 @@
+use hashbrown::HashMap;
+use crate::chunk::chunk_manager::ChunkManager;
+use crate::data::materials::VoxelMaterial;
+
+/// Largest model side a .vox file holds
+pub const MAX_VOX_SIZE: u32 = 256;
+
+/// Model of a .vox file, z up like MagicaVoxel
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxModel {
+  pub size: [u32; 3],
+  /// Corner of the model in the scene, from the scene graph translations
+  pub offset: [i32; 3],
+  /// Local positions and palette indices, 1..=255
+  pub voxels: Vec<([u8; 3], u8)>,
+}
+
+/// Models and palette of a MagicaVoxel file
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxFile {
+  pub models: Vec<VoxModel>,
+  /// RGBA of the palette index i + 1
+  pub palette: Vec<[u8; 4]>,
+}
+
+/// How the .vox palette maps onto the ChunkManager colors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PaletteMode {
+  /// Nearest existing color
+  Nearest,
+  /// New materials for the colors not in the palette, nearest once full
+  Append,
+}
+
+impl Default for VoxFile {
+  /// Files without a palette use the MagicaVoxel default one
+  fn default() -> Self {
+    Self {
+      models: Vec::new(),
+      palette: default_vox_palette(),
+    }
+  }
+}
+
+/**
+  MagicaVoxel's default palette for the indices 1..=255: the 6x6x6 color cube
+  without black, then ramps of red, green, blue and gray
+*/
+pub fn default_vox_palette() -> Vec<[u8; 4]> {
+  const CUBE: [u8; 6] = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+  const RAMP: [u8; 10] = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+
+  let mut palette = Vec::new();
+  for r in CUBE {
+    for g in CUBE {
+      for b in CUBE {
+        palette.push([r, g, b, 255]);
+      }
+    }
+  }
+  palette.pop();
+
+  for channel in 0..3 {
+    for v in RAMP {
+      let mut rgba = [0, 0, 0, 255];
+      rgba[channel] = v;
+      palette.push(rgba);
+    }
+  }
+  for v in RAMP {
+    palette.push([v, v, v, 255]);
+  }
+  palette
+}
+
+impl VoxFile {
+  /**
+    Reads the models, the palette and the translations of the scene graph.
+    Rotations are ignored, a model used by several shapes is placed at the
+    first one
+  */
+  pub fn read(bytes: &[u8]) -> Result<Self, String> {
+    let mut reader = Reader { bytes: bytes, pos: 0 };
+    if reader.take(4)? != b"VOX " {
+      return Err("Not a .vox file".to_string());
+    }
+    reader.i32()?;
+
+    let (id, _, _) = reader.chunk_header()?;
+    if id != *b"MAIN" {
+      return Err("Missing MAIN chunk".to_string());
+    }
+
+    let mut vox = VoxFile::default();
+    let mut nodes = HashMap::new();
+    while reader.pos < bytes.len() {
+      let (id, content, children) = reader.chunk_header()?;
+      let mut content = Reader { bytes: reader.take(content)?, pos: 0 };
+      reader.take(children)?;
+
+      match &id {
+        b"SIZE" => {
+          let size = [content.i32()?, content.i32()?, content.i32()?];
+          if size.iter().any(|s| *s < 0 || *s as u32 > MAX_VOX_SIZE) {
+            return Err(format!("Invalid model size {:?}", size));
+          }
+          vox.models.push(VoxModel {
+            size: size.map(|s| s as u32),
+            ..Default::default()
+          });
+        }
+        b"XYZI" => {
+          let model = vox.models.last_mut().ok_or("XYZI before SIZE")?;
+          let count = content.i32()?.max(0) as usize;
+          for _ in 0..count {
+            let v = content.take(4)?;
+            if v[3] > 0 {
+              model.voxels.push(([v[0], v[1], v[2]], v[3]));
+            }
+          }
+        }
+        b"RGBA" => {
+          for i in 0..vox.palette.len() {
+            let c = content.take(4)?;
+            vox.palette[i] = [c[0], c[1], c[2], c[3]];
+          }
+        }
+        b"nTRN" => {
+          let id = content.i32()?;
+          content.dict()?;
+          let child = content.i32()?;
+          content.take(8)?;
+          let mut translation = [0; 3];
+          if content.i32()? > 0 {
+            if let Some(t) = content.dict()?.get("_t") {
+              let t: Vec<i32> = t.split(' ').filter_map(|v| v.parse().ok()).collect();
+              if t.len() == 3 {
+                translation = [t[0], t[1], t[2]];
+              }
+            }
+          }
+          nodes.insert(id, Node::Transform(translation, child));
+        }
+        b"nGRP" => {
+          let id = content.i32()?;
+          content.dict()?;
+          let count = content.i32()?.max(0);
+          let mut children = Vec::new();
+          for _ in 0..count {
+            children.push(content.i32()?);
+          }
+          nodes.insert(id, Node::Group(children));
+        }
+        b"nSHP" => {
+          let id = content.i32()?;
+          content.dict()?;
+          let count = content.i32()?.max(0);
+          let mut models = Vec::new();
+          for _ in 0..count {
+            models.push(content.i32()?);
+            content.dict()?;
+          }
+          nodes.insert(id, Node::Shape(models));
+        }
+        _ => {}
+      }
+    }
+
+    let mut placed = vec![false; vox.models.len()];
+    place_models(&nodes, 0, [0; 3], &mut vox.models, &mut placed, 0);
+    Ok(vox)
+  }
+
+  /// Writes the models and the palette, without a scene graph
+  pub fn write(&self) -> Vec<u8> {
+    let mut children = Vec::new();
+    for model in self.models.iter() {
+      let mut size = Vec::new();
+      for s in model.size.iter() {
+        size.extend((*s as i32).to_le_bytes());
+      }
+      write_chunk(&mut children, b"SIZE", &size);
+
+      let mut xyzi = Vec::new();
+      xyzi.extend((model.voxels.len() as i32).to_le_bytes());
+      for (pos, index) in model.voxels.iter() {
+        xyzi.extend([pos[0], pos[1], pos[2], *index]);
+      }
+      write_chunk(&mut children, b"XYZI", &xyzi);
+    }
+
+    let mut rgba: Vec<u8> = self.palette.iter().flatten().cloned().collect();
+    rgba.resize(256 * 4, 0);
+    write_chunk(&mut children, b"RGBA", &rgba);
+
+    let mut bytes = Vec::new();
+    bytes.extend(b"VOX ");
+    bytes.extend(150_i32.to_le_bytes());
+    bytes.extend(b"MAIN");
+    bytes.extend(0_i32.to_le_bytes());
+    bytes.extend((children.len() as i32).to_le_bytes());
+    bytes.extend(children);
+    bytes
+  }
+
+  /// Min and max corners of the models in the scene
+  pub fn bounds(&self) -> ([i32; 3], [i32; 3]) {
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for model in self.models.iter() {
+      for i in 0..3 {
+        min[i] = min[i].min(model.offset[i]);
+        max[i] = max[i].max(model.offset[i] + model.size[i] as i32 - 1);
+      }
+    }
+    (min, max)
+  }
+
+  /// World voxels in a single model fitted to their bounds, the voxel values
+  /// are the indices in the palette of the colors
+  pub fn from_voxels(voxels: &[([i64; 3], u8)], colors: &[[f32; 3]]) -> Result<Self, String> {
+    let mut min = [i64::MAX; 3];
+    let mut max = [i64::MIN; 3];
+    for (pos, _) in voxels.iter() {
+      for i in 0..3 {
+        min[i] = min[i].min(pos[i]);
+        max[i] = max[i].max(pos[i]);
+      }
+    }
+    if voxels.is_empty() {
+      (min, max) = ([0; 3], [0; 3]);
+    }
+    check_region(min, max)?;
+    Ok(Self::from_region(min, max, voxels, colors))
+  }
+
+  fn from_region(
+    min: [i64; 3],
+    max: [i64; 3],
+    voxels: &[([i64; 3], u8)],
+    colors: &[[f32; 3]]
+  ) -> Self {
+    let size = [0, 1, 2].map(|i| max[i] - min[i] + 1);
+    let mut model = VoxModel {
+      size: [size[0] as u32, size[2] as u32, size[1] as u32],
+      ..Default::default()
+    };
+    for ([x, y, z], voxel) in voxels.iter() {
+      let local = [x - min[0], max[2] - z, y - min[1]];
+      model.voxels.push((local.map(|v| v as u8), *voxel));
+    }
+
+    let mut palette: Vec<[u8; 4]> = colors.iter().take(255).map(|c| to_rgba(c)).collect();
+    palette.resize(255, [0; 4]);
+    VoxFile {
+      models: vec![model],
+      palette: palette,
+    }
+  }
+}
+
+impl ChunkManager {
+  /**
+    Voxel values of the palette indices the models use, 0 for the unused
+    ones. Appending adds the materials to the registry and the colors
+  */
+  pub fn vox_palette_values(&mut self, vox: &VoxFile, mode: PaletteMode) -> Vec<u8> {
+    let mut used = [false; 256];
+    for model in vox.models.iter() {
+      for (_, index) in model.voxels.iter() {
+        used[*index as usize] = true;
+      }
+    }
+
+    let mut values = vec![0; 256];
+    for index in 1..256 {
+      if !used[index] {
+        continue;
+      }
+      let rgba = vox.palette.get(index - 1).cloned().unwrap_or([255; 4]);
+      let exact = self.colors.iter().position(|c| to_rgba(c)[0..3] == rgba[0..3]);
+      values[index] = match (exact, mode) {
+        (Some(i), _) => i as u8 + 1,
+        (None, PaletteMode::Append) if self.materials.materials.len() < 255 => {
+          let name = format!("Vox {}", index);
+          let mut materials = self.materials.clone();
+          materials.materials.push(VoxelMaterial::new(&name, to_color(&rgba)));
+          self.set_materials(materials);
+          self.materials.materials.len() as u8
+        }
+        _ => self.materials.nearest(&to_color(&rgba)),
+      };
+    }
+    values
+  }
+
+  /**
+    Places the models with the scene's min corner at pos, over the existing
+    voxels as the empty cells are skipped. The .vox z up becomes y up.
+    Returns the keys of the changed chunks, sorted
+  */
+  pub fn import_vox(
+    &mut self,
+    vox: &VoxFile,
+    pos: [i64; 3],
+    mode: PaletteMode
+  ) -> Vec<[i64; 3]> {
+    let voxels = self.place_vox(vox, pos, mode);
+    self.set_voxels(&voxels)
+  }
+
+  /// World positions and voxel values of the models import_vox sets
+  pub fn place_vox(
+    &mut self,
+    vox: &VoxFile,
+    pos: [i64; 3],
+    mode: PaletteMode
+  ) -> Vec<([i64; 3], u8)> {
+    if vox.models.is_empty() {
+      return Vec::new();
+    }
+    let values = self.vox_palette_values(vox, mode);
+    let (min, max) = vox.bounds();
+
+    let mut voxels = Vec::new();
+    for model in vox.models.iter() {
+      for (local, index) in model.voxels.iter() {
+        let s = [0, 1, 2].map(|i| model.offset[i] + local[i] as i32);
+        let world = [
+          pos[0] + (s[0] - min[0]) as i64,
+          pos[1] + (s[2] - min[2]) as i64,
+          pos[2] + (max[1] - s[1]) as i64,
+        ];
+        voxels.push((world, values[*index as usize]));
+      }
+    }
+    voxels
+  }
+
+  /// Voxels inside min..=max as a single model, the voxel values are the
+  /// palette indices. Unloaded chunks are sampled from the terrain generator
+  pub fn export_vox(&self, min: [i64; 3], max: [i64; 3]) -> Result<VoxFile, String> {
+    check_region(min, max)?;
+    let mut voxels = Vec::new();
+    for x in min[0]..=max[0] {
+      for y in min[1]..=max[1] {
+        for z in min[2]..=max[2] {
+          let voxel = self.voxel_or_generated(&[x, y, z]);
+          if voxel != 0 {
+            voxels.push(([x, y, z], voxel));
+          }
+        }
+      }
+    }
+    Ok(VoxFile::from_region(min, max, &voxels, &self.colors))
+  }
+}
+
+fn check_region(min: [i64; 3], max: [i64; 3]) -> Result<(), String> {
+  let size = [0, 1, 2].map(|i| max[i] - min[i] + 1);
+  if size.iter().any(|s| *s < 1 || *s > MAX_VOX_SIZE as i64) {
+    return Err(format!("Region size {:?} outside of 1..={}", size, MAX_VOX_SIZE));
+  }
+  Ok(())
+}
+
+enum Node {
+  /// Translation and child node
+  Transform([i32; 3], i32),
+  Group(Vec<i32>),
+  Shape(Vec<i32>),
+}
+
+fn place_models(
+  nodes: &HashMap<i32, Node>,
+  id: i32,
+  translation: [i32; 3],
+  models: &mut Vec<VoxModel>,
+  placed: &mut [bool],
+  depth: u32,
+) {
+  // Malformed graphs can loop
+  if depth > 64 {
+    return;
+  }
+  match nodes.get(&id) {
+    Some(Node::Transform(t, child)) => {
+      let t = [0, 1, 2].map(|i| translation[i] + t[i]);
+      place_models(nodes, *child, t, models, placed, depth + 1);
+    }
+    Some(Node::Group(children)) => {
+      for child in children.iter() {
+        place_models(nodes, *child, translation, models, placed, depth + 1);
+      }
+    }
+    Some(Node::Shape(ids)) => {
+      for model_id in ids.iter() {
+        let i = *model_id as usize;
+        if *model_id < 0 || i >= models.len() || placed[i] {
+          continue;
+        }
+        // Translations are to the model's center
+        let size = models[i].size;
+        models[i].offset = [0, 1, 2].map(|a| translation[a] - size[a] as i32 / 2);
+        placed[i] = true;
+      }
+    }
+    None => {}
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+    if self.pos + len > self.bytes.len() {
+      return Err("Unexpected end of .vox data".to_string());
+    }
+    let bytes = &self.bytes[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(bytes)
+  }
+
+  fn i32(&mut self) -> Result<i32, String> {
+    let b = self.take(4)?;
+    Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+  }
+
+  /// Id, content and children sizes
+  fn chunk_header(&mut self) -> Result<([u8; 4], usize, usize), String> {
+    let id = self.take(4)?;
+    let content = self.i32()?.max(0) as usize;
+    let children = self.i32()?.max(0) as usize;
+    Ok(([id[0], id[1], id[2], id[3]], content, children))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    let len = self.i32()?.max(0) as usize;
+    Ok(String::from_utf8_lossy(self.take(len)?).to_string())
+  }
+
+  fn dict(&mut self) -> Result<HashMap<String, String>, String> {
+    let mut dict = HashMap::new();
+    for _ in 0..self.i32()?.max(0) {
+      let key = self.string()?;
+      let value = self.string()?;
+      dict.insert(key, value);
+    }
+    Ok(dict)
+  }
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+  bytes.extend(id);
+  bytes.extend((content.len() as i32).to_le_bytes());
+  bytes.extend(0_i32.to_le_bytes());
+  bytes.extend(content);
+}
+
+fn to_rgba(color: &[f32; 3]) -> [u8; 4] {
+  let c = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+  [c[0], c[1], c[2], 255]
+}
+
+fn to_color(rgba: &[u8; 4]) -> [f32; 3] {
+  [rgba[0], rgba[1], rgba[2]].map(|c| c as f32 / 255.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn model() -> VoxFile {
+    let mut vox = VoxFile::default();
+    vox.palette[0] = [255, 0, 0, 255];
+    vox.palette[1] = [0, 0, 255, 255];
+    vox.models.push(VoxModel {
+      size: [20, 2, 3],
+      offset: [0; 3],
+      voxels: vec![([0, 0, 0], 1), ([19, 0, 0], 1), ([0, 1, 2], 2)],
+    });
+    vox
+  }
+
+  #[test]
+  fn test_vox_read_write() -> Result<(), String> {
+    let vox = model();
+    let bytes = vox.write();
+    assert_eq!(&bytes[0..4], b"VOX ");
+    assert_eq!(VoxFile::read(&bytes)?, vox);
+
+    assert!(VoxFile::read(b"VOX").is_err());
+    assert!(VoxFile::read(&bytes[0..bytes.len() - 10]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_vox_default_palette() -> Result<(), String> {
+    let palette = default_vox_palette();
+    assert_eq!(palette.len(), 255);
+    assert_eq!(palette[0], [0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(palette[1], [0xff, 0xff, 0xcc, 0xff]);
+    assert_eq!(palette[214], [0x00, 0x00, 0x33, 0xff]);
+    assert_eq!(palette[215], [0xee, 0x00, 0x00, 0xff]);
+    assert_eq!(palette[254], [0x11, 0x11, 0x11, 0xff]);
+
+    // Files without an RGBA chunk
+    let mut bytes = model().write();
+    let rgba = 12 + 256 * 4;
+    bytes.truncate(bytes.len() - rgba);
+    let children = i32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
+    bytes[16..20].copy_from_slice(&(children - rgba as i32).to_le_bytes());
+    assert_eq!(VoxFile::read(&bytes)?.palette, palette);
+    Ok(())
+  }
+
+  #[test]
+  fn test_vox_scene_translation() -> Result<(), String> {
+    let mut bytes = model().write();
+    let dict = |pairs: &[(&str, &str)]| {
+      let mut d = (pairs.len() as i32).to_le_bytes().to_vec();
+      for (k, v) in pairs {
+        for s in [k, v] {
+          d.extend((s.len() as i32).to_le_bytes());
+          d.extend(s.as_bytes());
+        }
+      }
+      d
+    };
+    let ints = |v: &[i32]| v.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<u8>>();
+
+    let mut graph = Vec::new();
+    let mut trn = ints(&[0]);
+    trn.extend(dict(&[]));
+    trn.extend(ints(&[1, -1, 0, 1]));
+    trn.extend(dict(&[("_t", "10 -4 3")]));
+    write_chunk(&mut graph, b"nTRN", &trn);
+    let mut shp = ints(&[1]);
+    shp.extend(dict(&[]));
+    shp.extend(ints(&[1, 0]));
+    shp.extend(dict(&[]));
+    write_chunk(&mut graph, b"nSHP", &shp);
+
+    let children = i32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
+    bytes[16..20].copy_from_slice(&(children + graph.len() as i32).to_le_bytes());
+    bytes.extend(graph);
+
+    let vox = VoxFile::read(&bytes)?;
+    assert_eq!(vox.models[0].offset, [0, -5, 2]);
+    assert_eq!(vox.bounds(), ([0, -5, 2], [19, -4, 4]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_vox_import_export() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let vox = model();
+    let pos = [-3, 100, 5];
+    let keys = chunk_manager.import_vox(&vox, pos, PaletteMode::Nearest);
+    assert!(keys.len() > 1, "model spans chunks");
+
+    // z up to y up, the .vox y goes toward -z
+    let red = chunk_manager.get_voxel(&[-3, 100, 6]);
+    assert!(red > 0);
+    assert_eq!(chunk_manager.get_voxel(&[16, 100, 6]), red);
+    let blue = chunk_manager.get_voxel(&[-3, 102, 5]);
+    assert!(blue > 0 && blue != red);
+
+    let exported = chunk_manager.export_vox(pos, [16, 102, 6])?;
+    assert_eq!(exported.models[0].size, [20, 2, 3]);
+    let mut voxels = exported.models[0].voxels.clone();
+    voxels.sort();
+    assert_eq!(voxels, vec![([0, 0, 0], red), ([0, 1, 2], blue), ([19, 0, 0], red)]);
+    assert!(chunk_manager.export_vox(pos, [300, 102, 6]).is_err());
+
+    let fitted = VoxFile::from_voxels(&[([16, 102, 6], red), ([-3, 102, 5], blue)], &chunk_manager.colors)?;
+    assert_eq!(fitted.models[0].size, [20, 2, 1]);
+    assert_eq!(fitted.models[0].voxels, vec![([19, 0, 0], red), ([0, 1, 0], blue)]);
+
+    // Exported palettes map back to the same voxels
+    let mut other = ChunkManager::default();
+    other.import_vox(&exported, pos, PaletteMode::Nearest);
+    assert_eq!(other.get_voxel(&[-3, 102, 5]), blue);
+    Ok(())
+  }
+
+  #[test]
+  fn test_vox_append_palette() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.set_materials(
+      crate::data::materials::MaterialRegistry::from_colors(&[[1.0, 0.0, 0.0]])
+    );
+    let vox = model();
+    let values = chunk_manager.vox_palette_values(&vox, PaletteMode::Nearest);
+    assert_eq!(values[1], 1);
+    assert_eq!(values[2], 1);
+
+    let values = chunk_manager.vox_palette_values(&vox, PaletteMode::Append);
+    assert_eq!(values[1], 1);
+    assert_eq!(values[2], 2);
+    assert_eq!(values[3], 0);
+    assert_eq!(chunk_manager.colors, vec![[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]);
+    assert_eq!(chunk_manager.materials.find("Vox 2"), Some(2));
+    Ok(())
+  }
+}
@@ -0,0 +1,400 @@
+use hashbrown::HashMap;
+use crate::chunk::chunk_manager::ChunkManager;
+use crate::data::materials::MaterialRegistry;
+
+/// Widest mesh in voxels, per axis
+pub const MAX_VOXELIZE_SIZE: i64 = 2048;
+/// Most voxels in the bounding box of a mesh
+pub const MAX_VOXELIZE_VOLUME: i64 = 1 << 26;
+
+/// Identity, column major like the glTF and bevy matrices
+pub const IDENTITY: [[f32; 4]; 4] = [
+  [1.0, 0.0, 0.0, 0.0],
+  [0.0, 1.0, 0.0, 0.0],
+  [0.0, 0.0, 1.0, 0.0],
+  [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Rays are moved off the voxel centers so they don't graze the edges of
+/// meshes built on the voxel grid
+const RAY_OFFSET: [f32; 2] = [0.000_123_7, 0.000_211_3];
+
+/// Triangles to voxelize
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TriangleMesh {
+  pub positions: Vec<[f32; 3]>,
+  pub indices: Vec<u32>,
+  /// Color of each triangle
+  pub colors: Vec<[f32; 3]>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelizeOptions {
+  /// Size of a voxel in the units of the transformed mesh
+  pub voxel_size: f32,
+  /// Column major, from the mesh to the space the voxel size is in
+  pub transform: [[f32; 4]; 4],
+  /// Fills the inside of the closed parts of the mesh, else only its surface
+  pub fill: bool,
+  /// Sets every voxel to the value, None maps the colors onto the palette
+  pub voxel: Option<u8>,
+}
+
+impl Default for VoxelizeOptions {
+  fn default() -> Self {
+    Self {
+      voxel_size: 1.0,
+      transform: IDENTITY,
+      fill: true,
+      voxel: None,
+    }
+  }
+}
+
+/**
+  Voxels the mesh covers and their colors, sorted. A voxel is on the surface
+  when a triangle overlaps its cube and takes the color of the closest one.
+  The inside is filled by ray parity along x, from the triangle the ray
+  enters through
+*/
+pub fn voxelize(
+  mesh: &TriangleMesh,
+  options: &VoxelizeOptions
+) -> Result<Vec<([i64; 3], [f32; 3])>, String> {
+  if options.voxel_size <= 0.0 {
+    return Err("Voxel size must be positive".to_string());
+  }
+  let positions: Vec<[f32; 3]> = mesh.positions
+    .iter()
+    .map(|p| transform_point(&options.transform, p).map(|v| v / options.voxel_size))
+    .collect();
+  if positions.iter().flatten().any(|v| !v.is_finite()) {
+    return Err("Mesh positions must be finite".to_string());
+  }
+  if positions.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  let mut min = [f32::MAX; 3];
+  let mut max = [f32::MIN; 3];
+  for p in positions.iter() {
+    for i in 0..3 {
+      min[i] = min[i].min(p[i]);
+      max[i] = max[i].max(p[i]);
+    }
+  }
+  if (0..3).any(|i| (max[i] - min[i]) as i64 > MAX_VOXELIZE_SIZE) {
+    return Err(format!("Mesh wider than {} voxels", MAX_VOXELIZE_SIZE));
+  }
+  let volume: i64 = (0..3).map(|i| (max[i] - min[i]) as i64 + 1).product();
+  if volume > MAX_VOXELIZE_VOLUME {
+    return Err(format!("Mesh bounds over {} voxels", MAX_VOXELIZE_VOLUME));
+  }
+
+  // Closest triangle distance and its color
+  let mut surface: HashMap<[i64; 3], (f32, [f32; 3])> = HashMap::new();
+  let mut columns: HashMap<[i64; 2], Vec<(f32, [f32; 3])>> = HashMap::new();
+  for (t, tri) in mesh.indices.chunks_exact(3).enumerate() {
+    if tri.iter().any(|i| *i as usize >= positions.len()) {
+      return Err("Index out of bounds".to_string());
+    }
+    let p = [0, 1, 2].map(|i| positions[tri[i] as usize]);
+    let color = *mesh.colors.get(t).unwrap_or(&[1.0; 3]);
+    add_surface(&p, color, &mut surface);
+    if options.fill {
+      add_crossings(&p, color, &mut columns);
+    }
+  }
+
+  let mut voxels: HashMap<[i64; 3], [f32; 3]> = HashMap::new();
+  for ([y, z], mut crossings) in columns.into_iter() {
+    crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for pair in crossings.chunks_exact(2) {
+      let start = pair[0].0.ceil() as i64;
+      let end = pair[1].0.floor() as i64;
+      for x in start..=end {
+        voxels.insert([x, y, z], pair[0].1);
+      }
+    }
+  }
+  for (pos, (_, color)) in surface.into_iter() {
+    voxels.insert(pos, color);
+  }
+
+  let mut voxels: Vec<([i64; 3], [f32; 3])> = voxels.into_iter().collect();
+  voxels.sort_by_key(|(pos, _)| *pos);
+  Ok(voxels)
+}
+
+/// Voxels the mesh covers with their colors mapped onto the materials,
+/// unless the options set every voxel
+pub fn voxelize_materials(
+  mesh: &TriangleMesh,
+  options: &VoxelizeOptions,
+  materials: &MaterialRegistry
+) -> Result<Vec<([i64; 3], u8)>, String> {
+  let voxels = voxelize(mesh, options)?;
+  Ok(voxels
+    .into_iter()
+    .map(|(pos, color)| (pos, options.voxel.unwrap_or_else(|| materials.nearest(&color))))
+    .collect())
+}
+
+impl ChunkManager {
+  /// World positions and voxel values of the voxelized mesh
+  pub fn place_mesh(
+    &self,
+    mesh: &TriangleMesh,
+    options: &VoxelizeOptions
+  ) -> Result<Vec<([i64; 3], u8)>, String> {
+    voxelize_materials(mesh, options, &self.materials)
+  }
+
+  /// Voxelizes the mesh into the chunks, returns the keys of the changed
+  /// chunks, sorted
+  pub fn import_mesh(
+    &mut self,
+    mesh: &TriangleMesh,
+    options: &VoxelizeOptions
+  ) -> Result<Vec<[i64; 3]>, String> {
+    let voxels = self.place_mesh(mesh, options)?;
+    Ok(self.set_voxels(&voxels))
+  }
+}
+
+fn add_surface(
+  p: &[[f32; 3]; 3],
+  color: [f32; 3],
+  surface: &mut HashMap<[i64; 3], (f32, [f32; 3])>
+) {
+  let normal = normalize(cross(sub(p[1], p[0]), sub(p[2], p[0])));
+  let mut min = [0; 3];
+  let mut max = [0; 3];
+  for i in 0..3 {
+    let low = p[0][i].min(p[1][i]).min(p[2][i]);
+    let high = p[0][i].max(p[1][i]).max(p[2][i]);
+    min[i] = (low - 0.5).ceil() as i64;
+    max[i] = (high + 0.5).floor() as i64;
+  }
+
+  for x in min[0]..=max[0] {
+    for y in min[1]..=max[1] {
+      for z in min[2]..=max[2] {
+        let center = [x as f32, y as f32, z as f32];
+        // Slightly smaller cubes, triangles on a face don't fill both sides
+        if !triangle_box_overlap(center, 0.5 - 1e-4, p) {
+          continue;
+        }
+        let dist = dot(sub(center, p[0]), normal).abs();
+        let entry = surface.entry([x, y, z]).or_insert((f32::MAX, color));
+        if dist < entry.0 {
+          *entry = (dist, color);
+        }
+      }
+    }
+  }
+}
+
+/// Where the rays along x through the voxel centers cross the triangle
+fn add_crossings(
+  p: &[[f32; 3]; 3],
+  color: [f32; 3],
+  columns: &mut HashMap<[i64; 2], Vec<(f32, [f32; 3])>>
+) {
+  let low = |i: usize| p[0][i].min(p[1][i]).min(p[2][i]);
+  let high = |i: usize| p[0][i].max(p[1][i]).max(p[2][i]);
+  // Barycentric coordinates in the yz plane
+  let det = (p[1][1] - p[0][1]) * (p[2][2] - p[0][2]) - (p[2][1] - p[0][1]) * (p[1][2] - p[0][2]);
+  if det.abs() <= f32::EPSILON {
+    return;
+  }
+
+  let min = [(low(1) - RAY_OFFSET[0]).ceil() as i64, (low(2) - RAY_OFFSET[1]).ceil() as i64];
+  let max = [(high(1) - RAY_OFFSET[0]).floor() as i64, (high(2) - RAY_OFFSET[1]).floor() as i64];
+  for y in min[0]..=max[0] {
+    for z in min[1]..=max[1] {
+      let ry = y as f32 + RAY_OFFSET[0] - p[0][1];
+      let rz = z as f32 + RAY_OFFSET[1] - p[0][2];
+      let u = (ry * (p[2][2] - p[0][2]) - (p[2][1] - p[0][1]) * rz) / det;
+      let v = ((p[1][1] - p[0][1]) * rz - ry * (p[1][2] - p[0][2])) / det;
+      if u < 0.0 || v < 0.0 || u + v > 1.0 {
+        continue;
+      }
+      let x = p[0][0] + u * (p[1][0] - p[0][0]) + v * (p[2][0] - p[0][0]);
+      columns.entry([y, z]).or_insert_with(Vec::new).push((x, color));
+    }
+  }
+}
+
+/// Separating axis test of the triangle and the cube around center
+fn triangle_box_overlap(center: [f32; 3], half: f32, tri: &[[f32; 3]; 3]) -> bool {
+  let v = tri.map(|p| sub(p, center));
+  let separated = |axis: [f32; 3]| {
+    let d = v.map(|p| dot(p, axis));
+    let r = half * (axis[0].abs() + axis[1].abs() + axis[2].abs());
+    d[0].min(d[1]).min(d[2]) > r || d[0].max(d[1]).max(d[2]) < -r
+  };
+
+  let edges = [sub(v[1], v[0]), sub(v[2], v[1]), sub(v[0], v[2])];
+  for edge in edges.iter() {
+    for i in 0..3 {
+      let mut unit = [0.0; 3];
+      unit[i] = 1.0;
+      if separated(cross(unit, *edge)) {
+        return false;
+      }
+    }
+  }
+  for i in 0..3 {
+    let mut unit = [0.0; 3];
+    unit[i] = 1.0;
+    if separated(unit) {
+      return false;
+    }
+  }
+  !separated(cross(edges[0], edges[1]))
+}
+
+pub(crate) fn transform_point(m: &[[f32; 4]; 4], p: &[f32; 3]) -> [f32; 3] {
+  [0, 1, 2].map(|r| m[0][r] * p[0] + m[1][r] * p[1] + m[2][r] * p[2] + m[3][r])
+}
+
+/// a * b, column major
+pub(crate) fn mul_matrix(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+  let mut m = [[0.0; 4]; 4];
+  for c in 0..4 {
+    for r in 0..4 {
+      m[c][r] = (0..4).map(|k| a[k][r] * b[c][k]).sum();
+    }
+  }
+  m
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+  [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+  a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+  [
+    a[1] * b[2] - a[2] * b[1],
+    a[2] * b[0] - a[0] * b[2],
+    a[0] * b[1] - a[1] * b[0],
+  ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+  let len = dot(v, v).sqrt();
+  if len <= f32::EPSILON {
+    return [0.0; 3];
+  }
+  v.map(|v| v / len)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Closed cube from -half to half, red on top
+  fn cube(half: f32) -> TriangleMesh {
+    let mut mesh = TriangleMesh::default();
+    for x in [-half, half] {
+      for y in [-half, half] {
+        for z in [-half, half] {
+          mesh.positions.push([x, y, z]);
+        }
+      }
+    }
+    // Vertex i has x = i & 4, y = i & 2, z = i & 1, wound outward
+    mesh.indices = vec![
+      0, 1, 3, 0, 3, 2, 4, 6, 7, 4, 7, 5,
+      0, 4, 5, 0, 5, 1, 2, 3, 7, 2, 7, 6,
+      0, 2, 6, 0, 6, 4, 1, 5, 7, 1, 7, 3,
+    ];
+    mesh.colors = vec![[0.5; 3]; 12];
+    mesh.colors[6] = [1.0, 0.0, 0.0];
+    mesh.colors[7] = [1.0, 0.0, 0.0];
+    mesh
+  }
+
+  #[test]
+  fn test_voxelize_cube() -> Result<(), String> {
+    let mesh = cube(3.0);
+    let filled = voxelize(&mesh, &VoxelizeOptions::default())?;
+    assert_eq!(filled.len(), 7 * 7 * 7);
+    assert!(filled.iter().all(|(p, _)| p.iter().all(|v| (-3..=3).contains(v))));
+    let color = |pos: [i64; 3]| filled.iter().find(|(p, _)| *p == pos).unwrap().1;
+    assert_eq!(color([0, 3, 0]), [1.0, 0.0, 0.0]);
+    assert_eq!(color([0, -3, 0]), [0.5; 3]);
+
+    let options = VoxelizeOptions { fill: false, ..Default::default() };
+    let shell = voxelize(&mesh, &options)?;
+    assert_eq!(shell.len(), 7 * 7 * 7 - 5 * 5 * 5);
+
+    // Half sized voxels, moved and scaled by the transform
+    let mut transform = IDENTITY;
+    transform[0][0] = 2.0;
+    transform[3] = [10.0, 0.0, 0.0, 1.0];
+    let options = VoxelizeOptions { voxel_size: 0.5, transform: transform, ..Default::default() };
+    let moved = voxelize(&mesh, &options)?;
+    assert_eq!(moved.first().unwrap().0, [8, -6, -6]);
+    assert_eq!(moved.last().unwrap().0, [32, 6, 6]);
+    assert_eq!(moved.len(), 25 * 13 * 13);
+
+    assert!(voxelize(&mesh, &VoxelizeOptions { voxel_size: 0.0, ..Default::default() }).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_voxelize_invalid() -> Result<(), String> {
+    let mut mesh = cube(3.0);
+    mesh.positions[0][1] = f32::NAN;
+    assert!(voxelize(&mesh, &VoxelizeOptions::default()).is_err());
+    mesh.positions[0][1] = f32::INFINITY;
+    assert!(voxelize(&mesh, &VoxelizeOptions::default()).is_err());
+
+    // Within the size of an axis, over the volume
+    let options = VoxelizeOptions { voxel_size: 3.0 / 1000.0, ..Default::default() };
+    assert!(voxelize(&cube(3.0), &options).is_err());
+    assert!(voxelize(&TriangleMesh::default(), &options)?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_import_mesh() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    chunk_manager.set_materials(crate::data::materials::MaterialRegistry::from_colors(
+      &[[0.4; 3], [0.9, 0.1, 0.1]]
+    ));
+
+    let mut transform = IDENTITY;
+    transform[3] = [14.0, 110.0, 0.0, 1.0];
+    let options = VoxelizeOptions { transform: transform, ..Default::default() };
+    let keys = chunk_manager.import_mesh(&cube(3.0), &options)?;
+    assert!(keys.len() > 1);
+    assert_eq!(chunk_manager.get_voxel(&[14, 113, 0]), 2);
+    assert_eq!(chunk_manager.get_voxel(&[11, 110, 0]), 1);
+    assert_eq!(chunk_manager.get_voxel(&[14, 110, 0]), 1);
+    assert_eq!(chunk_manager.get_voxel(&[14, 114, 0]), 0);
+
+    let options = VoxelizeOptions { voxel: Some(2), ..options };
+    let voxels = chunk_manager.place_mesh(&cube(3.0), &options)?;
+    assert!(voxels.iter().all(|(_, v)| *v == 2));
+    Ok(())
+  }
+
+  #[test]
+  fn test_mul_matrix() -> Result<(), String> {
+    let mut translate = IDENTITY;
+    translate[3] = [1.0, 2.0, 3.0, 1.0];
+    let mut scale = IDENTITY;
+    scale[1][1] = 2.0;
+    let m = mul_matrix(&translate, &scale);
+    assert_eq!(transform_point(&m, &[1.0, 1.0, 1.0]), [2.0, 4.0, 4.0]);
+    Ok(())
+  }
+}
@@ -0,0 +1,225 @@
+use hashbrown::{HashMap, HashSet};
+use crate::data::voxel_octree::MeshData;
+use super::MeshNode;
+
+/// Positions closer than this are welded
+pub const WELD_EPSILON: f32 = 1e-3;
+
+/// Meshes of a scene merged in world space, for the file exporters
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorldMesh {
+  pub positions: Vec<[f32; 3]>,
+  pub normals: Vec<[f32; 3]>,
+  pub colors: Vec<[f32; 3]>,
+  pub indices: Vec<u32>,
+  /// Voxel value of each triangle
+  pub voxels: Vec<u8>,
+}
+
+impl WorldMesh {
+  /**
+    The meshes of the nodes and their transparent meshes at the node
+    translations. Welding merges the vertices at the same position, like the
+    ones the chunks share on their seams, and drops the triangles that
+    collapsed or that both chunks meshed, leaving a single mesh
+  */
+  pub fn new(nodes: &[MeshNode], weld: bool) -> Self {
+    let mut world = WorldMesh::default();
+    for node in nodes.iter() {
+      world.append(node.mesh, node.translation);
+      for mesh in node.mesh.transparent.iter() {
+        world.append(mesh, node.translation);
+      }
+    }
+    if weld {
+      world.weld();
+    }
+    world
+  }
+
+  pub fn triangle_count(&self) -> usize {
+    self.indices.len() / 3
+  }
+
+  /// Unit normal of the triangle from its winding
+  pub fn face_normal(&self, triangle: usize) -> [f32; 3] {
+    let p = [0, 1, 2].map(|i| self.positions[self.indices[triangle * 3 + i] as usize]);
+    let u = [0, 1, 2].map(|i| p[1][i] - p[0][i]);
+    let v = [0, 1, 2].map(|i| p[2][i] - p[0][i]);
+    normalize([
+      u[1] * v[2] - u[2] * v[1],
+      u[2] * v[0] - u[0] * v[2],
+      u[0] * v[1] - u[1] * v[0],
+    ])
+  }
+
+  fn append(&mut self, mesh: &MeshData, offset: [f32; 3]) {
+    let start = self.positions.len() as u32;
+    for (i, p) in mesh.positions.iter().enumerate() {
+      self.positions.push([p[0] + offset[0], p[1] + offset[1], p[2] + offset[2]]);
+      self.normals.push(*mesh.normals.get(i).unwrap_or(&[0.0, 1.0, 0.0]));
+      self.colors.push(*mesh.colors.get(i).unwrap_or(&[1.0; 3]));
+    }
+
+    for tri in mesh.indices.chunks_exact(3) {
+      self.indices.extend(tri.iter().map(|i| start + i));
+      self.voxels.push(main_voxel(mesh, tri[0] as usize));
+    }
+  }
+
+  fn weld(&mut self) {
+    let mut welded = HashMap::new();
+    let mut remap = Vec::with_capacity(self.positions.len());
+    let mut positions = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut colors = Vec::new();
+    for (i, p) in self.positions.iter().enumerate() {
+      let cell = p.map(|v| (v / WELD_EPSILON).round() as i64);
+      let index = *welded.entry(cell).or_insert_with(|| {
+        positions.push(*p);
+        normals.push([0.0; 3]);
+        colors.push(self.colors[i]);
+        positions.len() as u32 - 1
+      });
+      for a in 0..3 {
+        normals[index as usize][a] += self.normals[i][a];
+      }
+      remap.push(index);
+    }
+
+    let mut seen = HashSet::new();
+    let mut indices = Vec::new();
+    let mut voxels = Vec::new();
+    for (t, tri) in self.indices.chunks_exact(3).enumerate() {
+      let tri = [0, 1, 2].map(|i| remap[tri[i] as usize]);
+      if tri[0] == tri[1] || tri[1] == tri[2] || tri[0] == tri[2] {
+        continue;
+      }
+      let mut sorted = tri;
+      sorted.sort();
+      if !seen.insert(sorted) {
+        continue;
+      }
+      indices.extend(tri);
+      voxels.push(self.voxels[t]);
+    }
+
+    self.positions = positions;
+    self.normals = normals.into_iter().map(normalize).collect();
+    self.colors = colors;
+    self.indices = indices;
+    self.voxels = voxels;
+  }
+}
+
+/// Voxel value with the largest weight at the vertex
+fn main_voxel(mesh: &MeshData, vertex: usize) -> u8 {
+  let (types, weights) = match (mesh.types.get(vertex), mesh.weights.get(vertex)) {
+    (Some(t), Some(w)) => (t, w),
+    _ => return 0,
+  };
+  let mut main = 0;
+  for i in 1..4 {
+    if weights[i] > weights[main] {
+      main = i;
+    }
+  }
+  types[main] as u8
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+  let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+  if len <= f32::EPSILON {
+    return [0.0, 1.0, 0.0];
+  }
+  v.map(|v| v / len)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use hashbrown::HashMap;
+  use crate::chunk::chunk_manager::ChunkManager;
+  use crate::data::{voxel_octree::VoxelMode, surface_nets::VoxelReuse};
+  use crate::utils::key_to_world_coord_f32;
+  use super::*;
+
+  /// Uses of each edge, a closed manifold uses every edge twice
+  fn edge_uses(mesh: &WorldMesh) -> HashMap<[u32; 2], usize> {
+    let mut edges = HashMap::new();
+    for tri in mesh.indices.chunks_exact(3) {
+      for i in 0..3 {
+        let mut edge = [tri[i], tri[(i + 1) % 3]];
+        edge.sort();
+        *edges.entry(edge).or_insert(0) += 1;
+      }
+    }
+    edges
+  }
+
+  #[test]
+  fn test_weld_chunk_seams() -> Result<(), String> {
+    let mut chunk_manager = ChunkManager::default();
+    let mut voxels = Vec::new();
+    for x in 10..20 {
+      for y in 110..116 {
+        for z in 3..8 {
+          voxels.push(([x, y, z], 1));
+        }
+      }
+    }
+    let keys = chunk_manager.set_voxels(&voxels);
+
+    let seamless = chunk_manager.seamless_size();
+    let mut meshes = Vec::new();
+    for key in keys.iter() {
+      let chunk = chunk_manager.get_chunk(key).unwrap();
+      let mesh = chunk.octree.compute_mesh_passes(
+        VoxelMode::SurfaceNets,
+        &mut VoxelReuse::new(chunk_manager.depth, 3),
+        &chunk_manager.materials,
+        chunk_manager.voxel_scale,
+        *key,
+        0
+      );
+      meshes.push((*key, mesh));
+    }
+    let nodes: Vec<MeshNode> = meshes
+      .iter()
+      .filter(|(_, mesh)| !mesh.indices.is_empty())
+      .map(|(key, mesh)| MeshNode {
+        name: String::new(),
+        translation: key_to_world_coord_f32(key, seamless),
+        mesh: mesh,
+      })
+      .collect();
+    assert!(nodes.len() > 1, "box spans chunks");
+
+    let separate = WorldMesh::new(&nodes, false);
+    assert!(edge_uses(&separate).values().any(|uses| *uses != 2));
+
+    let welded = WorldMesh::new(&nodes, true);
+    assert!(welded.positions.len() < separate.positions.len());
+    assert_eq!(welded.voxels.len(), welded.triangle_count());
+    assert!(welded.voxels.iter().all(|v| *v == 1));
+    assert!(edge_uses(&welded).values().all(|uses| *uses == 2));
+    Ok(())
+  }
+
+  #[test]
+  fn test_weld_drops_degenerate() -> Result<(), String> {
+    let mesh = MeshData {
+      positions: vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0001]],
+      normals: vec![[0.0, 0.0, 1.0]; 4],
+      indices: vec![0, 1, 2, 3, 1, 2, 0, 3, 1],
+      ..Default::default()
+    };
+    let node = MeshNode { name: String::new(), translation: [1.0; 3], mesh: &mesh };
+    let welded = WorldMesh::new(&[node], true);
+    assert_eq!(welded.positions.len(), 3);
+    assert_eq!(welded.indices, vec![0, 1, 2]);
+    assert_eq!(welded.positions[0], [1.0; 3]);
+    assert_eq!(welded.face_normal(0), [0.0, 0.0, 1.0]);
+    Ok(())
+  }
+}
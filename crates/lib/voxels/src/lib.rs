@@ -1,3 +1,4 @@
 pub mod chunk;
 pub mod data;
+pub mod formats;
 pub mod utils;
\ No newline at end of file
@@ -1,134 +1,313 @@
+use std::hash::Hash;
 use hashbrown::HashMap;
+use hashbrown::hash_map::{Entry, DefaultHashBuilder};
+use crate::chunk::coords::{ChunkKey, WorldVoxelPos};
 
-use crate::{chunk::world_pos_to_key};
+/// Keys placed in the grid by their first three coordinates
+pub trait GridKey: Copy + Eq + Hash {
+  fn position(&self) -> [i64; 3];
+}
 
-/*
-  TODO
-    Create a 3-dimensional grid to distribute access to HashMaps
-  Implementation:
-    Need to have grid size
-    Setup unit test
-    Setup benchmark
-    Make this generic?
-*/
+impl GridKey for [i64; 3] {
+  fn position(&self) -> [i64; 3] {
+    *self
+  }
+}
+
+/// x, y and z with a level, like the lod keys
+impl GridKey for [i64; 4] {
+  fn position(&self) -> [i64; 3] {
+    [self[0], self[1], self[2]]
+  }
+}
 
-pub struct GridHashMap<V> {
-  maps: HashMap<[i64; 3], HashMap<[i64; 4], V>>,
+impl GridKey for ChunkKey {
+  fn position(&self) -> [i64; 3] {
+    self.0
+  }
+}
+
+impl GridKey for WorldVoxelPos {
+  fn position(&self) -> [i64; 3] {
+    self.0
+  }
+}
+
+/**
+  HashMap split into the cells of a 3D grid, size keys wide. Lookups cost
+  two hashes and range queries only visit the cells overlapping their box,
+  so areas of large maps are found without scanning every key
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct GridHashMap<V, K: GridKey = [i64; 4]> {
+  cells: HashMap<[i64; 3], HashMap<K, V>>,
+  len: usize,
   pub size: u32,
 }
 
-impl<V> GridHashMap<V> {
-  pub fn get(&self, key: &[i64; 4]) -> Option<&V> {
-    let key3 = [key[0], key[1], key[2]];
-    let grid = world_pos_to_key(&key3, self.size);
-    let map_op = self.maps.get(&grid);
-    if map_op.is_none() {
-      return None;
+/**
+  Entry of a GridHashMap, inserting or removing through it keeps the count.
+  The cell of the key is only created once a value is inserted
+*/
+pub struct GridEntry<'a, K, V> {
+  key: K,
+  cell: Entry<'a, [i64; 3], HashMap<K, V>, DefaultHashBuilder>,
+  len: &'a mut usize,
+}
+
+impl<V, K: GridKey> GridHashMap<V, K> {
+  pub fn new(size: u32) -> Self {
+    assert!(size > 0);
+    Self {
+      cells: HashMap::new(),
+      len: 0,
+      size: size,
     }
+  }
 
-    let local_key = get_local_key(&key3, self.size);
-    let local_key4 = [local_key[0], local_key[1], local_key[2], key[3]];
-    map_op.unwrap().get(&local_key4)
+  /// Cell holding the position
+  pub fn cell_of(&self, pos: &[i64; 3]) -> [i64; 3] {
+    pos.map(|v| v.div_euclid(self.size as i64))
   }
 
-  pub fn insert(&mut self, key: [i64; 4], value: V) {
-    let key3 = [key[0], key[1], key[2]];
-    let grid = world_pos_to_key(&key3, self.size);
-    let map_op = self.maps.get(&grid);
+  pub fn get(&self, key: &K) -> Option<&V> {
+    self.cells.get(&self.cell_of(&key.position()))?.get(key)
+  }
 
-    let local_key = get_local_key(&key3, self.size);
-    let local_key4 = [local_key[0], local_key[1], local_key[2], key[3]];
-    if map_op.is_none() {
-      let mut new_map = HashMap::new();
-      new_map.insert(local_key4, value);
-      self.maps.insert(grid, new_map);
-    } else {
-      self.maps.get_mut(&grid).unwrap().insert(local_key4, value);
+  pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+    let cell = self.cell_of(&key.position());
+    self.cells.get_mut(&cell)?.get_mut(key)
+  }
+
+  /// Returns the previous value of the key
+  pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+    let cell = self.cell_of(&key.position());
+    let prev = self.cells.entry(cell).or_default().insert(key, value);
+    if prev.is_none() {
+      self.len += 1;
+    }
+    prev
+  }
+
+  /// Drops the cell once it is empty
+  pub fn remove(&mut self, key: &K) -> Option<V> {
+    let cell = self.cell_of(&key.position());
+    let map = self.cells.get_mut(&cell)?;
+    let value = map.remove(key);
+    if value.is_some() {
+      self.len -= 1;
+    }
+    if map.is_empty() {
+      self.cells.remove(&cell);
     }
+    value
   }
 
-  pub fn contains_key(&self, key: &[i64; 4]) -> bool {
+  pub fn entry(&mut self, key: K) -> GridEntry<'_, K, V> {
+    let cell = self.cell_of(&key.position());
+    GridEntry {
+      key: key,
+      cell: self.cells.entry(cell),
+      len: &mut self.len,
+    }
+  }
+
+  pub fn contains_key(&self, key: &K) -> bool {
     self.get(key).is_some()
   }
 
   pub fn len(&self) -> usize {
-    // self.maps.len()
-    let mut len = 0;
-    for (_key, val) in self.maps.iter() {
-      len += val.len();
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn clear(&mut self) {
+    self.cells.clear();
+    self.len = 0;
+  }
+
+  pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) {
+    for map in self.cells.values_mut() {
+      map.retain(|key, value| f(key, value));
     }
-    len
+    self.cells.retain(|_, map| !map.is_empty());
+    self.len = self.cells.values().map(|map| map.len()).sum();
   }
-}
 
-impl<V> Default for GridHashMap<V> {
-  fn default() -> Self {
-    GridHashMap {
-      maps: HashMap::<[i64; 3], HashMap<[i64; 4], V>>::new(),
-      size: 32,
+  pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+    self.cells.values().flat_map(|map| map.iter())
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+    self.cells.values_mut().flat_map(|map| map.iter_mut())
+  }
+
+  pub fn keys(&self) -> impl Iterator<Item = &K> {
+    self.iter().map(|(key, _)| key)
+  }
+
+  pub fn values(&self) -> impl Iterator<Item = &V> {
+    self.iter().map(|(_, value)| value)
+  }
+
+  pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+    self.iter_mut().map(|(_, value)| value)
+  }
+
+  /// Cells holding at least a key
+  pub fn cells(&self) -> impl Iterator<Item = &[i64; 3]> {
+    self.cells.keys()
+  }
+
+  pub fn iter_cell(&self, cell: &[i64; 3]) -> impl Iterator<Item = (&K, &V)> {
+    self.cells.get(cell).into_iter().flat_map(|map| map.iter())
+  }
+
+  /// Removes every key of the cell, returns how many there were
+  pub fn remove_cell(&mut self, cell: &[i64; 3]) -> usize {
+    let count = self.cells.remove(cell).map(|map| map.len()).unwrap_or(0);
+    self.len -= count;
+    count
+  }
+
+  /// Entries with their position inside min..=max
+  pub fn range(&self, min: [i64; 3], max: [i64; 3]) -> impl Iterator<Item = (&K, &V)> {
+    let (cell_min, cell_max) = (self.cell_of(&min), self.cell_of(&max));
+    let mut maps = Vec::new();
+    if (0..3).all(|i| min[i] <= max[i]) {
+      let count = (0..3)
+        .map(|i| (cell_max[i] - cell_min[i] + 1) as u128)
+        .product::<u128>();
+      // Looks the cells of the box up, unless there are fewer cells to scan
+      if count <= self.cells.len() as u128 {
+        for x in cell_min[0]..=cell_max[0] {
+          for y in cell_min[1]..=cell_max[1] {
+            for z in cell_min[2]..=cell_max[2] {
+              maps.extend(self.cells.get(&[x, y, z]));
+            }
+          }
+        }
+      } else {
+        maps.extend(self.cells
+          .iter()
+          .filter(|(cell, _)| (0..3).all(|i| cell_min[i] <= cell[i] && cell[i] <= cell_max[i]))
+          .map(|(_, map)| map));
+      }
     }
+
+    maps
+      .into_iter()
+      .flat_map(|map| map.iter())
+      .filter(move |(key, _)| {
+        let p = key.position();
+        (0..3).all(|i| min[i] <= p[i] && p[i] <= max[i])
+      })
   }
 }
 
-/**
- Needed for negative key to identify local key
-*/
-fn get_local_key(&key: &[i64; 3], size: u32) -> [i64; 3] {
-  let sizei64 = size as i64;
-  let rel_x = key[0] % sizei64;
-  let rel_y = key[1] % sizei64;
-  let rel_z = key[2] % sizei64;
+impl<'a, K: GridKey, V> GridEntry<'a, K, V> {
+  pub fn key(&self) -> &K {
+    &self.key
+  }
+
+  pub fn is_occupied(&self) -> bool {
+    match &self.cell {
+      Entry::Occupied(cell) => cell.get().contains_key(&self.key),
+      Entry::Vacant(_) => false,
+    }
+  }
+
+  pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+    if let Entry::Occupied(cell) = &mut self.cell {
+      if let Some(value) = cell.get_mut().get_mut(&self.key) {
+        f(value);
+      }
+    }
+    self
+  }
+
+  pub fn or_insert(self, default: V) -> &'a mut V {
+    self.or_insert_with(|| default)
+  }
 
-  let mut local_x = rel_x;
-  if rel_x < 0 {
-    local_x += sizei64;
+  pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+    let len = self.len;
+    self.cell.or_default().entry(self.key).or_insert_with(|| {
+      *len += 1;
+      f()
+    })
   }
-  let mut local_y = rel_y;
-  if rel_y < 0 {
-    local_y += sizei64;
+
+  pub fn or_default(self) -> &'a mut V where V: Default {
+    self.or_insert_with(V::default)
   }
-  let mut local_z = rel_z;
-  if local_z < 0 {
-    local_z += sizei64;
+
+  /// Removes the value, if the key has one. Drops the cell once it is empty
+  pub fn remove(self) -> Option<V> {
+    match self.cell {
+      Entry::Occupied(mut cell) => {
+        let value = cell.get_mut().remove(&self.key);
+        if value.is_some() {
+          *self.len -= 1;
+        }
+        if cell.get().is_empty() {
+          cell.remove();
+        }
+        value
+      }
+      Entry::Vacant(_) => None,
+    }
   }
+}
 
-  [local_x, local_y, local_z]
+impl<V, K: GridKey> Default for GridHashMap<V, K> {
+  fn default() -> Self {
+    GridHashMap::new(32)
+  }
+}
+
+impl<V, K: GridKey> Extend<(K, V)> for GridHashMap<V, K> {
+  fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+    for (key, value) in iter {
+      self.insert(key, value);
+    }
+  }
+}
+
+impl<V, K: GridKey> FromIterator<(K, V)> for GridHashMap<V, K> {
+  fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+    let mut map = GridHashMap::default();
+    map.extend(iter);
+    map
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use crate::chunk::chunk_manager::Chunk;
+  use crate::utils::tests::xorshift;
+  use super::*;
 
-use super::*;
+  /// Deterministic xorshift keys in -range..range
+  fn keys(count: usize, range: i64) -> Vec<[i64; 3]> {
+    let mut state = xorshift(0x9e37_79b9_7f4a_7c15);
+    let mut next = || (state() % (range as u64 * 2)) as i64 - range;
+    (0..count).map(|_| [next(), next(), next()]).collect()
+  }
 
   #[test]
   fn test_get_local_key() -> Result<(), String> {
-    let grid_hashmap = GridHashMap::<bool>::default();
-    let size = grid_hashmap.size;
-
-    let key = [0, 0, 0];
-    let local_key = get_local_key(&key, size);
-    assert_eq!(local_key, [0, 0, 0]);
-
-    let key = [1, 7, 8];
-    let local_key = get_local_key(&key, size);
-    assert_eq!(local_key, [1, 7, 0]);
-
-    let key = [16, 24, 32];
-    let local_key = get_local_key(&key, size);
-    assert_eq!(local_key, [0, 0, 0]);
+    let grid_hashmap = GridHashMap::<bool>::new(8);
 
-    let key = [-1, -7, -8];
-    let local_key = get_local_key(&key, size);
-    assert_eq!(local_key, [7, 1, 0]);
-
-    let key = [-16, -24, -32];
-    let local_key = get_local_key(&key, size);
-    assert_eq!(local_key, [0, 0, 0]);
-
-    let key = [-9, -17, -25];
-    let local_key = get_local_key(&key, size);
-    assert_eq!(local_key, [7, 7, 7]);
+    assert_eq!(grid_hashmap.cell_of(&[0, 0, 0]), [0, 0, 0]);
+    assert_eq!(grid_hashmap.cell_of(&[1, 7, 8]), [0, 0, 1]);
+    assert_eq!(grid_hashmap.cell_of(&[16, 24, 32]), [2, 3, 4]);
+    assert_eq!(grid_hashmap.cell_of(&[-1, -7, -8]), [-1, -1, -1]);
+    assert_eq!(grid_hashmap.cell_of(&[-16, -24, -32]), [-2, -3, -4]);
+    assert_eq!(grid_hashmap.cell_of(&[-9, -17, -25]), [-2, -3, -4]);
 
     Ok(())
   }
@@ -136,7 +315,6 @@ use super::*;
   #[test]
   fn test_get_chunk() -> Result<(), String> {
     let mut grid_hashmap = GridHashMap::default();
-    let size = grid_hashmap.size;
 
     let keys = vec![[0, 0, 0, 0], [1, 0, 0, 0], [2, 0, 0, 0], [3, 0, 0, 0]];
 
@@ -166,8 +344,103 @@ use super::*;
     Ok(())
   }
 
-  /*
-    TODO
-      Add more unit tests later
-  */
+  #[test]
+  fn test_remove_and_entry() -> Result<(), String> {
+    let mut map = GridHashMap::<usize, [i64; 3]>::new(4);
+    assert!(map.is_empty());
+    assert_eq!(map.insert([-1, 0, 0], 1), None);
+    assert_eq!(map.insert([-1, 0, 0], 2), Some(1));
+    assert_eq!(map.insert([3, 0, 0], 3), None);
+    assert_eq!(map.len(), 2);
+
+    *map.entry([3, 0, 0]).or_insert(0) += 10;
+    *map.entry([100, 0, 0]).or_insert(0) += 5;
+    assert_eq!(map.get(&[3, 0, 0]), Some(&13));
+    assert_eq!(map.get(&[100, 0, 0]), Some(&5));
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.entry([-1, 0, 0]).remove(), Some(2));
+    assert!(!map.contains_key(&[-1, 0, 0]));
+    assert_eq!(map.len(), 2);
+
+    // Entries left vacant don't create their cell
+    assert!(!map.entry([-50, 0, 0]).is_occupied());
+    assert_eq!(map.entry([-50, 0, 0]).remove(), None);
+    assert_eq!(*map.entry([-60, 0, 0]).and_modify(|v| *v += 1).key(), [-60, 0, 0]);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.cells().count(), 2);
+    assert!(map.cells().all(|cell| map.iter_cell(cell).count() > 0));
+
+    assert_eq!(map.remove(&[3, 0, 0]), Some(13));
+    assert_eq!(map.remove(&[3, 0, 0]), None);
+    *map.get_mut(&[100, 0, 0]).unwrap() = 7;
+    assert_eq!(map.iter().collect::<Vec<_>>(), vec![(&[100, 0, 0], &7)]);
+
+    map.retain(|key, _| key[0] < 0);
+    assert!(map.is_empty());
+    map.extend([([1, 2, 3], 1), ([1, 2, 3], 2), ([-9, 2, 3], 3)]);
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.remove_cell(&[-3, 0, 0]), 1);
+    assert_eq!(map.remove_cell(&[-3, 0, 0]), 0);
+    assert!(!map.contains_key(&[-9, 2, 3]));
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.cells().count(), 1);
+    map.clear();
+    assert!(map.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_cells_and_range() -> Result<(), String> {
+    let keys = keys(5000, 100);
+    let map: GridHashMap<usize, [i64; 3]> = keys
+      .iter()
+      .enumerate()
+      .map(|(i, key)| (*key, i))
+      .collect();
+    let mut unique = keys.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(map.len(), unique.len());
+
+    let cell_total: usize = map.cells().map(|cell| map.iter_cell(cell).count()).sum();
+    assert_eq!(cell_total, map.len());
+    for cell in map.cells() {
+      assert!(map.iter_cell(cell).all(|(key, _)| map.cell_of(key) == *cell));
+    }
+
+    let boxes = [
+      ([-10, -10, -10], [10, 10, 10]),
+      ([-100, 0, -33], [-1, 99, 64]),
+      ([5, 5, 5], [5, 5, 5]),
+      ([-1000, -1000, -1000], [1000, 1000, 1000]),
+      ([10, 0, 0], [0, 10, 10]),
+    ];
+    for (min, max) in boxes {
+      let mut found: Vec<[i64; 3]> = map.range(min, max).map(|(key, _)| *key).collect();
+      found.sort();
+      let expected: Vec<[i64; 3]> = unique
+        .iter()
+        .filter(|p| (0..3).all(|i| min[i] <= p[i] && p[i] <= max[i]))
+        .copied()
+        .collect();
+      assert_eq!(found, expected, "range {:?} {:?}", min, max);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_typed_keys() -> Result<(), String> {
+    let mut map = GridHashMap::<u8, ChunkKey>::new(16);
+    map.insert(ChunkKey([-1, 2, 3]), 1);
+    map.insert(ChunkKey([40, 2, 3]), 2);
+    assert_eq!(map.get(&ChunkKey([-1, 2, 3])), Some(&1));
+    assert_eq!(map.range([-5, 0, 0], [5, 5, 5]).count(), 1);
+
+    let mut lods = GridHashMap::<bool>::default();
+    lods.insert([0, 0, 0, 1], true);
+    lods.insert([0, 0, 0, 2], false);
+    assert_eq!(lods.len(), 2);
+    assert_eq!(lods.range([0, 0, 0], [0, 0, 0]).count(), 2);
+    Ok(())
+  }
 }
@@ -1,5 +1,5 @@
 pub mod grid_hashmap;
-use crate::chunk::voxel_pos_to_key;
+use crate::chunk::coords::{ChunkLayout, WorldVoxelPos};
 use crate::data::voxel_octree::VoxelOctree;
 
 pub struct Utils;
@@ -51,10 +51,9 @@ impl Utils {
 
 
 
-pub fn posf32_to_world_key(pos: &[f32; 3], seamless_size: u32) -> [i64; 3] {
-  let posi64 = [pos[0] as i64, pos[1] as i64, pos[2] as i64];
-  // world_pos_to_key(&posi64, seamless_size)
-  voxel_pos_to_key(&posi64, seamless_size)
+/// Key of the chunk holding the world position
+pub fn posf32_to_world_key(pos: &[f32; 3], layout: &ChunkLayout) -> [i64; 3] {
+  WorldVoxelPos::from_world(*pos, layout).key(layout).0
 }
 
 
@@ -97,6 +96,7 @@ pub fn get_length(depth: u8) -> usize {
 
 
 
+/// Same as coords::WorldVoxelPos::chunks for the default offset of 2
 pub fn get_chunk_coords(
   pos: &[i64; 3], 
   chunk_size: u32, 
@@ -104,7 +104,8 @@ pub fn get_chunk_coords(
 ) -> Vec<ChunkCoordinate> {
   let mut coords = Vec::new();
 
-  let keys = &potential_keys(pos, seamless_size);
+  let layout = ChunkLayout::new(chunk_size, seamless_size, 1.0);
+  let keys = &potential_keys(pos, &layout);
   for key in keys.iter() {
     // println!("key {:?}", key);
     if has_local_coord(pos, key, chunk_size, seamless_size as i64) {
@@ -130,7 +131,8 @@ pub fn get_chunk_coords2(
 ) -> Vec<ChunkCoordinate> {
   let mut coords = Vec::new();
 
-  let keys = &potential_keys(pos, seamless_size);
+  let layout = ChunkLayout::new(chunk_size, seamless_size, 1.0);
+  let keys = &potential_keys(pos, &layout);
   for key in keys.iter() {
     // println!("key {:?}", key);
     if has_local_coord(pos, key, chunk_size, seamless_size as i64) {
@@ -153,10 +155,10 @@ pub fn get_chunk_coords2(
 
 
 
-pub fn potential_keys(pos: &[i64; 3], seamless_size: u32) -> Vec<[i64; 3]> {
+pub fn potential_keys(pos: &[i64; 3], layout: &ChunkLayout) -> Vec<[i64; 3]> {
   let mut keys = Vec::new();
 
-  let start_key = voxel_pos_to_key(pos, seamless_size);
+  let start_key = WorldVoxelPos(*pos).key(layout).0;
   for x in -1..1 {
     for y in -1..1 {
       for z in -1..1 {
@@ -304,9 +306,20 @@ pub struct OctreeCoord {
 
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
   use super::*;
 
+  /// Deterministic xorshift generator, for the tests over many positions
+  pub(crate) fn xorshift(seed: u64) -> impl FnMut() -> u64 {
+    let mut state = seed;
+    move || {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state
+    }
+  }
+
   #[test]
   fn test_coord_to_index1() -> Result<(), String> {
     let default_value = 0;